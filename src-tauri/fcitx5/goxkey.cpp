@@ -0,0 +1,182 @@
+// The goxkey input method of Fcitx5. Fcitx5 only loads engines as shared
+// libraries, so this addon sends the keys to the goxkey engine over D-Bus
+// and applies the edits it answers with. Settings stay in goxkey's own
+// config file, which the engine reads.
+
+#include <cstdint>
+#include <string>
+
+#include <fcitx-utils/dbus/bus.h>
+#include <fcitx-utils/dbus/message.h>
+#include <fcitx-utils/key.h>
+#include <fcitx-utils/keysym.h>
+#include <fcitx-utils/log.h>
+#include <fcitx/addonfactory.h>
+#include <fcitx/event.h>
+#include <fcitx/inputcontext.h>
+#include <fcitx/inputmethodengine.h>
+#include <fcitx/inputpanel.h>
+#include <fcitx/text.h>
+#include <fcitx/userinterface.h>
+
+namespace {
+
+constexpr char kService[] = "com.goxgox.app.Fcitx5";
+constexpr char kPath[] = "/com/goxgox/app/Fcitx5";
+constexpr char kInterface[] = "com.goxgox.app.Fcitx5.Engine";
+
+// Fcitx5 waits for the engine before it handles the next event, so a key
+// the engine is too slow for, like the one that starts it, is typed as is.
+constexpr uint64_t kTimeoutUsec = 100000;
+
+// The IBus bit for released keys, which the engine reads the state with.
+constexpr uint32_t kReleaseMask = 1u << 30;
+
+// The evdev codes the engine expects are the X keycodes minus 8.
+constexpr int kKeycodeOffset = 8;
+
+struct Edits {
+    uint32_t backspaces = 0;
+    std::string commit;
+    std::string preedit;
+};
+
+class GoxKeyEngine : public fcitx::InputMethodEngineV2 {
+public:
+    GoxKeyEngine() : bus_(fcitx::dbus::BusType::Session) {}
+
+    void keyEvent(const fcitx::InputMethodEntry &,
+                  fcitx::KeyEvent &keyEvent) override {
+        const fcitx::Key &key = keyEvent.rawKey();
+        uint32_t state = static_cast<uint32_t>(key.states());
+        if (keyEvent.isRelease()) {
+            state |= kReleaseMask;
+        }
+        int keycode = key.code() >= kKeycodeOffset ? key.code() - kKeycodeOffset : 0;
+
+        auto message =
+            bus_.createMethodCall(kService, kPath, kInterface, "ProcessKeyEvent");
+        message << static_cast<uint32_t>(key.sym())
+                << static_cast<uint32_t>(keycode) << state;
+        auto reply = message.call(kTimeoutUsec);
+        bool handled = false;
+        Edits edits;
+        if (!readReply(reply, &handled) || !readEdits(reply, &edits)) {
+            forgetWord(keyEvent.inputContext());
+            return;
+        }
+        apply(keyEvent.inputContext(), edits);
+        if (handled) {
+            keyEvent.filterAndAccept();
+        }
+    }
+
+    void reset(const fcitx::InputMethodEntry &,
+               fcitx::InputContextEvent &event) override {
+        endWord(event.inputContext());
+    }
+
+    void deactivate(const fcitx::InputMethodEntry &,
+                    fcitx::InputContextEvent &event) override {
+        endWord(event.inputContext());
+    }
+
+private:
+    static bool readReply(fcitx::dbus::Message &reply, bool *handled) {
+        if (reply.type() != fcitx::dbus::MessageType::Reply) {
+            FCITX_ERROR() << "Cannot reach the goxkey engine: "
+                          << reply.errorName() << " " << reply.errorMessage();
+            return false;
+        }
+        reply >> *handled;
+        return true;
+    }
+
+    static bool readEdits(fcitx::dbus::Message &reply, Edits *edits) {
+        reply >> edits->backspaces >> edits->commit >> edits->preedit;
+        return static_cast<bool>(reply);
+    }
+
+    // Commits the word being typed, as the text field it was typed in is
+    // left.
+    void endWord(fcitx::InputContext *inputContext) {
+        auto message = bus_.createMethodCall(kService, kPath, kInterface, "Reset");
+        auto reply = message.call(kTimeoutUsec);
+        Edits edits;
+        if (reply.type() != fcitx::dbus::MessageType::Reply ||
+            !readEdits(reply, &edits)) {
+            commitShownWord(inputContext);
+            return;
+        }
+        apply(inputContext, edits);
+    }
+
+    // Has the engine start a new word without waiting for it, as the key it
+    // did not answer in time goes to the app as is.
+    void forgetWord(fcitx::InputContext *inputContext) {
+        auto message = bus_.createMethodCall(kService, kPath, kInterface, "Reset");
+        message.send();
+        commitShownWord(inputContext);
+    }
+
+    // Keeps the word as it is shown when the engine cannot tell how it ends.
+    void commitShownWord(fcitx::InputContext *inputContext) {
+        auto &panel = inputContext->inputPanel();
+        Edits edits;
+        if (inputContext->capabilityFlags().test(fcitx::CapabilityFlag::Preedit)) {
+            edits.commit = panel.clientPreedit().toString();
+        } else {
+            edits.commit = panel.preedit().toString();
+        }
+        apply(inputContext, edits);
+    }
+
+    void apply(fcitx::InputContext *inputContext, const Edits &edits) {
+        if (edits.backspaces > 0) {
+            // The characters before the preedit are already in the app:
+            // erase them from its surrounding text when it has one, with
+            // backspaces otherwise.
+            auto count = static_cast<int>(edits.backspaces);
+            if (inputContext->capabilityFlags().test(
+                    fcitx::CapabilityFlag::SurroundingText) &&
+                inputContext->surroundingText().isValid()) {
+                inputContext->deleteSurroundingText(-count, edits.backspaces);
+            } else {
+                for (int i = 0; i < count; i++) {
+                    inputContext->forwardKey(fcitx::Key(FcitxKey_BackSpace), false);
+                    inputContext->forwardKey(fcitx::Key(FcitxKey_BackSpace), true);
+                }
+            }
+        }
+        if (!edits.commit.empty()) {
+            inputContext->commitString(edits.commit);
+        }
+
+        fcitx::Text preedit;
+        if (!edits.preedit.empty()) {
+            preedit.append(edits.preedit, fcitx::TextFormatFlag::Underline);
+        }
+        preedit.setCursor(static_cast<int>(edits.preedit.size()));
+        auto &panel = inputContext->inputPanel();
+        if (inputContext->capabilityFlags().test(fcitx::CapabilityFlag::Preedit)) {
+            panel.setClientPreedit(preedit);
+        } else {
+            panel.setPreedit(preedit);
+        }
+        inputContext->updatePreedit();
+        inputContext->updateUserInterface(
+            fcitx::UserInterfaceComponent::InputPanel);
+    }
+
+    fcitx::dbus::Bus bus_;
+};
+
+class GoxKeyEngineFactory : public fcitx::AddonFactory {
+    fcitx::AddonInstance *create(fcitx::AddonManager *) override {
+        return new GoxKeyEngine();
+    }
+};
+
+} // namespace
+
+FCITX_ADDON_FACTORY(GoxKeyEngineFactory);
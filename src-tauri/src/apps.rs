@@ -1,6 +1,6 @@
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
-#[cfg(target_os = "macos")]
+#[cfg(any(target_os = "macos", target_os = "linux"))]
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -34,7 +34,13 @@ pub fn search_apps(query: Option<&str>) -> Vec<AppInfo> {
 }
 
 fn discover_apps() -> Vec<AppInfo> {
-    discover_apps_impl()
+    let mut results = discover_apps_impl();
+    results.sort_by(|a, b| {
+        let left = a.name.to_lowercase();
+        let right = b.name.to_lowercase();
+        left.cmp(&right).then_with(|| a.identifier.cmp(&b.identifier))
+    });
+    results
 }
 
 #[cfg(target_os = "macos")]
@@ -73,11 +79,6 @@ fn discover_apps_impl() -> Vec<AppInfo> {
             }
         }
     }
-    results.sort_by(|a, b| {
-        let left = a.name.to_lowercase();
-        let right = b.name.to_lowercase();
-        left.cmp(&right).then_with(|| a.identifier.cmp(&b.identifier))
-    });
     results
 }
 
@@ -135,7 +136,268 @@ fn read_app_bundle(path: &std::path::Path) -> Option<AppInfo> {
     })
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(target_os = "linux")]
+fn discover_apps_impl() -> Vec<AppInfo> {
+    use std::collections::HashSet;
+    use std::fs;
+
+    let locale = message_locale();
+    let mut results = Vec::new();
+    let mut seen = HashSet::new();
+    for dir in candidate_dirs() {
+        for (id, path) in desktop_files(&dir) {
+            // The first entry with an ID shadows the ones in the directories
+            // after it, even when it hides the app.
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Some(info) = read_desktop_entry(&id, &path, &contents, locale.as_deref()) {
+                results.push(info);
+            }
+        }
+    }
+    results
+}
+
+/// The `applications` directories of the XDG base directories, the most
+/// important first.
+#[cfg(target_os = "linux")]
+fn candidate_dirs() -> Vec<PathBuf> {
+    use std::env;
+
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| crate::platform::get_home_dir().map(|home| home.join(".local/share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    data_home
+        .into_iter()
+        .chain(
+            data_dirs
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+        )
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// The `.desktop` files under `dir` with their desktop ID: their path in
+/// `dir` without the extension, with `/` replaced by `-`.
+#[cfg(target_os = "linux")]
+fn desktop_files(dir: &std::path::Path) -> Vec<(String, PathBuf)> {
+    use std::fs;
+
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false) {
+                pending.push(path);
+                continue;
+            }
+            let is_desktop_entry = path
+                .extension()
+                .map(|ext| ext == "desktop")
+                .unwrap_or(false);
+            if !is_desktop_entry {
+                continue;
+            }
+            if let Ok(relative) = path.strip_prefix(dir) {
+                let id = relative
+                    .with_extension("")
+                    .to_string_lossy()
+                    .replace('/', "-");
+                files.push((id, path));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// The locale app names are shown in, like `vi_VN.UTF-8`.
+#[cfg(target_os = "linux")]
+fn message_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .filter(|value| value != "C" && value != "POSIX")
+}
+
+/// The keys a localized `Name` can have for `locale`, the most specific
+/// first, as the desktop entry specification matches them.
+#[cfg(target_os = "linux")]
+fn localized_name_keys(locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    let locale = locale.split('.').next().unwrap_or(locale);
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+    let mut keys = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        keys.push(format!("Name[{lang}_{country}@{modifier}]"));
+    }
+    if let Some(country) = country {
+        keys.push(format!("Name[{lang}_{country}]"));
+    }
+    if let Some(modifier) = modifier {
+        keys.push(format!("Name[{lang}@{modifier}]"));
+    }
+    keys.push(format!("Name[{lang}]"));
+    keys
+}
+
+#[cfg(target_os = "linux")]
+fn unescape_desktop_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(target_os = "linux")]
+fn read_desktop_entry(
+    id: &str,
+    path: &std::path::Path,
+    contents: &str,
+    locale: Option<&str>,
+) -> Option<AppInfo> {
+    use std::collections::HashMap;
+
+    let mut keys = HashMap::new();
+    let mut in_desktop_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_desktop_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_desktop_entry {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            keys.entry(key.trim()).or_insert(value.trim());
+        }
+    }
+    let is_true = |key: &str| keys.get(key).copied() == Some("true");
+    if keys.get("Type").copied() != Some("Application") || is_true("NoDisplay") || is_true("Hidden")
+    {
+        return None;
+    }
+    let name = locale
+        .map(localized_name_keys)
+        .unwrap_or_default()
+        .iter()
+        .find_map(|key| keys.get(key.as_str()))
+        .or_else(|| keys.get("Name"))
+        .map(|name| unescape_desktop_value(name))
+        .filter(|name| !name.is_empty())?;
+    // The window class is what the active window is matched against, most
+    // apps name their desktop entry after it when they do not set it.
+    let identifier = keys
+        .get("StartupWMClass")
+        .filter(|class| !class.is_empty())
+        .map(|class| class.to_string())
+        .unwrap_or_else(|| id.to_string());
+    // The desktop file tells the entries apart, many run the same program,
+    // like the web apps of a browser.
+    Some(AppInfo {
+        identifier,
+        name,
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
 fn discover_apps_impl() -> Vec<AppInfo> {
     Vec::new()
 }
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_read_desktop_entry() {
+    let path = std::path::Path::new("/usr/share/applications/org.gnome.TextEditor.desktop");
+    let contents = "\
+# A comment
+[Desktop Entry]
+Type=Application
+Name=Text\\sEditor
+Name[vi]=Trình soạn thảo văn bản
+Name[vi_VN]=Soạn thảo
+Exec=gnome-text-editor %U
+
+[Desktop Action new-window]
+Name=New Window
+StartupWMClass=ignored
+";
+    let read = |locale| read_desktop_entry("org.gnome.TextEditor", path, contents, locale);
+    let info = read(Some("vi_VN.UTF-8")).unwrap();
+    assert_eq!(info.name, "Soạn thảo");
+    assert_eq!(info.identifier, "org.gnome.TextEditor");
+    assert_eq!(info.path, path.to_string_lossy());
+    assert_eq!(read(Some("vi_CH")).unwrap().name, "Trình soạn thảo văn bản");
+    assert_eq!(read(None).unwrap().name, "Text Editor");
+
+    let firefox = "[Desktop Entry]\nType=Application\nName=Firefox\nStartupWMClass=Navigator\n";
+    assert_eq!(
+        read_desktop_entry("firefox", path, firefox, None)
+            .unwrap()
+            .identifier,
+        "Navigator"
+    );
+    for hidden in ["NoDisplay=true", "Hidden=true", "Type=Link"] {
+        let contents = format!("[Desktop Entry]\n{hidden}\nType=Application\nName=App\n");
+        assert_eq!(
+            read_desktop_entry("app", path, &contents, None),
+            None,
+            "{hidden}"
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_desktop_files() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("goxkey-apps-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("kde")).unwrap();
+    for file in ["firefox.desktop", "kde/okular.desktop", "readme.txt"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    let ids: Vec<_> = desktop_files(&dir).into_iter().map(|(id, _)| id).collect();
+    assert_eq!(ids, vec!["firefox", "kde-okular"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
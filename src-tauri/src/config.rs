@@ -1,11 +1,13 @@
 use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::{
-    fs::File,
+    fs::{self, File},
     io,
     io::{Result, Write},
     path::PathBuf,
     sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
 };
 
 use once_cell::sync::Lazy;
@@ -19,10 +21,13 @@ pub static CONFIG_MANAGER: Lazy<Mutex<ConfigStore>> = Lazy::new(|| Mutex::new(Co
 pub struct ConfigStore {
     hotkey: String,
     method: String,
+    /// The goxscript used instead of `method`, empty for none.
+    active_script: String,
     vn_apps: Vec<String>,
     en_apps: Vec<String>,
     is_macro_enabled: bool,
     macro_table: BTreeMap<String, String>,
+    is_numpad_tone_enabled: bool,
     is_auto_toggle_enabled: bool,
     is_gox_mode_enabled: bool,
     allowed_words: Vec<String>,
@@ -33,6 +38,9 @@ pub struct ConfigStore {
     exclude_apps_enabled: bool,
     open_window_on_launch: bool,
     language: String,
+    /// When the config file was written, as of the settings read or last
+    /// written by this process.
+    modified: Option<SystemTime>,
 }
 
 fn parse_vec_string(line: String) -> Vec<String> {
@@ -66,11 +74,26 @@ impl ConfigStore {
             .join(".goxkey")
     }
 
+    /// When the config file was last written, by this process or another.
+    fn modified() -> Option<SystemTime> {
+        fs::metadata(ConfigStore::get_config_path())
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Where user goxscript modules (`.gox` files) are stored.
+    pub fn get_scripts_dir() -> PathBuf {
+        get_home_dir()
+            .expect("Cannot read home directory!")
+            .join(".goxkey.d")
+    }
+
     fn write_config_data(&mut self) -> Result<()> {
         let mut file = File::create(ConfigStore::get_config_path())?;
 
         writeln!(file, "{} = {}", HOTKEY_CONFIG_KEY, self.hotkey)?;
         writeln!(file, "{} = {}", TYPING_METHOD_CONFIG_KEY, self.method)?;
+        writeln!(file, "{} = {}", ACTIVE_SCRIPT_CONFIG_KEY, self.active_script)?;
         writeln!(file, "{} = {}", VN_APPS_CONFIG_KEY, self.vn_apps.join(","))?;
         writeln!(file, "{} = {}", EN_APPS_CONFIG_KEY, self.en_apps.join(","))?;
         writeln!(
@@ -92,6 +115,11 @@ impl ConfigStore {
         for (k, v) in self.macro_table.iter() {
             writeln!(file, "{} = {}", MACROS_CONFIG_KEY, build_kv_string(k, &v))?;
         }
+        writeln!(
+            file,
+            "{} = {}",
+            NUMPAD_TONE_ENABLED_CONFIG_KEY, self.is_numpad_tone_enabled
+        )?;
         writeln!(
             file,
             "{} = {}",
@@ -137,13 +165,18 @@ impl ConfigStore {
     }
 
     pub fn new() -> Self {
+        // Read before the file, a write in between is caught by the next
+        // check of `watch_config_file`.
+        let modified = ConfigStore::modified();
         let mut config = Self {
             hotkey: "ctrl+space".to_string(),
             method: "telex".to_string(),
+            active_script: String::new(),
             vn_apps: Vec::new(),
             en_apps: Vec::new(),
             is_macro_enabled: false,
             macro_table: BTreeMap::new(),
+            is_numpad_tone_enabled: false,
             is_auto_toggle_enabled: false,
             is_gox_mode_enabled: false,
             allowed_words: vec!["đc".to_string()],
@@ -154,6 +187,7 @@ impl ConfigStore {
             exclude_apps_enabled: true,
             open_window_on_launch: false,
             language: "vi".to_string(),
+            modified,
         };
 
         let config_path = ConfigStore::get_config_path();
@@ -165,6 +199,9 @@ impl ConfigStore {
                     match left {
                         HOTKEY_CONFIG_KEY => config.hotkey = right.to_string(),
                         TYPING_METHOD_CONFIG_KEY => config.method = right.to_string(),
+                        ACTIVE_SCRIPT_CONFIG_KEY => {
+                            config.active_script = right.trim().to_string()
+                        }
                         VN_APPS_CONFIG_KEY => config.vn_apps = parse_vec_string(right.to_string()),
                         EN_APPS_CONFIG_KEY => config.en_apps = parse_vec_string(right.to_string()),
                         ALLOWED_WORDS_CONFIG_KEY => {
@@ -181,6 +218,9 @@ impl ConfigStore {
                                 config.macro_table.insert(k, v);
                             }
                         }
+                        NUMPAD_TONE_ENABLED_CONFIG_KEY => {
+                            config.is_numpad_tone_enabled = matches!(right.trim(), "true")
+                        }
                         GOX_MODE_CONFIG_KEY => {
                             config.is_gox_mode_enabled = matches!(right.trim(), "true")
                         }
@@ -234,6 +274,15 @@ impl ConfigStore {
         self.save();
     }
 
+    pub fn get_active_script(&self) -> Option<&str> {
+        Some(self.active_script.as_str()).filter(|name| !name.is_empty())
+    }
+
+    pub fn set_active_script(&mut self, name: Option<&str>) {
+        self.active_script = name.unwrap_or_default().to_string();
+        self.save();
+    }
+
     pub fn is_vietnamese_app(&self, app_name: &str) -> bool {
         self.vn_apps.contains(&app_name.to_string())
     }
@@ -260,8 +309,8 @@ impl ConfigStore {
         self.save();
     }
 
-    pub fn is_allowed_word(&self, word: &str) -> bool {
-        self.allowed_words.contains(&word.to_string())
+    pub fn get_allowed_words(&self) -> &Vec<String> {
+        &self.allowed_words
     }
 
     pub fn is_auto_toggle_enabled(&self) -> bool {
@@ -291,6 +340,15 @@ impl ConfigStore {
         self.save();
     }
 
+    pub fn is_numpad_tone_enabled(&self) -> bool {
+        self.is_numpad_tone_enabled
+    }
+
+    pub fn set_numpad_tone_enabled(&mut self, flag: bool) {
+        self.is_numpad_tone_enabled = flag;
+        self.save();
+    }
+
     pub fn get_macro_table(&self) -> &BTreeMap<String, String> {
         &self.macro_table
     }
@@ -377,11 +435,34 @@ impl ConfigStore {
     // Save config to file
     fn save(&mut self) {
         self.write_config_data().expect("Failed to write config");
+        self.modified = ConfigStore::modified();
     }
 }
 
+/// Reads the config again whenever another goxkey process writes it, like
+/// the engine an input method framework runs, then calls `cb`. The writes
+/// of this process are not reloaded.
+pub fn watch_config_file<F>(cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        let modified = ConfigStore::modified();
+        {
+            let mut config = CONFIG_MANAGER.lock().unwrap();
+            if modified == config.modified {
+                continue;
+            }
+            *config = ConfigStore::new();
+        }
+        cb();
+    });
+}
+
 const HOTKEY_CONFIG_KEY: &str = "hotkey";
 const TYPING_METHOD_CONFIG_KEY: &str = "method";
+const ACTIVE_SCRIPT_CONFIG_KEY: &str = "active_script";
 const VN_APPS_CONFIG_KEY: &str = "vn-apps";
 const EN_APPS_CONFIG_KEY: &str = "en-apps";
 const MACRO_ENABLED_CONFIG_KEY: &str = "is_macro_enabled";
@@ -396,3 +477,4 @@ const EXCLUDED_APPS_CONFIG_KEY: &str = "excluded_apps";
 const EXCLUDE_APPS_ENABLED_CONFIG_KEY: &str = "exclude_apps_enabled";
 const OPEN_WINDOW_ON_LAUNCH_CONFIG_KEY: &str = "open_window_on_launch";
 const LANGUAGE_CONFIG_KEY: &str = "language";
+const NUMPAD_TONE_ENABLED_CONFIG_KEY: &str = "is_numpad_tone_enabled";
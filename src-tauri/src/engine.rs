@@ -0,0 +1,835 @@
+use std::collections::BTreeMap;
+use std::{fmt::Display, str::FromStr};
+
+use log::debug;
+
+use crate::apps::AppInfo;
+use crate::hotkey::Hotkey;
+use crate::layout::KeyboardLayout;
+use crate::platform::{
+    EventTapType, KeyEvent, KeyModifier, PressedKey, KEY_DELETE, KEY_ENTER, KEY_ESCAPE, KEY_SPACE,
+    KEY_TAB, RAW_ARROW_DOWN, RAW_ARROW_LEFT, RAW_ARROW_RIGHT, RAW_ARROW_UP, RAW_KEY_GLOBE,
+};
+use crate::scripting::compiler::InputMethod;
+use crate::scripting::runtime;
+
+// According to Google search, the longest possible Vietnamese word
+// is "nghiêng", which is 7 letters long. Add a little buffer for
+// tone and marks, I guess the longest possible buffer length would
+// be around 10 to 12.
+const MAX_POSSIBLE_WORD_LENGTH: usize = 10;
+const TONE_DUPLICATE_PATTERNS: [&str; 17] = [
+    "ss", "ff", "jj", "rr", "xx", "ww", "kk", "tt", "nn", "mm", "yy", "hh", "ii", "aaa", "eee",
+    "ooo", "ddd",
+];
+
+pub const STOP_TRACKING_WORDS: [&str; 4] = [";", "'", "?", "/"];
+
+const WORD_BREAKING_CHARS: &str = "()[]{}<>/\\!@#$%^&*-_=+|~`,.;'\"/";
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TypingMethod {
+    VNI,
+    Telex,
+}
+
+impl FromStr for TypingMethod {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "vni" => TypingMethod::VNI,
+            _ => TypingMethod::Telex,
+        })
+    }
+}
+
+impl Display for TypingMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::VNI => "vni",
+                Self::Telex => "telex",
+            }
+        )
+    }
+}
+
+/// An edit the host should perform on the focused application after the
+/// engine processed a key event. Actions must be applied in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Delete this many characters behind the cursor.
+    Backspace(usize),
+    /// Type this string at the cursor.
+    Insert(String),
+    /// Block the original key event from reaching the application.
+    Swallow,
+    /// Vietnamese mode was flipped by a hotkey, the host should persist
+    /// the new mode and refresh the UI.
+    VietnameseToggled,
+}
+
+/// The parts of a transform result the engine cares about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransformOutcome {
+    pub tone_mark_removed: bool,
+    pub letter_modification_removed: bool,
+}
+
+impl From<vi::TransformResult> for TransformOutcome {
+    fn from(value: vi::TransformResult) -> Self {
+        Self {
+            tone_mark_removed: value.tone_mark_removed,
+            letter_modification_removed: value.letter_modification_removed,
+        }
+    }
+}
+
+/// The typing engine: keeps track of the word being typed and turns key
+/// events into edit actions.
+///
+/// It holds no global state and never talks to the OS, so it can be driven
+/// by any backend or replayed in tests. Settings are pushed in by the host
+/// through the setters below.
+pub struct Engine {
+    buffer: String,
+    display_buffer: String,
+    method: TypingMethod,
+    script: Option<InputMethod>,
+    hotkey: Hotkey,
+    keyboard_layout: KeyboardLayout,
+    enabled: bool,
+    should_track: bool,
+    previous_word: String,
+    active_app: String,
+    active_app_identifier: Option<String>,
+    is_macro_enabled: bool,
+    macro_table: BTreeMap<String, String>,
+    /// Whether the digits of the numeric keypad type tones like the digits
+    /// above the letters do, for VNI.
+    is_numpad_tone_enabled: bool,
+    allowed_words: Vec<String>,
+    temporary_disabled: bool,
+    previous_modifiers: KeyModifier,
+    excluded_apps: Vec<AppInfo>,
+    exclude_apps_enabled: bool,
+    hotkey_modifiers: KeyModifier,
+    hotkey_matching: bool,
+    hotkey_matching_circuit_break: bool,
+}
+
+impl Engine {
+    pub fn new(method: TypingMethod, hotkey: Hotkey) -> Self {
+        Self {
+            buffer: String::new(),
+            display_buffer: String::new(),
+            method,
+            script: None,
+            hotkey,
+            keyboard_layout: KeyboardLayout::default(),
+            enabled: true,
+            should_track: true,
+            previous_word: String::new(),
+            active_app: String::new(),
+            active_app_identifier: None,
+            is_macro_enabled: false,
+            macro_table: BTreeMap::new(),
+            is_numpad_tone_enabled: false,
+            allowed_words: Vec::new(),
+            temporary_disabled: false,
+            previous_modifiers: KeyModifier::empty(),
+            excluded_apps: Vec::new(),
+            exclude_apps_enabled: false,
+            hotkey_modifiers: KeyModifier::MODIFIER_NONE,
+            hotkey_matching: false,
+            hotkey_matching_circuit_break: false,
+        }
+    }
+
+    /// Process a single key event and return the edits to apply.
+    pub fn handle_event(&mut self, event: &KeyEvent) -> Vec<Action> {
+        let KeyEvent {
+            event_type,
+            key: pressed_key,
+            modifiers,
+            ..
+        } = *event;
+        // The word is made of the keys pressed, releases change nothing.
+        if event_type == EventTapType::KeyUp {
+            return Vec::new();
+        }
+        let mut actions = Vec::new();
+        let pressed_key_code = pressed_key.and_then(|p| match p {
+            PressedKey::Char(c) => Some(c),
+            _ => None,
+        });
+
+        if event_type == EventTapType::FlagsChanged {
+            if modifiers.is_empty() {
+                self.hotkey_modifiers = KeyModifier::MODIFIER_NONE;
+                self.hotkey_matching = false;
+                self.hotkey_matching_circuit_break = false;
+            } else {
+                self.hotkey_modifiers.set(modifiers, true);
+            }
+        }
+
+        // Check hotkey match using current modifiers for KeyDown events
+        let check_modifiers = if event_type == EventTapType::KeyDown {
+            modifiers
+        } else {
+            self.hotkey_modifiers
+        };
+
+        let is_hotkey_matched = self.hotkey.is_match(check_modifiers, pressed_key_code);
+        let hotkey_requires_key = self.hotkey.keycode().is_some();
+        let triggered_by_key = event_type == EventTapType::KeyDown && pressed_key_code.is_some();
+        let triggered_by_flags = !hotkey_requires_key && event_type == EventTapType::FlagsChanged;
+
+        if is_hotkey_matched && !self.hotkey_matching && (triggered_by_key || triggered_by_flags) {
+            debug!(
+                "Global hotkey matched via {:?} (key_required: {})",
+                event_type, hotkey_requires_key
+            );
+            self.toggle_vietnamese();
+            actions.push(Action::VietnameseToggled);
+            self.hotkey_matching = true;
+            if triggered_by_key {
+                actions.push(Action::Swallow);
+                return actions;
+            }
+        }
+
+        if self.hotkey_matching && !is_hotkey_matched {
+            self.hotkey_matching_circuit_break = true;
+        }
+        if pressed_key_code.is_none() {
+            self.hotkey_matching = is_hotkey_matched;
+        }
+
+        // With numpad tones, the keypad digits are read like the ones above
+        // the letters.
+        let pressed_key = match pressed_key.and_then(|key| key.keypad_digit()) {
+            Some(digit) if self.is_numpad_tone_enabled => Some(PressedKey::Char(digit)),
+            _ => pressed_key,
+        };
+
+        match pressed_key {
+            Some(key @ PressedKey::Raw(raw_keycode)) => {
+                if raw_keycode == RAW_KEY_GLOBE {
+                    self.toggle_vietnamese();
+                    actions.push(Action::VietnameseToggled);
+                    actions.push(Action::Swallow);
+                    return actions;
+                }
+                if raw_keycode == RAW_ARROW_UP || raw_keycode == RAW_ARROW_DOWN {
+                    self.new_word();
+                }
+                if raw_keycode == RAW_ARROW_LEFT || raw_keycode == RAW_ARROW_RIGHT {
+                    self.new_word();
+                }
+                if let Some(digit) = key.keypad_digit() {
+                    // Without numpad tones the digit is typed as is and
+                    // ends the word, like a shifted one.
+                    if self.is_enabled() {
+                        self.push(digit);
+                        self.new_word();
+                    }
+                }
+            }
+            Some(PressedKey::Char(keycode)) => {
+                if self.is_enabled() {
+                    match keycode {
+                        KEY_ENTER | KEY_TAB | KEY_SPACE | KEY_ESCAPE => {
+                            let is_valid_word =
+                                vi::validation::is_valid_word(self.get_displaying_word());
+                            let is_allowed_word = self.is_allowed_word(self.get_displaying_word());
+                            let is_transformed_word =
+                                !self.get_typing_buffer().eq(self.get_displaying_word());
+                            if is_transformed_word && !is_valid_word && !is_allowed_word {
+                                self.restore_word(&mut actions);
+                            }
+
+                            if self.previous_word_is_stop_tracking_words() {
+                                self.clear_previous_word();
+                            }
+
+                            if keycode == KEY_TAB || keycode == KEY_SPACE {
+                                if let Some(macro_target) = self.get_macro_target().cloned() {
+                                    debug!("Macro: {}", macro_target);
+                                    self.replace_word(&macro_target, &mut actions);
+                                }
+                            }
+
+                            self.new_word();
+                        }
+                        KEY_DELETE => {
+                            if !modifiers.is_empty() && !modifiers.is_shift() {
+                                self.new_word();
+                            } else {
+                                self.pop();
+                            }
+                        }
+                        c => {
+                            let typed = self.typed_char(c, modifiers);
+                            if WORD_BREAKING_CHARS.contains(typed)
+                                || (c.is_numeric() && modifiers.is_shift())
+                            {
+                                if c.is_numeric() {
+                                    self.push(c);
+                                }
+                                self.new_word();
+                            } else if modifiers.is_super() || modifiers.is_alt() {
+                                self.new_word();
+                            } else if self.is_tracking() {
+                                self.push(typed);
+                                let transformed = self.transform_word(&mut actions);
+                                self.stop_tracking_if_needed();
+                                if transformed {
+                                    actions.push(Action::Swallow);
+                                }
+                                return actions;
+                            }
+                        }
+                    }
+                } else if matches!(keycode, KEY_ENTER | KEY_TAB | KEY_SPACE | KEY_ESCAPE)
+                    || !modifiers.is_empty()
+                {
+                    self.new_word();
+                }
+            }
+            None => {
+                if self.previous_modifiers.is_empty() {
+                    if modifiers.is_control() {
+                        if !self.get_typing_buffer().is_empty() {
+                            self.restore_word(&mut actions);
+                        }
+                        self.set_temporary_disabled();
+                    }
+                    if modifiers.is_super() || event_type == EventTapType::Other {
+                        self.new_word();
+                    }
+                }
+            }
+        }
+        self.previous_modifiers = modifiers;
+        actions
+    }
+
+    fn transform_word(&mut self, actions: &mut Vec<Action>) -> bool {
+        let Ok((output, outcome)) = self.transform_keys() else {
+            return false;
+        };
+        debug!("Transformed: {:?}", output);
+        if !self.should_send_keyboard_event(&output) {
+            return false;
+        }
+        if self.should_dismiss_selection_if_needed() {
+            actions.push(Action::Insert(" ".to_string()));
+            actions.push(Action::Backspace(1));
+        }
+        actions.push(Action::Backspace(self.get_backspace_count(false)));
+        actions.push(Action::Insert(output.clone()));
+        self.replace(output);
+        if outcome.letter_modification_removed || outcome.tone_mark_removed {
+            self.stop_tracking();
+        }
+        true
+    }
+
+    fn restore_word(&mut self, actions: &mut Vec<Action>) {
+        let typing_buffer = self.buffer.clone();
+        self.replace_word(&typing_buffer, actions);
+    }
+
+    fn replace_word(&mut self, target: &str, actions: &mut Vec<Action>) {
+        actions.push(Action::Backspace(self.get_backspace_count(true)));
+        actions.push(Action::Insert(target.to_owned()));
+        self.replace(target.to_owned());
+    }
+
+    pub fn set_active_app(&mut self, name: String, identifier: Option<String>) {
+        self.active_app = name;
+        self.active_app_identifier = identifier;
+    }
+
+    pub fn active_app(&self) -> &str {
+        &self.active_app
+    }
+
+    pub fn set_temporary_disabled(&mut self) {
+        self.temporary_disabled = true;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_vietnamese_mode_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled_with_layout(&self.keyboard_layout)
+    }
+
+    /// Whether Vietnamese is typed once the engine gets `layout`, which the
+    /// OS reports before.
+    pub fn is_enabled_with_layout(&self, layout: &KeyboardLayout) -> bool {
+        !self.temporary_disabled
+            && self.enabled
+            && !self.is_current_app_excluded()
+            && !self.is_suspended_by(layout)
+    }
+
+    /// Whether Vietnamese is on but cannot be typed with the keyboard
+    /// layout, which does not type Latin letters. It resumes by itself once
+    /// the user switches back to a Latin one.
+    pub fn is_suspended_by_layout(&self) -> bool {
+        self.is_suspended_by(&self.keyboard_layout)
+    }
+
+    pub fn is_suspended_by(&self, layout: &KeyboardLayout) -> bool {
+        self.enabled && !layout.is_latin()
+    }
+
+    pub fn toggle_vietnamese(&mut self) {
+        self.enabled = !self.enabled;
+        self.temporary_disabled = false;
+        self.new_word();
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.should_track
+    }
+
+    pub fn is_buffer_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn new_word(&mut self) {
+        if !self.buffer.is_empty() {
+            self.clear();
+        }
+        if self.temporary_disabled {
+            self.temporary_disabled = false;
+        }
+        self.should_track = true;
+    }
+
+    pub fn set_method(&mut self, method: TypingMethod) {
+        self.method = method;
+        self.new_word();
+    }
+
+    pub fn get_method(&self) -> TypingMethod {
+        self.method
+    }
+
+    /// Use a compiled goxscript instead of the built-in typing method.
+    /// Pass `None` to go back to `method`.
+    pub fn set_script(&mut self, script: Option<InputMethod>) {
+        self.script = script;
+        self.new_word();
+    }
+
+    pub fn has_script(&self) -> bool {
+        self.script.is_some()
+    }
+
+    pub fn set_hotkey(&mut self, hotkey: Hotkey) {
+        self.hotkey = hotkey;
+    }
+
+    pub fn get_hotkey(&self) -> &Hotkey {
+        &self.hotkey
+    }
+
+    /// Switches to another layout, which starts a new word: the keys typed
+    /// so far were read with the previous one.
+    pub fn set_keyboard_layout(&mut self, layout: KeyboardLayout) {
+        self.keyboard_layout = layout;
+        self.new_word();
+    }
+
+    pub fn keyboard_layout(&self) -> &KeyboardLayout {
+        &self.keyboard_layout
+    }
+
+    /// The character typed by the key that types `c` without modifiers.
+    /// Keys the layout does not know are taken to be uppercased by shift
+    /// and Caps Lock.
+    fn typed_char(&self, c: char, modifiers: KeyModifier) -> char {
+        if let Some(typed) = self.keyboard_layout.resolve_char(c, modifiers) {
+            return typed;
+        }
+        if !modifiers.is_shift() && !modifiers.is_capslock() {
+            return c;
+        }
+        let mut upper = c.to_uppercase();
+        match (upper.next(), upper.next()) {
+            (Some(upper), None) => upper,
+            _ => c,
+        }
+    }
+
+    pub fn set_macro_enabled(&mut self, enabled: bool) {
+        self.is_macro_enabled = enabled;
+    }
+
+    pub fn is_macro_enabled(&self) -> bool {
+        self.is_macro_enabled
+    }
+
+    pub fn set_numpad_tone_enabled(&mut self, enabled: bool) {
+        self.is_numpad_tone_enabled = enabled;
+    }
+
+    pub fn is_numpad_tone_enabled(&self) -> bool {
+        self.is_numpad_tone_enabled
+    }
+
+    pub fn set_macro_table(&mut self, macro_table: BTreeMap<String, String>) {
+        self.macro_table = macro_table;
+    }
+
+    pub fn get_macro_table(&self) -> &BTreeMap<String, String> {
+        &self.macro_table
+    }
+
+    pub fn add_macro(&mut self, from: String, to: String) {
+        self.macro_table.insert(from, to);
+    }
+
+    pub fn delete_macro(&mut self, from: &String) {
+        self.macro_table.remove(from);
+    }
+
+    pub fn get_macro_target(&self) -> Option<&String> {
+        if !self.is_macro_enabled {
+            return None;
+        }
+        self.macro_table.get(&self.display_buffer)
+    }
+
+    pub fn set_allowed_words(&mut self, allowed_words: Vec<String>) {
+        self.allowed_words = allowed_words;
+    }
+
+    pub fn is_allowed_word(&self, word: &str) -> bool {
+        self.allowed_words.iter().any(|w| w == word)
+    }
+
+    pub fn set_exclude_apps_enabled(&mut self, enabled: bool) {
+        self.exclude_apps_enabled = enabled;
+    }
+
+    pub fn set_excluded_apps(&mut self, excluded_apps: Vec<AppInfo>) {
+        self.excluded_apps = excluded_apps;
+    }
+
+    pub fn add_excluded_app(&mut self, app: AppInfo) {
+        self.excluded_apps
+            .retain(|item| item.path != app.path && item.identifier != app.identifier);
+        self.excluded_apps.push(app);
+    }
+
+    pub fn remove_excluded_app(&mut self, path: &str) {
+        self.excluded_apps.retain(|item| item.path != path);
+    }
+
+    fn is_current_app_excluded(&self) -> bool {
+        if !self.exclude_apps_enabled {
+            return false;
+        }
+        let identifier = self.active_app_identifier.as_deref().unwrap_or("");
+        self.excluded_apps.iter().any(|item| {
+            // On Linux the path is the desktop file, so only the window
+            // class matches, whose case often differs from the desktop ID,
+            // like `Code` and `code`.
+            item.path == self.active_app
+                || (!identifier.is_empty() && item.identifier.eq_ignore_ascii_case(identifier))
+        })
+    }
+
+    pub fn get_typing_buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    pub fn get_displaying_word(&self) -> &str {
+        &self.display_buffer
+    }
+
+    pub fn stop_tracking(&mut self) {
+        self.clear();
+        self.should_track = false;
+    }
+
+    pub fn transform_keys(&self) -> Result<(String, TransformOutcome), ()> {
+        if let Some(script) = &self.script {
+            let mut output = String::new();
+            let outcome = runtime::transform_buffer(script, self.buffer.chars(), &mut output);
+            return Ok((output, outcome));
+        }
+        let transform_method = match self.method {
+            TypingMethod::VNI => vi::vni::transform_buffer,
+            TypingMethod::Telex => vi::telex::transform_buffer,
+        };
+        let result = std::panic::catch_unwind(|| {
+            let mut output = String::new();
+            let transform_result = transform_method(self.buffer.chars(), &mut output);
+            (output, TransformOutcome::from(transform_result))
+        });
+        result.map_err(|_| ())
+    }
+
+    pub fn should_send_keyboard_event(&self, word: &str) -> bool {
+        !self.display_buffer.eq(word)
+    }
+
+    pub fn should_dismiss_selection_if_needed(&self) -> bool {
+        self.active_app.contains("Firefox")
+    }
+
+    /// Number of backspaces needed to erase the word on screen. When the
+    /// word is being transformed, the key that triggered the transform was
+    /// never sent to the application, so one less backspace is needed.
+    pub fn get_backspace_count(&self, is_delete: bool) -> usize {
+        let dp_len = self.display_buffer.chars().count();
+        if is_delete && dp_len >= 1 {
+            dp_len
+        } else {
+            dp_len - 1
+        }
+    }
+
+    pub fn replace(&mut self, buf: String) {
+        self.display_buffer = buf;
+    }
+
+    pub fn push(&mut self, c: char) {
+        if let Some(first_char) = self.buffer.chars().next() {
+            if first_char.is_numeric() {
+                self.buffer.remove(0);
+                self.display_buffer.remove(0);
+            }
+        }
+        if self.buffer.len() <= MAX_POSSIBLE_WORD_LENGTH {
+            self.buffer.push(c);
+            self.display_buffer.push(c);
+            debug!(
+                "Input buffer: {:?} - Display buffer: {:?}",
+                self.buffer, self.display_buffer
+            );
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.display_buffer.pop();
+        self.buffer = self.display_buffer.clone();
+        if self.buffer.is_empty() {
+            self.new_word();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.previous_word = self.buffer.to_owned();
+        self.buffer.clear();
+        self.display_buffer.clear();
+    }
+
+    pub fn get_previous_word(&self) -> &str {
+        &self.previous_word
+    }
+
+    pub fn clear_previous_word(&mut self) {
+        self.previous_word.clear();
+    }
+
+    pub fn previous_word_is_stop_tracking_words(&self) -> bool {
+        STOP_TRACKING_WORDS.contains(&self.previous_word.as_str())
+    }
+
+    // a set of rules that will trigger a hard stop for tracking
+    // maybe these weird stuff should not be here, but let's
+    // implement it anyway. we'll figure out where to put these
+    // later on.
+    pub fn should_stop_tracking(&mut self) -> bool {
+        let len = self.buffer.len();
+        if len > MAX_POSSIBLE_WORD_LENGTH {
+            return true;
+        }
+        // detect attempts to restore a word
+        // by doubling tone marks like ss, rr, ff, jj, xx
+        let buf = &self.buffer;
+        if TONE_DUPLICATE_PATTERNS
+            .iter()
+            .any(|p| buf.to_ascii_lowercase().contains(*p))
+        {
+            return true;
+        }
+
+        if self.previous_word_is_stop_tracking_words() {
+            return true;
+        }
+
+        false
+    }
+
+    pub fn stop_tracking_if_needed(&mut self) {
+        if self.should_stop_tracking() {
+            self.stop_tracking();
+            debug!("! Stop tracking");
+        }
+    }
+
+    pub fn get_previous_modifiers(&self) -> KeyModifier {
+        self.previous_modifiers
+    }
+}
+
+/// Types `keys` into the engine one by one, as if on a keyboard, and returns
+/// the text the focused application would end up with. Uppercase letters
+/// are typed with shift.
+pub fn type_keys(engine: &mut Engine, keys: &str) -> String {
+    let mut screen = String::new();
+    for c in keys.chars() {
+        let (key, modifiers) = if c.is_ascii_uppercase() {
+            (c.to_ascii_lowercase(), KeyModifier::MODIFIER_SHIFT)
+        } else {
+            (c, KeyModifier::MODIFIER_NONE)
+        };
+        let actions = engine.handle_event(&KeyEvent::new(
+            EventTapType::KeyDown,
+            Some(PressedKey::Char(key)),
+            modifiers,
+        ));
+        let mut swallowed = false;
+        for action in actions {
+            match action {
+                Action::Backspace(n) => {
+                    for _ in 0..n {
+                        screen.pop();
+                    }
+                }
+                Action::Insert(s) => screen.push_str(&s),
+                Action::Swallow => swallowed = true,
+                Action::VietnameseToggled => {}
+            }
+        }
+        if !swallowed {
+            screen.push(c);
+        }
+    }
+    screen
+}
+
+#[test]
+fn test_engine_transforms_telex_word() {
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    assert_eq!(type_keys(&mut engine, "vieetj "), "việt ");
+}
+
+#[test]
+fn test_engine_transforms_vni_word() {
+    let mut engine = Engine::new(TypingMethod::VNI, Hotkey::from_str("ctrl+space"));
+    assert_eq!(type_keys(&mut engine, "vie65t "), "việt ");
+}
+
+#[test]
+fn test_engine_restores_invalid_word() {
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    assert_eq!(type_keys(&mut engine, "tesst "), "test ");
+}
+
+#[test]
+fn test_engine_applies_macro() {
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    engine.set_macro_enabled(true);
+    engine.add_macro("vn".to_string(), "Việt Nam".to_string());
+    assert_eq!(type_keys(&mut engine, "vn "), "Việt Nam ");
+}
+
+#[test]
+fn test_engine_does_nothing_when_disabled() {
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    engine.set_enabled(false);
+    assert_eq!(type_keys(&mut engine, "vieetj "), "vieetj ");
+}
+
+#[test]
+fn test_engine_runs_user_script() {
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    let script = crate::scripting::compile_script(
+        "on s: add_tone(acute) end\non a: letter_mod(circumflex for a) end",
+        &|_: &str| None,
+    )
+    .unwrap();
+    engine.set_script(Some(script));
+    assert_eq!(type_keys(&mut engine, "caaus "), "cấu ");
+}
+
+#[test]
+fn test_engine_hotkey_toggles_vietnamese() {
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    let actions = engine.handle_event(&KeyEvent::new(
+        EventTapType::KeyDown,
+        Some(PressedKey::Char(KEY_SPACE)),
+        KeyModifier::MODIFIER_CONTROL,
+    ));
+    assert_eq!(actions, vec![Action::VietnameseToggled, Action::Swallow]);
+    assert!(!engine.is_enabled());
+}
+
+#[test]
+fn test_engine_reads_shifted_keys_from_layout() {
+    use crate::layout::Level;
+
+    // The VNI tones are on the shift level of the AZERTY number row.
+    let mut layout = KeyboardLayout::new("French");
+    layout.set_key_char(15, Level::Base, '-');
+    layout.set_key_char(15, Level::Shift, '6');
+    layout.set_key_char(14, Level::Base, '(');
+    layout.set_key_char(14, Level::Shift, '5');
+    let mut engine = Engine::new(TypingMethod::VNI, Hotkey::from_str("ctrl+space"));
+    engine.set_keyboard_layout(layout);
+    for (c, modifiers) in [
+        ('v', KeyModifier::MODIFIER_SHIFT),
+        ('i', KeyModifier::MODIFIER_NONE),
+        ('e', KeyModifier::MODIFIER_NONE),
+        ('-', KeyModifier::MODIFIER_SHIFT),
+        ('(', KeyModifier::MODIFIER_SHIFT),
+        ('t', KeyModifier::MODIFIER_NONE),
+    ] {
+        let key = Some(PressedKey::Char(c));
+        engine.handle_event(&KeyEvent::new(EventTapType::KeyDown, key, modifiers));
+    }
+    assert_eq!(engine.get_displaying_word(), "Việt");
+}
+
+#[test]
+fn test_engine_suspends_on_non_latin_layout() {
+    use crate::layout::Level;
+
+    let mut layout = KeyboardLayout::new("Russian");
+    for (keycode, c) in [(24, 'й'), (25, 'ц'), (26, 'у')] {
+        layout.set_key_char(keycode, Level::Base, c);
+    }
+    let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+    assert!(engine.is_suspended_by(&layout));
+    assert!(!engine.is_enabled_with_layout(&layout));
+    assert!(engine.is_enabled());
+    engine.set_keyboard_layout(layout);
+    assert!(engine.is_suspended_by_layout());
+    assert!(!engine.is_enabled());
+    assert!(engine.is_vietnamese_mode_enabled());
+    assert_eq!(type_keys(&mut engine, "vieetj "), "vieetj ");
+
+    engine.set_keyboard_layout(KeyboardLayout::default());
+    assert!(!engine.is_suspended_by_layout());
+    assert_eq!(type_keys(&mut engine, "vieetj "), "việt ");
+}
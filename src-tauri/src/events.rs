@@ -2,6 +2,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 
 use once_cell::sync::OnceCell;
 use tauri::{image::Image, AppHandle, Emitter};
+use tauri_plugin_notification::NotificationExt;
 
 use crate::state::UiState;
 
@@ -40,7 +41,7 @@ fn vietnamese_tray_icon() -> &'static Image<'static> {
 }
 
 pub fn tray_icon_for_state(state: &UiState) -> Image<'static> {
-    if state.is_enabled {
+    if state.is_typing_vietnamese {
         vietnamese_tray_icon().clone()
     } else {
         english_tray_icon().clone()
@@ -69,6 +70,7 @@ fn get_menu_label(label_type: &str, language: &str) -> &'static str {
             "disable_vietnamese" => "Disable Vietnamese typing",
             "show_window" => "Show window",
             "quit" => "Quit",
+            "script_error" => "The script could not be loaded, typing with Telex",
             _ => "",
         },
         _ => match label_type {
@@ -76,6 +78,7 @@ fn get_menu_label(label_type: &str, language: &str) -> &'static str {
             "disable_vietnamese" => "Tắt tiếng Việt",
             "show_window" => "Hiện cửa sổ",
             "quit" => "Thoát",
+            "script_error" => "Không tải được script, đang gõ bằng Telex",
             _ => "",
         },
     }
@@ -111,6 +114,24 @@ fn build_tray_menu_internal(
         .build()
 }
 
+/// Tells the user, even with the window closed, that their script is not
+/// in use and why.
+pub fn notify_script_error(handle: &AppHandle) {
+    let state = current_state();
+    let Some(error) = state.script_error else {
+        return;
+    };
+    let result = handle
+        .notification()
+        .builder()
+        .title(get_menu_label("script_error", &state.language))
+        .body(error)
+        .show();
+    if let Err(err) = result {
+        log::warn!("failed to show notification: {err}");
+    }
+}
+
 pub fn current_state() -> UiState {
     UiState::snapshot(accessibility_ready())
 }
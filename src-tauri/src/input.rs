@@ -1,227 +1,179 @@
 use std::collections::BTreeMap;
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::str::FromStr;
+use std::sync::Mutex;
 
 use log::debug;
-use once_cell::sync::{Lazy, OnceCell};
-use rdev::{Keyboard, KeyboardState};
-use vi::TransformResult;
+use once_cell::sync::Lazy;
 
 use crate::apps::AppInfo;
-use crate::platform::{get_active_app_identifier, get_active_app_name, KeyModifier};
-use crate::{config::CONFIG_MANAGER, events, hotkey::Hotkey, platform::is_in_text_selection};
-
-// According to Google search, the longest possible Vietnamese word
-// is "nghiêng", which is 7 letters long. Add a little buffer for
-// tone and marks, I guess the longest possible buffer length would
-// be around 10 to 12.
-const MAX_POSSIBLE_WORD_LENGTH: usize = 10;
-const MAX_DUPLICATE_LENGTH: usize = 4;
-const TONE_DUPLICATE_PATTERNS: [&str; 17] = [
-    "ss", "ff", "jj", "rr", "xx", "ww", "kk", "tt", "nn", "mm", "yy", "hh", "ii", "aaa", "eee",
-    "ooo", "ddd",
-];
+pub use crate::engine::TypingMethod;
+use crate::engine::{Action, Engine};
+use crate::layout::KeyboardLayout;
+use crate::platform::{
+    get_active_app_identifier, get_active_app_name, keyboard_layout, KeyEvent,
+};
+use crate::scripts::ScriptStore;
+use crate::config::CONFIG_MANAGER;
+use crate::{events, hotkey::Hotkey};
 
 pub static mut INPUT_STATE: Lazy<InputState> = Lazy::new(InputState::new);
-pub static mut HOTKEY_MODIFIERS: KeyModifier = KeyModifier::MODIFIER_NONE;
-pub static mut HOTKEY_MATCHING: bool = false;
-pub static mut HOTKEY_MATCHING_CIRCUIT_BREAK: bool = false;
-
-pub const PREDEFINED_CHARS: [char; 47] = [
-    'a', '`', '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '-', '=', 'q', 'w', 'e', 'r', 't',
-    'y', 'u', 'i', 'o', 'p', '[', ']', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l', ';', '\'', '\\',
-    'z', 'x', 'c', 'v', 'b', 'n', 'm', ',', '.', '/',
-];
-
-pub const STOP_TRACKING_WORDS: [&str; 4] = [";", "'", "?", "/"];
-
-pub fn get_key_from_char(c: char) -> rdev::Key {
-    use rdev::Key::*;
-    match &c {
-        'a' => KeyA,
-        '`' => BackQuote,
-        '1' => Num1,
-        '2' => Num2,
-        '3' => Num3,
-        '4' => Num4,
-        '5' => Num5,
-        '6' => Num6,
-        '7' => Num7,
-        '8' => Num8,
-        '9' => Num9,
-        '0' => Num0,
-        '-' => Minus,
-        '=' => Equal,
-        'q' => KeyQ,
-        'w' => KeyW,
-        'e' => KeyE,
-        'r' => KeyR,
-        't' => KeyT,
-        'y' => KeyY,
-        'u' => KeyU,
-        'i' => KeyI,
-        'o' => KeyO,
-        'p' => KeyP,
-        '[' => LeftBracket,
-        ']' => RightBracket,
-        's' => KeyS,
-        'd' => KeyD,
-        'f' => KeyF,
-        'g' => KeyG,
-        'h' => KeyH,
-        'j' => KeyJ,
-        'k' => KeyK,
-        'l' => KeyL,
-        ';' => SemiColon,
-        '\'' => Quote,
-        '\\' => BackSlash,
-        'z' => KeyZ,
-        'x' => KeyX,
-        'c' => KeyC,
-        'v' => KeyV,
-        'b' => KeyB,
-        'n' => KeyN,
-        'm' => KeyM,
-        ',' => Comma,
-        '.' => Dot,
-        '/' => Slash,
-        _ => Unknown(0),
-    }
-}
 
-pub static mut KEYBOARD_LAYOUT_CHARACTER_MAP: OnceCell<HashMap<char, char>> = OnceCell::new();
-
-fn build_keyboard_layout_map(map: &mut HashMap<char, char>) {
-    map.clear();
-    let mut kb = Keyboard::new().unwrap();
-    for c in PREDEFINED_CHARS {
-        let key = rdev::EventType::KeyPress(get_key_from_char(c));
-        if let Some(s) = kb.add(&key) {
-            let ch = s.chars().last().unwrap();
-            map.insert(c, ch);
-        }
-    }
-}
+/// The layout read from the OS, until the thread that handles the keys
+/// hands it to the engine. The OS tells about new layouts on other threads,
+/// which must not change the engine under that one.
+static PENDING_LAYOUT: Lazy<Mutex<Option<KeyboardLayout>>> = Lazy::new(|| Mutex::new(None));
 
+/// Reads the keyboard layout of the OS, so the engine knows what the keys
+/// type at each level. The engine gets it from `apply_keyboard_layout`, the
+/// window right away.
 pub fn rebuild_keyboard_layout_map() {
-    unsafe {
-        if let Some(map) = KEYBOARD_LAYOUT_CHARACTER_MAP.get_mut() {
-            debug!("Rebuild keyboard layout map...");
-            build_keyboard_layout_map(map);
-            debug!("Done");
-        } else {
-            debug!("Creating keyboard layout map...");
-            let mut map = HashMap::new();
-            build_keyboard_layout_map(&mut map);
-            _ = KEYBOARD_LAYOUT_CHARACTER_MAP.set(map);
-            debug!("Done");
-        }
-    }
-}
-
-#[allow(clippy::upper_case_acronyms)]
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub enum TypingMethod {
-    VNI,
-    Telex,
-}
-
-impl FromStr for TypingMethod {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s.to_ascii_lowercase().as_str() {
-            "vni" => TypingMethod::VNI,
-            _ => TypingMethod::Telex,
-        })
-    }
-}
-
-impl Display for TypingMethod {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::VNI => "vni",
-                Self::Telex => "telex",
-            }
-        )
-    }
+    debug!("Reading keyboard layout...");
+    let layout = keyboard_layout();
+    debug!("Done: {}", layout.name());
+    *PENDING_LAYOUT.lock().unwrap() = Some(layout);
+    events::emit_state_changed();
 }
 
+/// The engine wired to the user's config and the OS: settings changes are
+/// persisted to `CONFIG_MANAGER` and the active app is read from the platform.
 pub struct InputState {
-    buffer: String,
-    display_buffer: String,
-    method: TypingMethod,
-    hotkey: Hotkey,
-    enabled: bool,
-    should_track: bool,
-    previous_word: String,
-    active_app: String,
-    active_app_identifier: Option<String>,
-    is_macro_enabled: bool,
-    macro_table: BTreeMap<String, String>,
-    temporary_disabled: bool,
-    previous_modifiers: KeyModifier,
+    engine: Engine,
     is_auto_toggle_enabled: bool,
     is_gox_mode_enabled: bool,
-    excluded_apps: Vec<AppInfo>,
-    exclude_apps_enabled: bool,
+    /// The name of the goxscript used instead of the typing method.
+    active_script: Option<String>,
+    /// Why the active script from the config could not be loaded on startup.
+    script_error: Option<String>,
 }
 
 impl InputState {
     pub fn new() -> Self {
         let config = CONFIG_MANAGER.lock().unwrap();
+        let mut engine = Engine::new(
+            TypingMethod::from_str(config.get_method()).unwrap(),
+            Hotkey::from_str(config.get_hotkey()),
+        );
+        engine.set_enabled(config.is_vietnamese_mode_enabled());
+        engine.set_macro_enabled(config.is_macro_enabled());
+        engine.set_macro_table(config.get_macro_table().clone());
+        engine.set_numpad_tone_enabled(config.is_numpad_tone_enabled());
+        engine.set_allowed_words(config.get_allowed_words().clone());
+        engine.set_excluded_apps(config.get_excluded_apps().clone());
+        engine.set_exclude_apps_enabled(config.is_exclude_apps_enabled());
         let mut state = Self {
-            buffer: String::new(),
-            display_buffer: String::new(),
-            method: TypingMethod::from_str(config.get_method()).unwrap(),
-            hotkey: Hotkey::from_str(config.get_hotkey()),
-            enabled: config.is_vietnamese_mode_enabled(),
-            should_track: true,
-            previous_word: String::new(),
-            active_app: String::new(),
-            active_app_identifier: None,
-            is_macro_enabled: config.is_macro_enabled(),
-            macro_table: config.get_macro_table().clone(),
-            temporary_disabled: false,
-            previous_modifiers: KeyModifier::empty(),
+            engine,
             is_auto_toggle_enabled: config.is_auto_toggle_enabled(),
             is_gox_mode_enabled: config.is_gox_mode_enabled(),
-            excluded_apps: config.get_excluded_apps().clone(),
-            exclude_apps_enabled: config.is_exclude_apps_enabled(),
+            active_script: None,
+            script_error: None,
         };
+        let active_script = config.get_active_script().map(str::to_string);
+        drop(config);
+        if let Some(name) = active_script {
+            state.load_script(&name);
+        }
         state.update_active_app(false);
         state
     }
 
+    /// Uses the script saved as active in the config. A script that no
+    /// longer compiles is reported, and typing falls back to Telex.
+    fn load_script(&mut self, name: &str) {
+        match ScriptStore::user().compile(name) {
+            Ok(script) => {
+                self.engine.set_script(Some(script));
+                self.active_script = Some(name.to_string());
+            }
+            Err(err) => {
+                log::error!("Falling back to Telex: {err}");
+                self.engine.set_method(TypingMethod::Telex);
+                self.script_error = Some(err);
+            }
+        }
+    }
+
+    /// Takes the settings of `CONFIG_MANAGER` again, once it read what
+    /// another goxkey process wrote. The word being typed is kept, unless
+    /// the typing method or the script changed.
+    pub fn apply_config(&mut self) {
+        debug!("Applying the reloaded config...");
+        let config = CONFIG_MANAGER.lock().unwrap();
+        let method = TypingMethod::from_str(config.get_method()).unwrap();
+        if method != self.engine.get_method() {
+            self.engine.set_method(method);
+        }
+        self.engine.set_hotkey(Hotkey::from_str(config.get_hotkey()));
+        self.engine.set_enabled(config.is_vietnamese_mode_enabled());
+        self.engine.set_macro_enabled(config.is_macro_enabled());
+        self.engine.set_macro_table(config.get_macro_table().clone());
+        self.engine.set_numpad_tone_enabled(config.is_numpad_tone_enabled());
+        self.engine.set_allowed_words(config.get_allowed_words().clone());
+        self.engine.set_excluded_apps(config.get_excluded_apps().clone());
+        self.engine.set_exclude_apps_enabled(config.is_exclude_apps_enabled());
+        self.is_auto_toggle_enabled = config.is_auto_toggle_enabled();
+        self.is_gox_mode_enabled = config.is_gox_mode_enabled();
+        let active_script = config.get_active_script().map(str::to_string);
+        drop(config);
+        if active_script == self.active_script {
+            return;
+        }
+        self.engine.set_script(None);
+        self.active_script = None;
+        self.script_error = None;
+        if let Some(name) = active_script {
+            self.load_script(&name);
+        }
+    }
+
+    /// Hands the layout last read from the OS to the engine. Only the
+    /// thread that handles the keys calls it, before reading a key.
+    pub fn apply_keyboard_layout(&mut self) {
+        let Some(layout) = PENDING_LAYOUT.lock().unwrap().take() else {
+            return;
+        };
+        self.engine.set_keyboard_layout(layout);
+    }
+
+    pub fn keyboard_layout(&self) -> &KeyboardLayout {
+        self.engine.keyboard_layout()
+    }
+
+    /// The layout the OS reported last, which the engine may not have yet.
+    pub fn reported_keyboard_layout(&self) -> KeyboardLayout {
+        PENDING_LAYOUT
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| self.engine.keyboard_layout().clone())
+    }
+
+    pub fn handle_event(&mut self, event: &KeyEvent) -> Vec<Action> {
+        self.engine.handle_event(event)
+    }
+
     pub fn update_active_app(&mut self, apply_auto_toggle: bool) -> Option<()> {
         let current_active_app = get_active_app_name();
+        let identifier = get_active_app_identifier();
         // Only check if switch app
-        if current_active_app == self.active_app {
-            self.active_app_identifier = get_active_app_identifier();
+        if current_active_app == self.engine.active_app() {
+            self.engine.set_active_app(current_active_app, identifier);
             return None;
         }
-        self.active_app = current_active_app;
-        self.active_app_identifier = get_active_app_identifier();
+        self.engine.set_active_app(current_active_app, identifier);
         if apply_auto_toggle {
             let config = CONFIG_MANAGER.lock().unwrap();
-            if config.is_vietnamese_app(&self.active_app) {
-                self.enabled = true;
+            if config.is_vietnamese_app(self.engine.active_app()) {
+                self.engine.set_enabled(true);
             }
-            if config.is_english_app(&self.active_app) {
-                self.enabled = false;
+            if config.is_english_app(self.engine.active_app()) {
+                self.engine.set_enabled(false);
             }
         }
         Some(())
     }
 
     pub fn active_app(&self) -> &str {
-        &self.active_app
-    }
-
-    pub fn set_temporary_disabled(&mut self) {
-        self.temporary_disabled = true;
+        self.engine.active_app()
     }
 
     pub fn is_gox_mode_enabled(&self) -> bool {
@@ -229,82 +181,101 @@ impl InputState {
     }
 
     pub fn is_enabled(&self) -> bool {
-        !self.temporary_disabled && self.enabled && !self.is_current_app_excluded()
+        self.engine.is_enabled_with_layout(&self.reported_keyboard_layout())
     }
 
-    pub fn is_tracking(&self) -> bool {
-        self.should_track
+    /// Whether the user turned Vietnamese on, even if something keeps it
+    /// from being typed right now.
+    pub fn is_vietnamese_mode_enabled(&self) -> bool {
+        self.engine.is_vietnamese_mode_enabled()
     }
 
-    pub fn is_buffer_empty(&self) -> bool {
-        self.buffer.is_empty()
+    pub fn is_suspended_by_layout(&self) -> bool {
+        self.engine.is_suspended_by(&self.reported_keyboard_layout())
     }
 
-    pub fn new_word(&mut self) {
-        if !self.buffer.is_empty() {
-            self.clear();
-        }
-        if self.temporary_disabled {
-            self.temporary_disabled = false;
-        }
-        self.should_track = true;
+    pub fn toggle_vietnamese(&mut self) {
+        self.engine.toggle_vietnamese();
+        self.save_vietnamese_mode();
     }
 
-    pub fn get_macro_target(&self) -> Option<&String> {
-        if !self.is_macro_enabled {
-            return None;
+    /// Persist the current Vietnamese mode, and remember it for the active
+    /// app so auto toggle can restore it later.
+    pub fn save_vietnamese_mode(&self) {
+        let enabled = self.engine.is_vietnamese_mode_enabled();
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        config.set_vietnamese_mode_enabled(enabled);
+        if enabled {
+            config.add_vietnamese_app(self.engine.active_app());
+        } else {
+            config.add_english_app(self.engine.active_app());
         }
-        self.macro_table.get(&self.display_buffer)
     }
 
-    pub fn get_typing_buffer(&self) -> &str {
-        &self.buffer
+    pub fn set_method(&mut self, method: TypingMethod) {
+        self.engine.set_method(method);
+        self.engine.set_script(None);
+        self.active_script = None;
+        self.script_error = None;
+        let mut config = CONFIG_MANAGER.lock().unwrap();
+        config.set_method(&method.to_string());
+        config.set_active_script(None);
+        drop(config);
+        events::emit_state_changed();
     }
 
-    pub fn get_displaying_word(&self) -> &str {
-        &self.display_buffer
+    pub fn active_script(&self) -> Option<&str> {
+        self.active_script.as_deref()
     }
 
-    pub fn stop_tracking(&mut self) {
-        self.clear();
-        self.should_track = false;
+    pub fn script_error(&self) -> Option<&str> {
+        self.script_error.as_deref()
     }
 
-    pub fn toggle_vietnamese(&mut self) {
-        self.enabled = !self.enabled;
-        self.temporary_disabled = false;
-        let mut config = CONFIG_MANAGER.lock().unwrap();
-        config.set_vietnamese_mode_enabled(self.enabled);
-        if self.enabled {
-            config.add_vietnamese_app(&self.active_app);
-        } else {
-            config.add_english_app(&self.active_app);
-        }
-        self.new_word();
+    /// Types with the script `name` from the user's scripts, instead of
+    /// the typing method.
+    pub fn activate_script(&mut self, name: &str) -> Result<(), String> {
+        let script = ScriptStore::user().compile(name)?;
+        self.engine.set_script(Some(script));
+        self.active_script = Some(name.to_string());
+        self.script_error = None;
+        CONFIG_MANAGER
+            .lock()
+            .unwrap()
+            .set_active_script(Some(name));
+        events::emit_state_changed();
+        Ok(())
     }
 
-    pub fn set_method(&mut self, method: TypingMethod) {
-        self.method = method;
-        self.new_word();
+    /// Goes back to the typing method, when the active script is deleted.
+    pub fn deactivate_script(&mut self) {
+        let method = TypingMethod::from_str(CONFIG_MANAGER.lock().unwrap().get_method())
+            .unwrap_or(TypingMethod::Telex);
+        self.set_method(method);
+    }
+
+    /// Follows the active script when it is renamed.
+    pub fn rename_active_script(&mut self, new_name: &str) {
+        self.active_script = Some(new_name.to_string());
         CONFIG_MANAGER
             .lock()
             .unwrap()
-            .set_method(&method.to_string());
+            .set_active_script(Some(new_name));
         events::emit_state_changed();
     }
 
     pub fn get_method(&self) -> TypingMethod {
-        self.method
+        self.engine.get_method()
     }
 
     pub fn set_hotkey(&mut self, key_sequence: &str) {
-        self.hotkey = Hotkey::from_str(key_sequence);
+        self.engine.set_hotkey(Hotkey::from_str(key_sequence));
         CONFIG_MANAGER.lock().unwrap().set_hotkey(key_sequence);
         events::emit_state_changed();
     }
 
     pub fn get_hotkey(&self) -> &Hotkey {
-        &self.hotkey
+        self.engine.get_hotkey()
     }
 
     pub fn is_auto_toggle_enabled(&self) -> bool {
@@ -321,24 +292,35 @@ impl InputState {
     }
 
     pub fn is_macro_enabled(&self) -> bool {
-        self.is_macro_enabled
+        self.engine.is_macro_enabled()
     }
 
     pub fn toggle_macro_enabled(&mut self) {
-        self.is_macro_enabled = !self.is_macro_enabled;
+        let enabled = !self.engine.is_macro_enabled();
+        self.engine.set_macro_enabled(enabled);
+        CONFIG_MANAGER.lock().unwrap().set_macro_enabled(enabled);
+        events::emit_state_changed();
+    }
+
+    pub fn is_numpad_tone_enabled(&self) -> bool {
+        self.engine.is_numpad_tone_enabled()
+    }
+
+    pub fn set_numpad_tone_enabled(&mut self, enabled: bool) {
+        self.engine.set_numpad_tone_enabled(enabled);
         CONFIG_MANAGER
             .lock()
             .unwrap()
-            .set_macro_enabled(self.is_macro_enabled);
+            .set_numpad_tone_enabled(enabled);
         events::emit_state_changed();
     }
 
     pub fn get_macro_table(&self) -> &BTreeMap<String, String> {
-        &self.macro_table
+        self.engine.get_macro_table()
     }
 
     pub fn delete_macro(&mut self, from: &String) {
-        self.macro_table.remove(from);
+        self.engine.delete_macro(from);
         CONFIG_MANAGER.lock().unwrap().delete_macro(from);
         events::emit_state_changed();
     }
@@ -348,153 +330,12 @@ impl InputState {
             .lock()
             .unwrap()
             .add_macro(from.clone(), to.clone());
-        self.macro_table.insert(from, to);
+        self.engine.add_macro(from, to);
         events::emit_state_changed();
     }
 
-    pub fn should_transform_keys(&self, _c: &char) -> bool {
-        self.is_enabled()
-    }
-
-    pub fn transform_keys(&self) -> Result<(String, TransformResult), ()> {
-        let transform_method = match self.method {
-            TypingMethod::VNI => vi::vni::transform_buffer,
-            TypingMethod::Telex => vi::telex::transform_buffer,
-        };
-        let result = std::panic::catch_unwind(|| {
-            let mut output = String::new();
-            let transform_result = transform_method(self.buffer.chars(), &mut output);
-            (output, transform_result)
-        });
-        if let Ok((output, transform_result)) = result {
-            return Ok((output, transform_result));
-        }
-        Err(())
-    }
-
-    pub fn should_send_keyboard_event(&self, word: &str) -> bool {
-        !self.display_buffer.eq(word)
-    }
-
-    pub fn should_dismiss_selection_if_needed(&self) -> bool {
-        return self.active_app.contains("Firefox");
-    }
-
-    pub fn get_backspace_count(&self, is_delete: bool) -> usize {
-        let dp_len = self.display_buffer.chars().count();
-        let backspace_count = if is_delete && dp_len >= 1 {
-            dp_len
-        } else {
-            dp_len - 1
-        };
-
-        // Add an extra backspace to compensate the initial text selection deletion.
-        // This is useful in applications like chrome, where the URL bar uses text selection
-        // for autocompletion, causing the first backspace to delete the selection instead of
-        // the character behind the cursor.
-        if is_in_text_selection() {
-            backspace_count + 1
-        } else {
-            backspace_count
-        }
-    }
-
-    pub fn replace(&mut self, buf: String) {
-        self.display_buffer = buf;
-    }
-
-    pub fn push(&mut self, c: char) {
-        if let Some(first_char) = self.buffer.chars().next() {
-            if first_char.is_numeric() {
-                self.buffer.remove(0);
-                self.display_buffer.remove(0);
-            }
-        }
-        if self.buffer.len() <= MAX_POSSIBLE_WORD_LENGTH {
-            self.buffer.push(c);
-            self.display_buffer.push(c);
-            debug!(
-                "Input buffer: {:?} - Display buffer: {:?}",
-                self.buffer, self.display_buffer
-            );
-        }
-    }
-
-    pub fn pop(&mut self) {
-        self.display_buffer.pop();
-        self.buffer = self.display_buffer.clone();
-        if self.buffer.is_empty() {
-            self.new_word();
-        }
-    }
-
-    pub fn clear(&mut self) {
-        self.previous_word = self.buffer.to_owned();
-        self.buffer.clear();
-        self.display_buffer.clear();
-    }
-
-    pub fn get_previous_word(&self) -> &str {
-        &self.previous_word
-    }
-
-    pub fn clear_previous_word(&mut self) {
-        self.previous_word.clear();
-    }
-
-    pub fn previous_word_is_stop_tracking_words(&self) -> bool {
-        STOP_TRACKING_WORDS.contains(&self.previous_word.as_str())
-    }
-
-    // a set of rules that will trigger a hard stop for tracking
-    // maybe these weird stuff should not be here, but let's
-    // implement it anyway. we'll figure out where to put these
-    // later on.
-    pub fn should_stop_tracking(&mut self) -> bool {
-        let len = self.buffer.len();
-        if len > MAX_POSSIBLE_WORD_LENGTH {
-            return true;
-        }
-        // detect attempts to restore a word
-        // by doubling tone marks like ss, rr, ff, jj, xx
-        let buf = &self.buffer;
-        if TONE_DUPLICATE_PATTERNS
-            .iter()
-            .find(|p| buf.to_ascii_lowercase().contains(*p))
-            .is_some()
-        {
-            return true;
-        }
-
-        if self.previous_word_is_stop_tracking_words() {
-            return true;
-        }
-
-        false
-    }
-
-    pub fn stop_tracking_if_needed(&mut self) {
-        if self.should_stop_tracking() {
-            self.stop_tracking();
-            debug!("! Stop tracking");
-        }
-    }
-
-    pub fn get_previous_modifiers(&self) -> KeyModifier {
-        self.previous_modifiers
-    }
-
-    pub fn save_previous_modifiers(&mut self, modifiers: KeyModifier) {
-        self.previous_modifiers = modifiers;
-    }
-
-    pub fn is_allowed_word(&self, word: &str) -> bool {
-        let config = CONFIG_MANAGER.lock().unwrap();
-        return config.is_allowed_word(word);
-    }
-
     pub fn set_exclude_apps_enabled(&mut self, enabled: bool) {
-        self.exclude_apps_enabled = enabled;
+        self.engine.set_exclude_apps_enabled(enabled);
         CONFIG_MANAGER
             .lock()
             .unwrap()
@@ -504,26 +345,13 @@ impl InputState {
 
     pub fn add_excluded_app(&mut self, app: AppInfo) {
         CONFIG_MANAGER.lock().unwrap().add_excluded_app(app.clone());
-        self.excluded_apps
-            .retain(|item| item.path != app.path && item.identifier != app.identifier);
-        self.excluded_apps.push(app);
+        self.engine.add_excluded_app(app);
         events::emit_state_changed();
     }
 
     pub fn remove_excluded_app(&mut self, path: &str) {
         CONFIG_MANAGER.lock().unwrap().remove_excluded_app(path);
-        self.excluded_apps.retain(|item| item.path != path);
+        self.engine.remove_excluded_app(path);
         events::emit_state_changed();
     }
-
-    fn is_current_app_excluded(&self) -> bool {
-        if !self.exclude_apps_enabled {
-            return false;
-        }
-        let identifier = self.active_app_identifier.as_deref().unwrap_or("");
-        self
-            .excluded_apps
-            .iter()
-            .any(|item| item.path == self.active_app || (!identifier.is_empty() && item.identifier == identifier))
-    }
 }
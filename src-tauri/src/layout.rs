@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use crate::platform::KeyModifier;
+
+/// The shift levels of a key, in the order keymaps list them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Level {
+    Base,
+    Shift,
+    AltGr,
+    ShiftAltGr,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Base, Level::Shift, Level::AltGr, Level::ShiftAltGr];
+
+    pub fn new(is_shift: bool, is_altgr: bool) -> Self {
+        match (is_shift, is_altgr) {
+            (false, false) => Level::Base,
+            (true, false) => Level::Shift,
+            (false, true) => Level::AltGr,
+            (true, true) => Level::ShiftAltGr,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The languages whose input sources type another script than Latin, even
+/// when they read the keys of a Latin layout like the Japanese IMEs do.
+const NON_LATIN_LANGUAGES: [&str; 30] = [
+    "am", "ar", "be", "bg", "bn", "el", "fa", "gu", "he", "hi", "hy", "ja", "ka", "kk", "km", "kn",
+    "ko", "lo", "mk", "ml", "mn", "my", "ne", "pa", "ru", "ta", "te", "th", "uk", "zh",
+];
+
+/// What each physical key of the keyboard types, at each of its levels.
+///
+/// The keycodes are the ones of the platform the layout was read on. The
+/// engine only sees the character a key types without modifiers, so it
+/// finds the key back from that character.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyboardLayout {
+    name: String,
+    /// The language of the input source, when the OS tells it.
+    language: Option<String>,
+    keys: BTreeMap<u32, [Option<char>; 4]>,
+}
+
+impl KeyboardLayout {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            language: None,
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// The name of the layout, as the OS shows it.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets the BCP 47 language tag of the input source, like `ja`.
+    pub fn set_language(&mut self, language: impl Into<String>) {
+        self.language = Some(language.into());
+    }
+
+    /// Whether the layout types Latin letters, the ones Vietnamese is
+    /// typed with. Layouts without letters, like unknown ones, count as
+    /// Latin.
+    pub fn is_latin(&self) -> bool {
+        let primary_language = self
+            .language
+            .as_deref()
+            .and_then(|language| language.split(['-', '_']).next());
+        if primary_language.is_some_and(|language| NON_LATIN_LANGUAGES.contains(&language)) {
+            return false;
+        }
+        let (latin, other) = self
+            .keys
+            .values()
+            .filter_map(|levels| levels[Level::Base.index()])
+            .filter(|c| c.is_alphabetic())
+            .fold((0, 0), |(latin, other), c| {
+                if is_latin_letter(c) {
+                    (latin + 1, other)
+                } else {
+                    (latin, other + 1)
+                }
+            });
+        latin >= other
+    }
+
+    pub fn set_key_char(&mut self, keycode: u32, level: Level, c: char) {
+        self.keys.entry(keycode).or_default()[level.index()] = Some(c);
+    }
+
+    pub fn key_char(&self, keycode: u32, level: Level) -> Option<char> {
+        self.keys
+            .get(&keycode)
+            .and_then(|levels| levels[level.index()])
+    }
+
+    /// The character a key types with `modifiers`, where Caps Lock only
+    /// shifts the keys that type a letter. Levels a key does not have fall
+    /// back to its base level, like keymaps do.
+    pub fn resolve(&self, keycode: u32, modifiers: KeyModifier, is_altgr: bool) -> Option<char> {
+        let base = self.key_char(keycode, Level::Base)?;
+        let is_shift = modifiers.is_shift() ^ (modifiers.is_capslock() && has_case(base));
+        let level = Level::new(is_shift, is_altgr);
+        self.key_char(keycode, level).or(Some(base))
+    }
+
+    /// The character typed by the key that types `base` without modifiers.
+    pub fn resolve_char(&self, base: char, modifiers: KeyModifier) -> Option<char> {
+        let keycode = self
+            .keys
+            .iter()
+            .find(|(_, levels)| levels[Level::Base.index()] == Some(base))
+            .map(|(&keycode, _)| keycode)?;
+        self.resolve(keycode, modifiers, false)
+    }
+}
+
+/// Whether a letter is in the Latin blocks: ASCII, then the extended ones
+/// that hold the Vietnamese letters too.
+fn is_latin_letter(c: char) -> bool {
+    matches!(c, 'A'..='Z' | 'a'..='z' | '\u{c0}'..='\u{24f}' | '\u{1e00}'..='\u{1eff}')
+}
+
+fn has_case(c: char) -> bool {
+    c.is_lowercase() || c.is_uppercase()
+}
+
+#[test]
+fn test_resolve_levels() {
+    let mut layout = KeyboardLayout::new("French");
+    layout.set_key_char(10, Level::Base, '&');
+    layout.set_key_char(10, Level::Shift, '1');
+    layout.set_key_char(24, Level::Base, 'a');
+    layout.set_key_char(24, Level::Shift, 'A');
+    layout.set_key_char(24, Level::AltGr, 'æ');
+    layout.set_key_char(65, Level::Base, ' ');
+
+    let shift = KeyModifier::MODIFIER_SHIFT;
+    let capslock = KeyModifier::MODIFIER_CAPSLOCK;
+    assert_eq!(layout.resolve(10, shift, false), Some('1'));
+    assert_eq!(layout.resolve(24, KeyModifier::new(), true), Some('æ'));
+    // Without a shift+AltGr level, the key types its base character.
+    assert_eq!(layout.resolve(24, shift, true), Some('a'));
+    assert_eq!(layout.resolve(65, shift, false), Some(' '));
+    assert_eq!(layout.resolve(11, shift, false), None);
+
+    // Caps Lock shifts letters only, and shift undoes it.
+    assert_eq!(layout.resolve_char('a', capslock), Some('A'));
+    assert_eq!(layout.resolve_char('a', capslock | shift), Some('a'));
+    assert_eq!(layout.resolve_char('&', capslock), Some('&'));
+    assert_eq!(layout.resolve_char('&', shift), Some('1'));
+    assert_eq!(layout.resolve_char('z', shift), None);
+}
+
+#[test]
+fn test_is_latin() {
+    let mut layout = KeyboardLayout::new("Russian");
+    assert!(layout.is_latin());
+    for (keycode, c) in [(24, 'й'), (25, 'ц'), (26, 'у'), (10, '1')] {
+        layout.set_key_char(keycode, Level::Base, c);
+    }
+    layout.set_key_char(27, Level::Base, 'q');
+    assert!(!layout.is_latin());
+
+    let mut layout = KeyboardLayout::new("French");
+    for (keycode, c) in [(24, 'a'), (25, 'z'), (11, 'é'), (10, '&')] {
+        layout.set_key_char(keycode, Level::Base, c);
+    }
+    assert!(layout.is_latin());
+    // Japanese IMEs read the keys of a Latin layout.
+    layout.set_language("ja-JP");
+    assert!(!layout.is_latin());
+    layout.set_language("vi");
+    assert!(layout.is_latin());
+}
@@ -1,83 +1,59 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod config;
+mod engine;
 mod events;
 mod hotkey;
 mod input;
+mod layout;
 mod platform;
 mod scripting;
+mod scripts;
 mod state;
+mod trace;
 mod apps;
 
 use std::process::Command;
 use std::thread;
 
 use crate::apps::AppInfo;
+use crate::engine::Action;
 use crate::hotkey::Hotkey;
-use input::{
-    rebuild_keyboard_layout_map, HOTKEY_MATCHING, HOTKEY_MATCHING_CIRCUIT_BREAK, HOTKEY_MODIFIERS,
-    INPUT_STATE,
-};
+use crate::scripting::diagnostic::Diagnostic;
+use crate::scripting::resolver::ModuleDir;
+use crate::scripts::{ScriptInfo, ScriptStore};
+use input::{rebuild_keyboard_layout_map, TypingMethod, INPUT_STATE};
 use serde::Serialize;
 use platform::{
-    add_app_change_callback, ensure_accessibility_permission, run_event_listener, send_backspace,
-    send_string, EventTapType, Handle, KeyModifier, PressedKey, KEY_DELETE, KEY_ENTER, KEY_ESCAPE,
-    KEY_SPACE, KEY_TAB, RAW_ARROW_DOWN, RAW_ARROW_LEFT, RAW_ARROW_RIGHT, RAW_ARROW_UP,
-    RAW_KEY_GLOBE,
+    add_app_change_callback, add_layout_change_callback, ensure_accessibility_permission,
+    run_event_listener, send_backspace, send_string, Handle, KeyEvent,
 };
 use state::{TypingMethodDto, UiState};
 use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
 use tauri::{AppHandle, Emitter, Manager, WebviewWindowBuilder, WindowEvent};
 
-fn do_transform_keys(handle: Handle, is_delete: bool) -> bool {
-    unsafe {
-        if let Ok((output, transform_result)) = INPUT_STATE.transform_keys() {
-            log::debug!("Transformed: {:?}", output);
-            if INPUT_STATE.should_send_keyboard_event(&output) || is_delete {
-                if INPUT_STATE.should_dismiss_selection_if_needed() {
-                    _ = send_string(handle, " ");
-                    _ = send_backspace(handle, 1);
-                }
-
-                let backspace_count = INPUT_STATE.get_backspace_count(is_delete);
-                log::debug!("Backspace count: {}", backspace_count);
-                _ = send_backspace(handle, backspace_count);
-                _ = send_string(handle, &output);
-                log::debug!("Sent: {:?}", output);
-                INPUT_STATE.replace(output);
-                if transform_result.letter_modification_removed
-                    || transform_result.tone_mark_removed
-                {
-                    INPUT_STATE.stop_tracking();
-                }
-                return true;
+/// Perform the edits requested by the engine on the focused application.
+/// Returns `true` if the original key event should be blocked.
+fn apply_actions(handle: Handle, actions: Vec<Action>) -> bool {
+    let mut swallowed = false;
+    for action in actions {
+        match action {
+            Action::Backspace(count) => {
+                log::debug!("Backspace count: {}", count);
+                _ = send_backspace(handle, count);
+            }
+            Action::Insert(text) => {
+                _ = send_string(handle, &text);
+                log::debug!("Sent: {:?}", text);
             }
+            Action::Swallow => swallowed = true,
+            Action::VietnameseToggled => unsafe {
+                INPUT_STATE.save_vietnamese_mode();
+                events::emit_state_changed();
+            },
         }
     }
-    false
-}
-
-fn do_restore_word(handle: Handle) {
-    unsafe {
-        let backspace_count = INPUT_STATE.get_backspace_count(true);
-        log::debug!("Backspace count: {}", backspace_count);
-        _ = send_backspace(handle, backspace_count);
-        let typing_buffer = INPUT_STATE.get_typing_buffer();
-        _ = send_string(handle, typing_buffer);
-        log::debug!("Sent: {:?}", typing_buffer);
-        INPUT_STATE.replace(typing_buffer.to_owned());
-    }
-}
-
-fn do_macro_replace(handle: Handle, target: &String) {
-    unsafe {
-        let backspace_count = INPUT_STATE.get_backspace_count(true);
-        log::debug!("Backspace count: {}", backspace_count);
-        _ = send_backspace(handle, backspace_count);
-        _ = send_string(handle, target);
-        log::debug!("Sent: {:?}", target);
-        INPUT_STATE.replace(target.to_owned());
-    }
+    swallowed
 }
 
 unsafe fn toggle_vietnamese() {
@@ -96,170 +72,64 @@ unsafe fn auto_toggle_vietnamese() {
     events::emit_state_changed();
 }
 
-fn event_handler(
-    handle: Handle,
-    event_type: EventTapType,
-    pressed_key: Option<PressedKey>,
-    modifiers: KeyModifier,
-) -> bool {
-    unsafe {
-        let pressed_key_code = pressed_key.and_then(|p| match p {
-            PressedKey::Char(c) => Some(c),
-            _ => None,
-        });
-
-        if event_type == EventTapType::FlagsChanged {
-            if modifiers.is_empty() {
-                HOTKEY_MODIFIERS = KeyModifier::MODIFIER_NONE;
-                HOTKEY_MATCHING = false;
-                HOTKEY_MATCHING_CIRCUIT_BREAK = false;
-            } else {
-                HOTKEY_MODIFIERS.set(modifiers, true);
-            }
-        }
-
-        // Check hotkey match using current modifiers for KeyDown events
-        let check_modifiers = if event_type == EventTapType::KeyDown {
-            modifiers
-        } else {
-            HOTKEY_MODIFIERS
-        };
+fn event_handler(handle: Handle, event: &KeyEvent) -> bool {
+    let actions = unsafe {
+        INPUT_STATE.apply_keyboard_layout();
+        trace::record(event, INPUT_STATE.active_app());
+        INPUT_STATE.handle_event(event)
+    };
+    apply_actions(handle, actions)
+}
 
-        let (is_hotkey_matched, hotkey_requires_key) = {
-            let hotkey = INPUT_STATE.get_hotkey();
-            (hotkey.is_match(check_modifiers, pressed_key_code), hotkey.keycode().is_some())
-        };
-        let triggered_by_key = event_type == EventTapType::KeyDown && pressed_key_code.is_some();
-        let triggered_by_flags = !hotkey_requires_key && event_type == EventTapType::FlagsChanged;
-
-        if is_hotkey_matched && !HOTKEY_MATCHING && (triggered_by_key || triggered_by_flags) {
-            log::debug!(
-                "Global hotkey matched via {:?} (key_required: {})",
-                event_type,
-                hotkey_requires_key
-            );
-            toggle_vietnamese();
-            HOTKEY_MATCHING = true;
-            if triggered_by_key {
-                return true;
+/// Runs without a window or a tray, as the engine of the input method
+/// framework that started this process. The goxkey the user started owns
+/// the settings, which are read again whenever it saves them.
+#[cfg(target_os = "linux")]
+fn run_framework_engine(framework: platform::Framework) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Without a window to keep in sync, the app and config changes are
+    // applied on the thread of the keys too.
+    static APP_CHANGED: AtomicBool = AtomicBool::new(false);
+    static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+    add_app_change_callback(|| APP_CHANGED.store(true, Ordering::Relaxed));
+    add_layout_change_callback(rebuild_keyboard_layout_map);
+    config::watch_config_file(|| CONFIG_CHANGED.store(true, Ordering::Relaxed));
+    platform::run_framework_engine(framework, &|handle: Handle, event: &KeyEvent| {
+        unsafe {
+            if CONFIG_CHANGED.swap(false, Ordering::Relaxed) {
+                INPUT_STATE.apply_config();
             }
-        }
-
-        if HOTKEY_MATCHING && !is_hotkey_matched {
-            HOTKEY_MATCHING_CIRCUIT_BREAK = true;
-        }
-        if !pressed_key_code.is_some() {
-            HOTKEY_MATCHING = is_hotkey_matched;
-        }
-
-        match pressed_key {
-            Some(pressed_key) => match pressed_key {
-                PressedKey::Raw(raw_keycode) => {
-                    if raw_keycode == RAW_KEY_GLOBE {
-                        toggle_vietnamese();
-                        return true;
-                    }
-                    if raw_keycode == RAW_ARROW_UP || raw_keycode == RAW_ARROW_DOWN {
-                        INPUT_STATE.new_word();
-                    }
-                    if raw_keycode == RAW_ARROW_LEFT || raw_keycode == RAW_ARROW_RIGHT {
-                        INPUT_STATE.new_word();
-                    }
-                }
-                PressedKey::Char(keycode) => {
-                    if INPUT_STATE.is_enabled() {
-                        match keycode {
-                            KEY_ENTER | KEY_TAB | KEY_SPACE | KEY_ESCAPE => {
-                                let is_valid_word = vi::validation::is_valid_word(
-                                    INPUT_STATE.get_displaying_word(),
-                                );
-                                let is_allowed_word =
-                                    INPUT_STATE.is_allowed_word(INPUT_STATE.get_displaying_word());
-                                let is_transformed_word = !INPUT_STATE
-                                    .get_typing_buffer()
-                                    .eq(INPUT_STATE.get_displaying_word());
-                                if is_transformed_word && !is_valid_word && !is_allowed_word {
-                                    do_restore_word(handle);
-                                }
-
-                                if INPUT_STATE.previous_word_is_stop_tracking_words() {
-                                    INPUT_STATE.clear_previous_word();
-                                }
-
-                                if keycode == KEY_TAB || keycode == KEY_SPACE {
-                                    if let Some(macro_target) = INPUT_STATE.get_macro_target() {
-                                        log::debug!("Macro: {}", macro_target);
-                                        do_macro_replace(handle, macro_target)
-                                    }
-                                }
-
-                                INPUT_STATE.new_word();
-                            }
-                            KEY_DELETE => {
-                                if !modifiers.is_empty() && !modifiers.is_shift() {
-                                    INPUT_STATE.new_word();
-                                } else {
-                                    INPUT_STATE.pop();
-                                }
-                            }
-                            c => {
-                                if "()[]{}<>/\\!@#$%^&*-_=+|~`,.;'\"/".contains(c)
-                                    || (c.is_numeric() && modifiers.is_shift())
-                                {
-                                    if c.is_numeric() {
-                                        INPUT_STATE.push(c);
-                                    }
-                                    INPUT_STATE.new_word();
-                                } else if modifiers.is_super() || modifiers.is_alt() {
-                                    INPUT_STATE.new_word();
-                                } else if INPUT_STATE.is_tracking() {
-                                    INPUT_STATE.push(
-                                        if modifiers.is_shift() || modifiers.is_capslock() {
-                                            c.to_ascii_uppercase()
-                                        } else {
-                                            c
-                                        },
-                                    );
-                                    let ret = do_transform_keys(handle, false);
-                                    INPUT_STATE.stop_tracking_if_needed();
-                                    return ret;
-                                }
-                            }
-                        }
-                    } else if matches!(keycode, KEY_ENTER | KEY_TAB | KEY_SPACE | KEY_ESCAPE) {
-                        INPUT_STATE.new_word();
-                    } else if !modifiers.is_empty() {
-                        INPUT_STATE.new_word();
-                    }
-                }
-            },
-            None => {
-                let previous_modifiers = INPUT_STATE.get_previous_modifiers();
-                if previous_modifiers.is_empty() {
-                    if modifiers.is_control() {
-                        if !INPUT_STATE.get_typing_buffer().is_empty() {
-                            do_restore_word(handle);
-                        }
-                        INPUT_STATE.set_temporary_disabled();
-                    }
-                    if modifiers.is_super() || event_type == EventTapType::Other {
-                        INPUT_STATE.new_word();
-                    }
-                }
+            if APP_CHANGED.swap(false, Ordering::Relaxed) {
+                auto_toggle_vietnamese();
             }
         }
-        INPUT_STATE.save_previous_modifiers(modifiers);
-    }
-    false
+        event_handler(handle, event)
+    });
 }
 
 fn spawn_event_sources() {
+    if let Some(path) = std::env::var_os(trace::TRACE_ENV_VAR) {
+        let session = trace::Session::from_config(&config::CONFIG_MANAGER.lock().unwrap());
+        if let Err(err) = trace::start_recording(std::path::Path::new(&path), session) {
+            log::error!("Unable to record keystroke trace: {err}");
+        }
+    }
     thread::spawn(|| {
         run_event_listener(&event_handler);
     });
     add_app_change_callback(|| unsafe {
         auto_toggle_vietnamese();
     });
+    // Read on the thread of the notification, applied on the one of the
+    // keys.
+    add_layout_change_callback(rebuild_keyboard_layout_map);
+    // The engine an input method framework runs saves the settings too.
+    #[cfg(target_os = "linux")]
+    config::watch_config_file(|| unsafe {
+        INPUT_STATE.apply_config();
+        events::emit_state_changed();
+    });
 }
 
 #[derive(Serialize)]
@@ -278,7 +148,7 @@ fn get_state() -> UiState {
 #[tauri::command]
 fn set_enabled(enabled: bool) -> UiState {
     unsafe {
-        if INPUT_STATE.is_enabled() != enabled {
+        if INPUT_STATE.is_vietnamese_mode_enabled() != enabled {
             INPUT_STATE.toggle_vietnamese();
         }
     }
@@ -287,12 +157,16 @@ fn set_enabled(enabled: bool) -> UiState {
 }
 
 #[tauri::command]
-fn set_typing_method(method: TypingMethodDto) -> UiState {
+fn set_typing_method(method: TypingMethodDto) -> Result<UiState, String> {
     unsafe {
-        INPUT_STATE.set_method(method.into());
+        match method {
+            TypingMethodDto::Telex => INPUT_STATE.set_method(TypingMethod::Telex),
+            TypingMethodDto::Vni => INPUT_STATE.set_method(TypingMethod::VNI),
+            TypingMethodDto::Custom { name } => INPUT_STATE.activate_script(&name)?,
+        }
     }
     events::emit_state_changed();
-    events::current_state()
+    Ok(events::current_state())
 }
 
 #[tauri::command]
@@ -355,6 +229,15 @@ fn set_macro_enabled(enabled: bool) -> UiState {
     events::current_state()
 }
 
+#[tauri::command]
+fn set_numpad_tone_enabled(enabled: bool) -> UiState {
+    unsafe {
+        INPUT_STATE.set_numpad_tone_enabled(enabled);
+    }
+    events::emit_state_changed();
+    events::current_state()
+}
+
 #[tauri::command]
 fn set_exclude_apps_enabled(enabled: bool) -> UiState {
     unsafe {
@@ -404,12 +287,14 @@ fn remove_excluded_app(path: String) -> UiState {
 }
 
 #[tauri::command]
-fn set_launch_on_login(enabled: bool) -> UiState {
-    if let Err(err) = platform::update_launch_on_login(enabled) {
-        log::error!("Unable to update launch on login: {err}");
-    }
+fn set_launch_on_login(enabled: bool) -> Result<UiState, String> {
+    platform::update_launch_on_login(enabled).map_err(|err| {
+        let message = format!("Unable to update launch on login: {err}");
+        log::error!("{message}");
+        message
+    })?;
     events::emit_state_changed();
-    events::current_state()
+    Ok(events::current_state())
 }
 
 #[tauri::command]
@@ -467,6 +352,64 @@ fn set_open_window_on_launch(enabled: bool) -> UiState {
     events::current_state()
 }
 
+#[tauri::command]
+fn check_script(source: String) -> Vec<Diagnostic> {
+    let modules = ModuleDir(config::ConfigStore::get_scripts_dir());
+    scripting::check_script(&source, &modules)
+}
+
+#[tauri::command]
+fn format_script(source: String) -> Result<String, Vec<Diagnostic>> {
+    scripting::format_script(&source)
+}
+
+fn script_list() -> Result<Vec<ScriptInfo>, String> {
+    ScriptStore::user().infos(unsafe { INPUT_STATE.active_script() })
+}
+
+#[tauri::command]
+fn list_scripts() -> Result<Vec<ScriptInfo>, String> {
+    script_list()
+}
+
+#[tauri::command]
+fn create_script(name: String, source: String) -> Result<Vec<ScriptInfo>, String> {
+    ScriptStore::user().create(&name, &source)?;
+    script_list()
+}
+
+#[tauri::command]
+fn rename_script(name: String, new_name: String) -> Result<Vec<ScriptInfo>, String> {
+    ScriptStore::user().rename(&name, &new_name)?;
+    unsafe {
+        if INPUT_STATE.active_script() == Some(name.as_str()) {
+            INPUT_STATE.rename_active_script(&new_name);
+        }
+    }
+    script_list()
+}
+
+#[tauri::command]
+fn delete_script(name: String) -> Result<Vec<ScriptInfo>, String> {
+    ScriptStore::user().delete(&name)?;
+    unsafe {
+        if INPUT_STATE.active_script() == Some(name.as_str()) {
+            INPUT_STATE.deactivate_script();
+        }
+    }
+    script_list()
+}
+
+#[tauri::command]
+fn validate_script(name: String) -> Result<Vec<Diagnostic>, String> {
+    ScriptStore::user().validate(&name)
+}
+
+#[tauri::command]
+fn activate_script(name: String) -> Result<UiState, String> {
+    set_typing_method(TypingMethodDto::Custom { name })
+}
+
 #[tauri::command]
 fn open_url(url: String) {
     #[cfg(target_os = "macos")]
@@ -500,11 +443,65 @@ fn show_main_window(app: &AppHandle) {
     }
 }
 
+/// Runs the `expect` assertions of a goxscript file for
+/// `goxkey goxscript test <file>`, and returns the process exit code.
+fn run_script_tests(path: &str) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            return 2;
+        }
+    };
+    let modules = ModuleDir(config::ConfigStore::get_scripts_dir());
+    let results = match scripting::test_script(&source, &modules) {
+        Ok(results) => results,
+        Err(diagnostics) => {
+            for diagnostic in diagnostics.iter().filter(|d| d.is_error()) {
+                eprintln!("{path}:{diagnostic}");
+            }
+            return 2;
+        }
+    };
+    let mut failed = 0;
+    for result in &results {
+        if result.passed() {
+            println!("PASS {path}:{} {:?}", result.span.start, result.input);
+        } else {
+            failed += 1;
+            println!(
+                "FAIL {path}:{} {:?}: expected {:?}, got {:?}",
+                result.span.start, result.input, result.expected, result.actual
+            );
+        }
+    }
+    println!("{} passed, {failed} failed", results.len() - failed);
+    i32::from(failed > 0)
+}
+
 fn main() {
+    #[cfg(target_os = "linux")]
+    platform::init_threads();
+
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, command, subcommand, path] = &args[..] {
+        if command == "goxscript" && subcommand == "test" {
+            std::process::exit(run_script_tests(path));
+        }
+    }
+
     env_logger::init();
     rebuild_keyboard_layout_map();
+    unsafe { INPUT_STATE.apply_keyboard_layout() };
+
+    #[cfg(target_os = "linux")]
+    if let Some(framework) = platform::framework_engine() {
+        run_framework_engine(framework);
+        return;
+    }
 
     tauri::Builder::default()
+        .plugin(tauri_plugin_notification::init())
         .on_window_event(|window, event| {
             if let WindowEvent::CloseRequested { api, .. } = event {
                 api.prevent_close();
@@ -524,14 +521,19 @@ fn main() {
                 .lock()
                 .map(|c| c.open_window_on_launch())
                 .unwrap_or(false);
+            // Make sure the user sees why their script is not in use.
+            let has_script_error = unsafe { INPUT_STATE.script_error().is_some() };
             if let Some(window) = app.get_webview_window("main") {
-                if open_window_on_launch {
+                if open_window_on_launch || has_script_error {
                     let _ = window.show();
                 } else {
                     let _ = window.hide();
                 }
             }
             events::register_app_handle(&app.handle());
+            if has_script_error {
+                events::notify_script_error(&app.handle());
+            }
             let has_permission = ensure_accessibility_permission();
             events::set_accessibility_ready(has_permission);
             if has_permission {
@@ -603,9 +605,18 @@ fn main() {
             set_show_menubar_icon,
             set_theme,
             set_language,
+            set_numpad_tone_enabled,
             set_exclude_apps_enabled,
             set_open_window_on_launch,
             search_apps,
+            check_script,
+            format_script,
+            list_scripts,
+            create_script,
+            rename_script,
+            delete_script,
+            validate_script,
+            activate_script,
             open_url
         ])
         .run(tauri::generate_context!())
@@ -1,26 +1,200 @@
-// TODO: Implement this
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
 
-use super::{CallbackFn, KeyModifier};
+mod linux_atspi;
+mod linux_composer;
+mod linux_evdev;
+mod linux_fcitx5;
+mod linux_hotkey;
+mod linux_ibus;
+mod linux_layout;
+mod linux_wayland;
+mod linux_window;
+mod linux_x11;
+
+use super::{CallbackFn, KeyEvent, KeyModifier};
+use crate::layout::KeyboardLayout;
 
 pub const SYMBOL_SHIFT: &str = "⇧";
 pub const SYMBOL_CTRL: &str = "⌃";
 pub const SYMBOL_SUPER: &str = "❖";
 pub const SYMBOL_ALT: &str = "⌥";
 
+/// The backend the edits of the engine are sent to, only valid while the
+/// event listener callback runs.
+#[derive(Clone, Copy)]
+pub enum Handle {
+    X11(*const linux_x11::Injector),
+    Evdev(*const linux_evdev::Injector),
+    /// The input method frontends, which show the word as preedit text.
+    Composer(*const linux_composer::Composer),
+}
+
+/// Lets the threads of the event listener, the window watcher and the
+/// toolkit share Xlib. Has to come before any other Xlib call.
+pub fn init_threads() {
+    unsafe { x11::xlib::XInitThreads() };
+}
+
 pub fn get_home_dir() -> Option<PathBuf> {
     env::var("HOME").ok().map(PathBuf::from)
 }
 
-pub fn send_backspace(count: usize) -> Result<(), ()> {
-    todo!()
+pub fn send_backspace(handle: Handle, count: usize) -> Result<(), ()> {
+    queue_backspace(handle, count, is_in_text_selection);
+    Ok(())
+}
+
+/// The backends that type the backspaces add one when the focused text has
+/// a selection, which the first backspace only deletes, like the address
+/// bar of browsers autocompletes with. The preedit never touches it.
+fn queue_backspace(handle: Handle, count: usize, is_in_text_selection: fn() -> bool) {
+    let typed_count = || {
+        if is_in_text_selection() {
+            count + 1
+        } else {
+            count
+        }
+    };
+    unsafe {
+        match handle {
+            Handle::X11(injector) => (*injector).queue_backspace(typed_count()),
+            Handle::Evdev(injector) => (*injector).queue_backspace(typed_count()),
+            Handle::Composer(composer) => (*composer).queue_backspace(count),
+        }
+    }
+}
+
+pub fn send_string(handle: Handle, string: &str) -> Result<(), ()> {
+    unsafe {
+        match handle {
+            Handle::X11(injector) => (*injector).queue_string(string),
+            Handle::Evdev(injector) => (*injector).queue_string(string),
+            Handle::Composer(composer) => (*composer).queue_string(string),
+        }
+    }
+    Ok(())
+}
+
+/// The input method frameworks that start goxkey as their engine, in a
+/// process of its own next to the one the user started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framework {
+    /// Started by ibus-daemon with `--ibus`.
+    IBus,
+    /// Started by D-Bus for the Fcitx5 addon with `--fcitx5`.
+    Fcitx5,
+}
+
+/// The framework that started this process, from its command line.
+pub fn framework_engine() -> Option<Framework> {
+    env::args().find_map(|arg| match arg.as_str() {
+        "--ibus" => Some(Framework::IBus),
+        "--fcitx5" => Some(Framework::Fcitx5),
+        _ => None,
+    })
+}
+
+/// Runs as the engine of `framework` until it lets go of the process.
+pub fn run_framework_engine(framework: Framework, callback: &CallbackFn) {
+    match framework {
+        Framework::IBus => {
+            if let Err(err) = linux_ibus::run_event_listener(callback) {
+                log::error!("Cannot run as an IBus engine: {err}");
+            }
+        }
+        Framework::Fcitx5 => {
+            if let Err(err) = linux_fcitx5::run_event_listener(callback) {
+                log::error!("Cannot run as a Fcitx5 engine: {err}");
+            }
+        }
+    }
+}
+
+/// Whether the goxkey engine of IBus or Fcitx5 runs, which then gets the
+/// keys.
+static IBUS_ENGINE_RUNNING: AtomicBool = AtomicBool::new(false);
+static FCITX5_ENGINE_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Keeps `running` telling whether `name` has an owner on the bus.
+fn watch_bus_name(
+    connection: zbus::blocking::Connection,
+    name: &'static str,
+    running: &'static AtomicBool,
+) -> zbus::Result<()> {
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&connection)?;
+    // Subscribed before asking, so no change is missed in between.
+    let changes = dbus.receive_name_owner_changed_with_args(&[(0, name)])?;
+    running.store(dbus.name_has_owner(name.try_into()?)?, Ordering::Relaxed);
+    thread::spawn(move || {
+        for change in changes {
+            if let Ok(args) = change.args() {
+                running.store(args.new_owner().is_some(), Ordering::Relaxed);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Watches the frameworks that can run goxkey as their engine. Returns
+/// whether one of them runs.
+fn watch_framework_engines() -> bool {
+    let ibus = linux_ibus::watch_engine(&IBUS_ENGINE_RUNNING)
+        .map_err(|err| log::debug!("Not watching the IBus engine: {err}"));
+    let fcitx5 = linux_fcitx5::watch_engine(&FCITX5_ENGINE_RUNNING)
+        .map_err(|err| log::debug!("Not watching the Fcitx5 engine: {err}"));
+    ibus.is_ok() || fcitx5.is_ok()
+}
+
+/// Whether a framework runs the goxkey engine, in a process of its own.
+fn is_framework_engine_running() -> bool {
+    IBUS_ENGINE_RUNNING.load(Ordering::Relaxed) || FCITX5_ENGINE_RUNNING.load(Ordering::Relaxed)
+}
+
+/// Listens as the input method of a Wayland session when the compositor
+/// supports it, and through X11 otherwise, which also covers the XWayland
+/// apps of a session whose compositor does not. Without either, like on
+/// the console, the keyboards of /dev/input are read directly.
+///
+/// The keys go untouched while an input method framework runs goxkey as
+/// its engine, which types them instead. The Wayland input method is left
+/// to the framework when one runs, as a session only has one.
+pub fn run_event_listener(callback: &'static CallbackFn) {
+    let has_framework = watch_framework_engines();
+    let callback = move |handle: Handle, event: &KeyEvent| {
+        !is_framework_engine_running() && callback(handle, event)
+    };
+    let callback: &CallbackFn = &callback;
+    if env::var_os("WAYLAND_DISPLAY").is_some() && !has_framework {
+        match linux_wayland::run_event_listener(callback) {
+            Ok(()) => return,
+            Err(err) => log::warn!("Cannot listen as a Wayland input method: {err}"),
+        }
+    }
+    match linux_x11::run_event_listener(callback) {
+        Ok(()) => return,
+        Err(err) => log::warn!("Cannot listen to X11 key events: {err}"),
+    }
+    if let Err(err) = linux_evdev::run_event_listener(callback) {
+        log::error!("Cannot listen to key events: {err}");
+    }
 }
 
-pub fn send_string(string: &str) -> Result<(), ()> {
-    todo!()
+pub fn keyboard_layout() -> KeyboardLayout {
+    linux_layout::keyboard_layout()
 }
 
-pub fn run_event_listener(callback: &CallbackFn) {
-    todo!()
+pub fn add_layout_change_callback<F>(cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+    if let Err(err) = linux_layout::watch_keyboard_layout(cb) {
+        log::error!("Cannot watch the keyboard layout: {err}");
+    }
 }
 
 pub fn ensure_accessibility_permission() -> bool {
@@ -28,17 +202,172 @@ pub fn ensure_accessibility_permission() -> bool {
 }
 
 pub fn is_in_text_selection() -> bool {
-    todo!()
+    linux_atspi::is_in_text_selection()
 }
 
-pub fn update_launch_on_login(is_enable: bool) {
-    todo!()
+pub fn add_app_change_callback<F>(cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+    if let Err(err) = linux_window::watch_active_window(cb) {
+        log::error!("Cannot watch the active window: {err}");
+    }
 }
 
-pub fn is_launch_on_login() {
-    todo!()
+/// The executable of the active window, the closest thing to the app
+/// bundle path used on macOS.
+pub fn get_active_app_name() -> String {
+    linux_window::active_window()
+        .and_then(|window| window.exe)
+        .map(|exe| exe.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "/Unknown".to_string())
 }
 
-pub fn check_hotkey_conflict(_: KeyModifier, _: Option<char>) -> std::result::Result<(), String> {
-    Ok(())
+/// The `WM_CLASS` class of the active window.
+pub fn get_active_app_identifier() -> Option<String> {
+    linux_window::active_window().and_then(|window| window.class)
+}
+
+/// The XDG config directory, where the desktop and the other apps keep
+/// their settings.
+fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| get_home_dir().map(|home| home.join(".config")))
+}
+
+/// The XDG autostart entry that launches goxkey when the user logs in.
+fn autostart_entry_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("autostart").join("goxkey.desktop"))
+}
+
+/// Quotes an argument of `Exec` the way the desktop entry specification
+/// asks: in double quotes, with `"`, `` ` ``, `$` and `\` escaped, then the
+/// backslashes escaped again as the value is a string.
+fn quote_exec_argument(argument: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in argument.chars() {
+        match c {
+            '"' | '`' | '$' => quoted.push_str(&format!("\\\\{c}")),
+            '\\' => quoted.push_str("\\\\\\\\"),
+            '%' => quoted.push_str("%%"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn autostart_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Gõ Gõ\n\
+         Exec={}\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        quote_exec_argument(&exe.to_string_lossy())
+    )
+}
+
+pub fn update_launch_on_login(is_enable: bool) -> Result<(), io::Error> {
+    let path = autostart_entry_path().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "cannot find the config directory")
+    })?;
+    if !is_enable {
+        return match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    // An AppImage runs from a mount that is gone after it exits, the
+    // image itself is what has to be launched.
+    let exe = match env::var_os("APPIMAGE") {
+        Some(image) => PathBuf::from(image),
+        None => env::current_exe()?,
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, autostart_entry(&exe))
+}
+
+pub fn is_launch_on_login() -> bool {
+    autostart_entry_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|entry| !entry.lines().any(|line| line.trim() == "Hidden=true"))
+        .unwrap_or(false)
+}
+
+pub fn check_hotkey_conflict(modifiers: KeyModifier, key: Option<char>) -> Result<(), String> {
+    linux_hotkey::check_hotkey_conflict(modifiers, key)
+}
+
+/// Starts a bus of its own for the tests of the D-Bus backends, and
+/// returns the daemon to kill with the address to connect to. `None`
+/// without `dbus-daemon`.
+#[cfg(test)]
+fn private_bus() -> Option<(std::process::Child, String)> {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let mut daemon = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let mut address = String::new();
+    BufReader::new(daemon.stdout.take()?)
+        .read_line(&mut address)
+        .ok()?;
+    Some((daemon, address.trim().to_string()))
+}
+
+/// Puts an acute accent on the `a` before `s`, like telex.
+#[cfg(test)]
+fn fake_telex(handle: Handle, event: &KeyEvent) -> bool {
+    use super::{EventTapType, PressedKey};
+
+    if let (EventTapType::KeyDown, Some(PressedKey::Char('s'))) = (event.event_type, event.key) {
+        send_backspace(handle, 1).unwrap();
+        send_string(handle, "á").unwrap();
+        return true;
+    }
+    false
+}
+
+#[test]
+fn test_selection_keeps_committed_text_of_preedit() {
+    use linux_composer::{Composer, Edits};
+
+    let composer = Composer::default();
+    composer.queue_string("vie");
+    _ = composer.take_edits();
+    // The engine replacing `vie` by `việ` with a range selected.
+    queue_backspace(Handle::Composer(&composer), 1, || true);
+    composer.queue_string("ệ");
+    assert_eq!(
+        composer.take_edits(),
+        Edits {
+            backspaces: 0,
+            commit: String::new(),
+            preedit: "việ".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_autostart_entry() {
+    assert_eq!(
+        quote_exec_argument("/opt/Gõ Gõ/goxkey"),
+        "\"/opt/Gõ Gõ/goxkey\""
+    );
+    assert_eq!(
+        quote_exec_argument(r#"/tmp/a"b$c\d%e"#),
+        r#""/tmp/a\\"b\\$c\\\\d%%e""#
+    );
+    let entry = autostart_entry(Path::new("/usr/bin/goxkey"));
+    assert!(entry.starts_with("[Desktop Entry]\nType=Application\n"));
+    assert!(entry.contains("\nExec=\"/usr/bin/goxkey\"\n"));
 }
@@ -0,0 +1,224 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::thread;
+
+use zbus::blocking::{connection, Connection, MessageIterator};
+use zbus::message::{Message, Type as MessageType};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, Structure, Value};
+use zbus::MatchRule;
+
+/// Whether the focused text has a selection, as of the last AT-SPI event.
+static HAS_SELECTION: AtomicBool = AtomicBool::new(false);
+
+static WATCHER: Once = Once::new();
+
+const REGISTRY_NAME: &str = "org.a11y.atspi.Registry";
+const REGISTRY_PATH: &str = "/org/a11y/atspi/registry";
+const OBJECT_EVENTS: &str = "org.a11y.atspi.Event.Object";
+
+/// Connects to the accessibility bus, which the apps only expose their
+/// widgets on once accessibility is enabled.
+fn a11y_bus() -> zbus::Result<Connection> {
+    let session = Connection::session()?;
+    let enable = session.call_method(
+        Some("org.a11y.Bus"),
+        "/org/a11y/bus",
+        Some("org.freedesktop.DBus.Properties"),
+        "Set",
+        &("org.a11y.Status", "IsEnabled", Value::from(true)),
+    );
+    if let Err(err) = enable {
+        log::warn!("Cannot enable accessibility: {err}");
+    }
+    let reply = session.call_method(
+        Some("org.a11y.Bus"),
+        "/org/a11y/bus",
+        Some("org.a11y.Bus"),
+        "GetAddress",
+        &(),
+    )?;
+    let address: String = reply.body().deserialize()?;
+    connection::Builder::address(address.as_str())?.build()
+}
+
+/// The detail of an event and its first number, whose other arguments
+/// changed across AT-SPI versions.
+fn event_detail(message: &Message) -> Option<(String, i32)> {
+    let body = message.body();
+    let args: Structure = body.deserialize().ok()?;
+    match args.fields() {
+        [Value::Str(detail), Value::I32(detail1), ..] => Some((detail.to_string(), *detail1)),
+        _ => None,
+    }
+}
+
+fn has_selection(connection: &Connection, app: &str, path: &ObjectPath) -> zbus::Result<bool> {
+    let text = Some("org.a11y.atspi.Text");
+    let reply = connection.call_method(Some(app), path, text, "GetNSelections", &())?;
+    let count: i32 = reply.body().deserialize()?;
+    if count == 0 {
+        return Ok(false);
+    }
+    let reply = connection.call_method(Some(app), path, text, "GetSelection", &(0,))?;
+    let (start, end): (i32, i32) = reply.body().deserialize()?;
+    Ok(start != end)
+}
+
+/// Follows the focused widget and its selection, until the connection
+/// closes. The selection is read when either changes, so the keys do not
+/// wait on the apps.
+fn watch(connection: &Connection) -> zbus::Result<()> {
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(OBJECT_EVENTS)?
+        .build();
+    let messages = MessageIterator::for_match_rule(rule, connection, None)?;
+    // The apps only send the events someone registered for.
+    for event in [
+        "object:state-changed:focused",
+        "object:text-selection-changed",
+    ] {
+        connection.call_method(
+            Some(REGISTRY_NAME),
+            REGISTRY_PATH,
+            Some(REGISTRY_NAME),
+            "RegisterEvent",
+            &(event,),
+        )?;
+    }
+
+    let mut focused: Option<(String, OwnedObjectPath)> = None;
+    for message in messages {
+        let message = message?;
+        let header = message.header();
+        let (Some(sender), Some(path), Some(member)) =
+            (header.sender(), header.path(), header.member())
+        else {
+            continue;
+        };
+        let is_focused = focused.as_ref().is_some_and(|(app, object)| {
+            app == sender.as_str() && object.as_str() == path.as_str()
+        });
+        match (member.as_str(), event_detail(&message)) {
+            ("StateChanged", Some((detail, gained))) if detail == "focused" => {
+                if gained == 1 {
+                    focused = Some((sender.to_string(), path.to_owned().into()));
+                } else if is_focused {
+                    focused = None;
+                }
+            }
+            ("TextSelectionChanged", _) if is_focused => {}
+            _ => continue,
+        }
+        let selected = match &focused {
+            Some((app, object)) => has_selection(connection, app, object).unwrap_or(false),
+            None => false,
+        };
+        HAS_SELECTION.store(selected, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Whether the focused text has a selection, which typing replaces. The
+/// first call starts watching the accessibility bus, so it is only known
+/// from the next focus change on.
+pub fn is_in_text_selection() -> bool {
+    WATCHER.call_once(|| {
+        thread::spawn(|| {
+            if let Err(err) = a11y_bus().and_then(|connection| watch(&connection)) {
+                log::warn!("Cannot watch the text selection with AT-SPI: {err}");
+            }
+        });
+    });
+    HAS_SELECTION.load(Ordering::Relaxed)
+}
+
+/// Plays the registry and an app with a text field on a private bus, then
+/// selects some of its text. Needs `dbus-daemon`.
+#[test]
+fn test_watch_text_selection() {
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use zbus::interface;
+
+    struct Registry(Arc<AtomicUsize>);
+
+    #[interface(name = "org.a11y.atspi.Registry")]
+    impl Registry {
+        fn register_event(&self, _event: &str) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    struct Text(Arc<Mutex<(i32, i32)>>);
+
+    #[interface(name = "org.a11y.atspi.Text")]
+    impl Text {
+        fn get_n_selections(&self) -> i32 {
+            let (start, end) = *self.0.lock().unwrap();
+            (start != end).into()
+        }
+
+        fn get_selection(&self, _selection: i32) -> (i32, i32) {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    let Some((mut daemon, address)) = super::private_bus() else {
+        return;
+    };
+
+    let registered = Arc::new(AtomicUsize::new(0));
+    let selection = Arc::new(Mutex::new((0, 0)));
+    let field_path = "/org/a11y/atspi/accessible/1";
+    let app = connection::Builder::address(address.as_str())
+        .unwrap()
+        .serve_at(REGISTRY_PATH, Registry(registered.clone()))
+        .unwrap()
+        .serve_at(field_path, Text(selection.clone()))
+        .unwrap()
+        .name(REGISTRY_NAME)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let watcher = connection::Builder::address(address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+    thread::spawn(move || watch(&watcher));
+    while registered.load(Ordering::Relaxed) < 2 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let emit = |member, detail: &str, detail1: i32| {
+        let properties = HashMap::<String, Value>::new();
+        let body = (detail, detail1, 0i32, Value::from(0i32), properties);
+        app.emit_signal(None::<()>, field_path, OBJECT_EVENTS, member, &body)
+            .unwrap();
+    };
+    let wait_for = |expected: bool| {
+        for _ in 0..100 {
+            if HAS_SELECTION.load(Ordering::Relaxed) == expected {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the selection never became {expected}");
+    };
+
+    *selection.lock().unwrap() = (2, 5);
+    emit("StateChanged", "focused", 1);
+    wait_for(true);
+    *selection.lock().unwrap() = (5, 5);
+    emit("TextSelectionChanged", "", 0);
+    wait_for(false);
+    *selection.lock().unwrap() = (0, 5);
+    emit("TextSelectionChanged", "", 0);
+    wait_for(true);
+    emit("StateChanged", "focused", 0);
+    wait_for(false);
+    daemon.kill().unwrap();
+}
@@ -0,0 +1,493 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use xkbcommon_dl::{
+    xkb_context, xkb_context_flags, xkb_key_direction, xkb_keymap, xkb_keymap_compile_flags,
+    xkb_keymap_format, xkb_rule_names, xkb_state, xkb_state_component, XkbCommon, XKB_MOD_NAME_ALT,
+    XKB_MOD_NAME_CAPS, XKB_MOD_NAME_CTRL, XKB_MOD_NAME_LOGO, XKB_MOD_NAME_SHIFT,
+};
+
+#[cfg(test)]
+use super::super::KEY_ENTER;
+use super::super::{KeyModifier, PressedKey, KEY_DELETE};
+use super::linux_x11::is_keypad_keysym;
+use crate::layout::{KeyboardLayout, Level};
+
+/// The word being typed, shown as preedit text until it is done.
+///
+/// The engine edits the word with backspaces and strings as if the app
+/// already had it. Those edits are applied to the preedit instead, so the
+/// app only gets the word once, when a key that is not part of it is typed.
+#[derive(Default)]
+pub struct Composer {
+    preedit: RefCell<String>,
+    /// Text that is done, to commit to the app.
+    commit: RefCell<String>,
+    /// Characters before the preedit to erase, which only backspaces on
+    /// the virtual keyboard can reach.
+    backspaces: Cell<usize>,
+}
+
+/// What to send to the app after a key was handled.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Edits {
+    pub backspaces: usize,
+    pub commit: String,
+    pub preedit: String,
+}
+
+impl Composer {
+    pub fn queue_backspace(&self, count: usize) {
+        let mut preedit = self.preedit.borrow_mut();
+        for _ in 0..count {
+            if preedit.pop().is_none() {
+                self.backspaces.set(self.backspaces.get() + 1);
+            }
+        }
+    }
+
+    pub fn queue_string(&self, string: &str) {
+        self.preedit.borrow_mut().push_str(string);
+    }
+
+    /// Applies a key the engine let through to the word: `typed` is the
+    /// character it types. Returns whether the app still has to get the
+    /// key, as it is not part of a word.
+    pub fn type_key(&self, key: PressedKey, typed: Option<char>, modifiers: KeyModifier) -> bool {
+        let is_typing = !modifiers.intersects(
+            KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_SUPER | KeyModifier::MODIFIER_ALT,
+        );
+        match (key, typed) {
+            (PressedKey::Char(KEY_DELETE), _) if self.pop() => false,
+            (_, Some(c)) if is_typing && c.is_alphanumeric() => {
+                self.push(c);
+                false
+            }
+            (_, Some(c)) if is_typing && !c.is_control() => {
+                self.finish(&c.to_string());
+                false
+            }
+            _ => {
+                self.finish("");
+                true
+            }
+        }
+    }
+
+    fn push(&self, c: char) {
+        self.preedit.borrow_mut().push(c);
+    }
+
+    /// Erases the last character of the word, if it has any left.
+    fn pop(&self) -> bool {
+        self.preedit.borrow_mut().pop().is_some()
+    }
+
+    /// Commits the word, followed by `suffix`.
+    pub fn finish(&self, suffix: &str) {
+        let word = self.preedit.take();
+        let mut commit = self.commit.borrow_mut();
+        commit.push_str(&word);
+        commit.push_str(suffix);
+    }
+
+    /// Drops the word, when the text field it was typed in lost the focus.
+    pub fn clear(&self) {
+        self.preedit.take();
+        self.commit.take();
+        self.backspaces.set(0);
+    }
+
+    pub fn take_edits(&self) -> Edits {
+        Edits {
+            backspaces: self.backspaces.replace(0),
+            commit: self.commit.take(),
+            preedit: self.preedit.borrow().clone(),
+        }
+    }
+}
+
+/// An xkb keymap, to read the keys with.
+pub struct Keymap {
+    xkb: &'static XkbCommon,
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+    state: *mut xkb_state,
+}
+
+/// The names of the xkb settings a keymap is compiled from, like the
+/// system configures them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct KeymapNames {
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: String,
+}
+
+impl Keymap {
+    /// Compiles a keymap in the text format, like the ones compositors send.
+    pub fn from_text(xkb: &'static XkbCommon, text: &[u8]) -> Option<Self> {
+        let text = text.strip_suffix(&[0]).unwrap_or(text);
+        Self::new(xkb, |context| unsafe {
+            (xkb.xkb_keymap_new_from_buffer)(
+                context,
+                text.as_ptr().cast(),
+                text.len(),
+                xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            )
+        })
+    }
+
+    /// The keymap of the system defaults, or of the `XKB_DEFAULT_*`
+    /// environment variables.
+    pub fn from_defaults(xkb: &'static XkbCommon) -> Option<Self> {
+        Self::new(xkb, |context| unsafe {
+            (xkb.xkb_keymap_new_from_names)(
+                context,
+                ptr::null(),
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            )
+        })
+    }
+
+    /// The keymap of an xkb layout and variant, like `setxkbmap` sets.
+    pub fn from_names(xkb: &'static XkbCommon, layout: &str, variant: &str) -> Option<Self> {
+        let names = KeymapNames {
+            layout: layout.to_string(),
+            variant: variant.to_string(),
+            ..KeymapNames::default()
+        };
+        Self::from_rule_names(xkb, &names)
+    }
+
+    /// The keymap of xkb names, the empty ones take their default.
+    pub fn from_rule_names(xkb: &'static XkbCommon, names: &KeymapNames) -> Option<Self> {
+        let model = CString::new(names.model.as_str()).ok()?;
+        let layout = CString::new(names.layout.as_str()).ok()?;
+        let variant = CString::new(names.variant.as_str()).ok()?;
+        let options = CString::new(names.options.as_str()).ok()?;
+        let names = xkb_rule_names {
+            rules: ptr::null(),
+            model: model.as_ptr(),
+            layout: layout.as_ptr(),
+            variant: variant.as_ptr(),
+            options: options.as_ptr(),
+        };
+        Self::new(xkb, |context| unsafe {
+            (xkb.xkb_keymap_new_from_names)(
+                context,
+                &names,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            )
+        })
+    }
+
+    fn new(
+        xkb: &'static XkbCommon,
+        compile: impl FnOnce(*mut xkb_context) -> *mut xkb_keymap,
+    ) -> Option<Self> {
+        unsafe {
+            let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return None;
+            }
+            let keymap = compile(context);
+            if keymap.is_null() {
+                (xkb.xkb_context_unref)(context);
+                return None;
+            }
+            let state = (xkb.xkb_state_new)(keymap);
+            Some(Self {
+                xkb,
+                context,
+                keymap,
+                state,
+            })
+        }
+    }
+
+    /// The keysym of a key without modifiers, like the X11 backend reads.
+    pub fn base_keysym(&self, keycode: u32) -> u32 {
+        unsafe {
+            let layout = (self.xkb.xkb_state_key_get_layout)(self.state, keycode);
+            let mut keysyms = std::ptr::null();
+            let count = (self.xkb.xkb_keymap_key_get_syms_by_level)(
+                self.keymap,
+                keycode,
+                layout,
+                0,
+                &mut keysyms,
+            );
+            if count > 0 {
+                *keysyms
+            } else {
+                0
+            }
+        }
+    }
+
+    /// The keysym the engine reads a key with: the one without modifiers,
+    /// but for the keypad, whose digits need Num Lock on.
+    pub fn pressed_keysym(&self, keycode: u32) -> u32 {
+        let keysym = self.base_keysym(keycode);
+        if is_keypad_keysym(keysym.into()) {
+            unsafe { (self.xkb.xkb_state_key_get_one_sym)(self.state, keycode) }
+        } else {
+            keysym
+        }
+    }
+
+    /// The character a key types with the current modifiers.
+    pub fn key_char(&self, keycode: u32) -> Option<char> {
+        let code = unsafe { (self.xkb.xkb_state_key_get_utf32)(self.state, keycode) };
+        char::from_u32(code).filter(|&c| c != '\0')
+    }
+
+    /// The key and the shift level that type `c` in the first layout.
+    pub fn key_for_char(&self, c: char) -> Option<(u32, u32)> {
+        unsafe {
+            let min = (self.xkb.xkb_keymap_min_keycode)(self.keymap);
+            let max = (self.xkb.xkb_keymap_max_keycode)(self.keymap);
+            (min..=max).find_map(|keycode| {
+                (0..2).find_map(|level| {
+                    let types_c = self.level_char(keycode, 0, level) == Some(c);
+                    types_c.then_some((keycode, level))
+                })
+            })
+        }
+    }
+
+    /// The character a key types at a level of a layout, if it types one.
+    fn level_char(&self, keycode: u32, layout: u32, level: u32) -> Option<char> {
+        unsafe {
+            let mut keysyms = ptr::null();
+            let count = (self.xkb.xkb_keymap_key_get_syms_by_level)(
+                self.keymap,
+                keycode,
+                layout,
+                level,
+                &mut keysyms,
+            );
+            if count != 1 {
+                return None;
+            }
+            char::from_u32((self.xkb.xkb_keysym_to_utf32)(*keysyms))
+                .filter(|c| *c != '\0' && !c.is_control())
+        }
+    }
+
+    /// What the keys type at the first four levels of the active layout,
+    /// which keymaps order as shift, then AltGr.
+    pub fn layout(&self) -> KeyboardLayout {
+        let mut layout = KeyboardLayout::new(self.layout_name().unwrap_or_default());
+        unsafe {
+            let min = (self.xkb.xkb_keymap_min_keycode)(self.keymap);
+            let max = (self.xkb.xkb_keymap_max_keycode)(self.keymap);
+            for keycode in min..=max {
+                let group = (self.xkb.xkb_state_key_get_layout)(self.state, keycode);
+                for (index, level) in Level::ALL.into_iter().enumerate() {
+                    if let Some(c) = self.level_char(keycode, group, index as u32) {
+                        layout.set_key_char(keycode, level, c);
+                    }
+                }
+            }
+        }
+        layout
+    }
+
+    /// The name of the active layout, like "English (US)", which only the
+    /// text of the keymap has.
+    fn layout_name(&self) -> Option<String> {
+        let (group, text) = unsafe {
+            let group = (self.xkb.xkb_state_serialize_layout)(
+                self.state,
+                xkb_state_component::XKB_STATE_LAYOUT_EFFECTIVE,
+            );
+            let text = (self.xkb.xkb_keymap_get_as_string)(
+                self.keymap,
+                xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+            );
+            if text.is_null() {
+                return None;
+            }
+            let owned = CStr::from_ptr(text).to_string_lossy().into_owned();
+            libc::free(text as *mut _);
+            (group, owned)
+        };
+        // Lowercasing ASCII keeps the byte offsets.
+        let key = format!("name[group{}]=\"", group + 1);
+        let start = text.to_ascii_lowercase().find(&key)? + key.len();
+        let end = start + text[start..].find('"')?;
+        Some(text[start..end].to_string())
+    }
+
+    /// Follows a key the keymap does not get from a compositor, for the
+    /// modifiers it sets.
+    pub fn update_key(&self, keycode: u32, is_press: bool) {
+        let direction = if is_press {
+            xkb_key_direction::XKB_KEY_DOWN
+        } else {
+            xkb_key_direction::XKB_KEY_UP
+        };
+        unsafe {
+            (self.xkb.xkb_state_update_key)(self.state, keycode, direction);
+        }
+    }
+
+    pub fn update_mask(&self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        unsafe {
+            (self.xkb.xkb_state_update_mask)(self.state, depressed, latched, locked, 0, 0, group);
+        }
+    }
+
+    pub fn modifiers(&self) -> KeyModifier {
+        let is_active = |name: &[u8]| unsafe {
+            (self.xkb.xkb_state_mod_name_is_active)(
+                self.state,
+                name.as_ptr().cast(),
+                xkb_state_component::XKB_STATE_MODS_EFFECTIVE,
+            ) > 0
+        };
+        let mut modifiers = KeyModifier::new();
+        modifiers.apply(
+            is_active(XKB_MOD_NAME_LOGO),
+            is_active(XKB_MOD_NAME_CTRL),
+            is_active(XKB_MOD_NAME_ALT),
+            is_active(XKB_MOD_NAME_SHIFT),
+            is_active(XKB_MOD_NAME_CAPS),
+        );
+        modifiers
+    }
+}
+
+impl Drop for Keymap {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xkb.xkb_state_unref)(self.state);
+            (self.xkb.xkb_keymap_unref)(self.keymap);
+            (self.xkb.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+#[test]
+fn test_composer_edits_the_word() {
+    let composer = Composer::default();
+    for c in "vieet".chars() {
+        assert!(!composer.type_key(PressedKey::Char(c), Some(c), KeyModifier::new()));
+    }
+    // The engine replacing `vieet` by `việt` only changes the preedit.
+    composer.queue_backspace(3);
+    composer.queue_string("ệt");
+    assert_eq!(
+        composer.take_edits(),
+        Edits {
+            backspaces: 0,
+            commit: String::new(),
+            preedit: "việt".to_string(),
+        }
+    );
+    let backspace = PressedKey::Char(KEY_DELETE);
+    assert!(!composer.type_key(backspace, None, KeyModifier::new()));
+    assert!(!composer.type_key(PressedKey::Char(' '), Some(' '), KeyModifier::new()));
+    assert_eq!(
+        composer.take_edits(),
+        Edits {
+            backspaces: 0,
+            commit: "việ ".to_string(),
+            preedit: String::new(),
+        }
+    );
+}
+
+#[test]
+fn test_composer_erases_committed_text() {
+    let composer = Composer::default();
+    composer.push('a');
+    // Going back into a word that was already committed.
+    composer.queue_backspace(3);
+    composer.queue_string("đây");
+    assert_eq!(
+        composer.take_edits(),
+        Edits {
+            backspaces: 2,
+            commit: String::new(),
+            preedit: "đây".to_string(),
+        }
+    );
+    composer.clear();
+    assert!(!composer.pop());
+    assert_eq!(composer.take_edits().preedit, "");
+}
+
+#[test]
+fn test_composer_forwards_other_keys() {
+    let composer = Composer::default();
+    composer.queue_string("đ");
+    let mut ctrl = KeyModifier::new();
+    ctrl.add_control();
+    assert!(composer.type_key(PressedKey::Char('c'), Some('c'), ctrl));
+    assert_eq!(composer.take_edits().commit, "đ");
+    let enter = PressedKey::Char(KEY_ENTER);
+    assert!(composer.type_key(enter, Some('\r'), KeyModifier::new()));
+    // Backspace only reaches the app once the word is empty.
+    let backspace = PressedKey::Char(KEY_DELETE);
+    assert!(composer.type_key(backspace, None, KeyModifier::new()));
+}
+
+/// Needs libxkbcommon and the xkb data files.
+#[test]
+fn test_keymap_from_defaults() {
+    let Some(xkb) = xkbcommon_dl::xkbcommon_option() else {
+        return;
+    };
+    let keymap = Keymap::from_defaults(xkb).unwrap();
+    // The A key of the default US layout, in X keycodes.
+    assert_eq!(keymap.base_keysym(38), 'a' as u32);
+    keymap.update_mask(1, 0, 0, 0);
+    assert_eq!(keymap.key_char(38), Some('A'));
+    assert!(keymap.modifiers().is_shift());
+    assert_eq!(keymap.key_for_char('A'), Some((38, 1)));
+    assert_eq!(keymap.key_for_char('á'), None);
+
+    // The 7 of the keypad moves the cursor home until Num Lock, which is
+    // the Mod2 modifier, is on.
+    keymap.update_mask(0, 0, 0, 0);
+    assert_eq!(keymap.pressed_keysym(79), x11::keysym::XK_KP_Home);
+    keymap.update_mask(0, 0, 1 << 4, 0);
+    assert_eq!(keymap.pressed_keysym(79), x11::keysym::XK_KP_7);
+}
+
+/// Needs libxkbcommon and the xkb data files.
+#[test]
+fn test_keymap_layout() {
+    let Some(xkb) = xkbcommon_dl::xkbcommon_option() else {
+        return;
+    };
+    // The keys at the Q, A and E positions of a US keyboard, in X keycodes.
+    let dvorak = Keymap::from_names(xkb, "us", "dvorak").unwrap().layout();
+    assert_eq!(dvorak.name(), "English (Dvorak)");
+    assert_eq!(dvorak.key_char(24, Level::Base), Some('\''));
+    assert_eq!(dvorak.key_char(38, Level::Shift), Some('A'));
+    assert_eq!(dvorak.key_char(26, Level::Base), Some('.'));
+
+    let azerty = Keymap::from_names(xkb, "fr", "").unwrap().layout();
+    assert_eq!(azerty.name(), "French");
+    assert_eq!(azerty.key_char(24, Level::Base), Some('a'));
+    assert_eq!(azerty.key_char(26, Level::AltGr), Some('€'));
+    assert_eq!(
+        azerty.resolve_char('é', KeyModifier::MODIFIER_SHIFT),
+        Some('2')
+    );
+
+    let qwertz = Keymap::from_names(xkb, "de", "").unwrap().layout();
+    assert_eq!(qwertz.key_char(29, Level::Base), Some('z'));
+    assert_eq!(qwertz.key_char(20, Level::Base), Some('ß'));
+    assert_eq!(
+        qwertz.resolve_char('ü', KeyModifier::MODIFIER_CAPSLOCK),
+        Some('Ü')
+    );
+}
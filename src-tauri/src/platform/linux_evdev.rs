@@ -0,0 +1,608 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, Device, EventType, InputEvent, KeyCode};
+
+use super::super::{CallbackFn, EventTapType, KeyEvent, KeyModifier, ModifierKeys};
+use super::linux_composer::{Keymap, KeymapNames};
+use super::linux_x11::{keysym_to_modifier, keysym_to_modifier_key, keysym_to_pressed_key};
+use super::Handle;
+
+const VIRTUAL_KEYBOARD_NAME: &str = "goxkey virtual keyboard";
+
+/// The values of key events.
+const RELEASED: i32 = 0;
+const PRESSED: i32 = 1;
+const REPEATED: i32 = 2;
+
+/// Keymaps number the keys from the evdev codes plus 8.
+const XKB_KEYCODE_OFFSET: u32 = 8;
+
+/// The files the keymap of the system is set in, by localed and by the
+/// `keyboard-configuration` package of Debian.
+const KEYBOARD_FILES: [&str; 2] = ["/etc/vconsole.conf", "/etc/default/keyboard"];
+
+/// The environment variables that override the keymap of the system.
+const XKB_DEFAULT_VARS: [&str; 5] = [
+    "XKB_DEFAULT_RULES",
+    "XKB_DEFAULT_MODEL",
+    "XKB_DEFAULT_LAYOUT",
+    "XKB_DEFAULT_VARIANT",
+    "XKB_DEFAULT_OPTIONS",
+];
+
+/// Letters every Vietnamese word is typed with some of.
+const VIETNAMESE_LETTERS: &str = "ăâđêôơưáàảãạ";
+
+/// An edit to type into the focused app.
+enum Injection {
+    Backspace(usize),
+    Text(String),
+}
+
+fn key_event(key: KeyCode, value: i32) -> InputEvent {
+    InputEvent::new(EventType::KEY.0, key.code(), value)
+}
+
+fn tap(events: &mut Vec<InputEvent>, key: KeyCode, shift: bool) {
+    if shift {
+        events.push(key_event(KeyCode::KEY_LEFTSHIFT, PRESSED));
+    }
+    events.push(key_event(key, PRESSED));
+    events.push(key_event(key, RELEASED));
+    if shift {
+        events.push(key_event(KeyCode::KEY_LEFTSHIFT, RELEASED));
+    }
+}
+
+/// Taps the key of `c` in the keymap, if it has one.
+fn tap_char(events: &mut Vec<InputEvent>, keymap: &Keymap, c: char) -> bool {
+    let Some((keycode, level)) = keymap.key_for_char(c) else {
+        return false;
+    };
+    let key = KeyCode::new((keycode - XKB_KEYCODE_OFFSET) as u16);
+    tap(events, key, level == 1);
+    true
+}
+
+/// The key events that type the edits, or the first character the keymap
+/// has no key for.
+fn edit_events(keymap: &Keymap, injections: &[Injection]) -> Result<Vec<InputEvent>, char> {
+    let mut events = Vec::new();
+    for injection in injections {
+        match injection {
+            Injection::Backspace(count) => {
+                for _ in 0..*count {
+                    tap(&mut events, KeyCode::KEY_BACKSPACE, false);
+                }
+            }
+            Injection::Text(text) => {
+                for c in text.chars() {
+                    if !tap_char(&mut events, keymap, c) {
+                        return Err(c);
+                    }
+                }
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// Types the edits of the engine on a uinput keyboard, which also sends
+/// the apps the keys of the grabbed keyboards the engine let through.
+pub struct Injector {
+    device: RefCell<VirtualDevice>,
+    keymap: Keymap,
+    pending: RefCell<Vec<Injection>>,
+}
+
+impl Injector {
+    pub fn queue_backspace(&self, count: usize) {
+        self.pending.borrow_mut().push(Injection::Backspace(count));
+    }
+
+    pub fn queue_string(&self, string: &str) {
+        self.pending
+            .borrow_mut()
+            .push(Injection::Text(string.to_string()));
+    }
+
+    fn emit(&self, event: InputEvent) {
+        // A frame of its own for every event, or a press and release of
+        // the same key could be merged.
+        if let Err(err) = self.device.borrow_mut().emit(&[event]) {
+            log::error!("Cannot type on the virtual keyboard: {err}");
+        }
+    }
+
+    /// Types the queued edits, unless the keymap cannot type all of them.
+    /// The shift keys the user holds are released meanwhile, or they would
+    /// change the case of the text.
+    fn flush(&self, held_shifts: &[KeyCode]) -> bool {
+        let pending = self.pending.take();
+        if pending.is_empty() {
+            return true;
+        }
+        let events = match edit_events(&self.keymap, &pending) {
+            Ok(events) => events,
+            Err(c) => {
+                log::error!("Cannot type `{c}`, the keymap has no key for it");
+                return false;
+            }
+        };
+        for &key in held_shifts {
+            self.emit(key_event(key, RELEASED));
+        }
+        for event in events {
+            self.emit(event);
+        }
+        for &key in held_shifts {
+            self.emit(key_event(key, PRESSED));
+        }
+        true
+    }
+}
+
+/// The events of the devices, with the time they happened at in
+/// milliseconds.
+enum DeviceEvent {
+    Key(KeyCode, i32, u64),
+    Click(u64),
+}
+
+struct Listener<'a> {
+    callback: &'a CallbackFn,
+    injector: Injector,
+    modifiers: KeyModifier,
+    modifier_keys: ModifierKeys,
+    /// The keys the apps got the press of, whose release has to follow.
+    forwarded_keys: HashSet<KeyCode>,
+}
+
+impl Listener<'_> {
+    /// Calls back with `event`, at the modifiers held.
+    fn call(&self, event: KeyEvent) -> bool {
+        let handle = Handle::Evdev(&self.injector);
+        let event = KeyEvent {
+            modifiers: self.modifiers,
+            modifier_keys: self.modifier_keys,
+            ..event
+        };
+        (self.callback)(handle, &event)
+    }
+
+    /// Types the queued edits. When the keymap cannot, the app never gets
+    /// the word the engine has, so the engine starts a new one.
+    fn flush(&self) -> bool {
+        let held_shifts: Vec<KeyCode> = [KeyCode::KEY_LEFTSHIFT, KeyCode::KEY_RIGHTSHIFT]
+            .into_iter()
+            .filter(|key| self.forwarded_keys.contains(key))
+            .collect();
+        if self.injector.flush(&held_shifts) {
+            return true;
+        }
+        self.call(KeyEvent::new(EventTapType::Other, None, KeyModifier::new()));
+        self.injector.pending.take();
+        false
+    }
+
+    fn forward(&mut self, key: KeyCode, value: i32) {
+        let is_forwarded = match value {
+            PRESSED => {
+                self.forwarded_keys.insert(key);
+                true
+            }
+            RELEASED => self.forwarded_keys.remove(&key),
+            _ => self.forwarded_keys.contains(&key),
+        };
+        if is_forwarded {
+            self.injector.emit(key_event(key, value));
+        }
+    }
+
+    fn on_key(&mut self, key: KeyCode, value: i32, timestamp: u64) {
+        let keycode = key.code() as u32 + XKB_KEYCODE_OFFSET;
+        let keymap = &self.injector.keymap;
+        if value != REPEATED {
+            keymap.update_key(keycode, value == PRESSED);
+        }
+        let key_event = |event_type, pressed_key| KeyEvent {
+            keycode: Some(keycode),
+            is_repeat: value == REPEATED,
+            timestamp,
+            ..KeyEvent::new(event_type, pressed_key, KeyModifier::new())
+        };
+        let keysym = keymap.pressed_keysym(keycode);
+        if keysym_to_modifier(keysym.into()).is_some() {
+            let modifiers = keymap.modifiers();
+            let mut modifier_keys = self.modifier_keys;
+            if let Some(modifier_key) = keysym_to_modifier_key(keysym.into()) {
+                if value != REPEATED {
+                    modifier_keys.set(modifier_key, value == PRESSED);
+                }
+            }
+            if modifiers != self.modifiers || modifier_keys != self.modifier_keys {
+                self.modifiers = modifiers;
+                self.modifier_keys = modifier_keys;
+                self.call(key_event(EventTapType::FlagsChanged, None));
+                self.flush();
+            }
+            self.forward(key, value);
+            return;
+        }
+        let pressed_key = keysym_to_pressed_key(keysym.into(), keycode);
+        if value == RELEASED {
+            self.call(key_event(EventTapType::KeyUp, Some(pressed_key)));
+            self.flush();
+            self.forward(key, value);
+            return;
+        }
+        let blocked = self.call(key_event(EventTapType::KeyDown, Some(pressed_key)));
+        // The key is typed as is when its edit cannot be, not lost.
+        let is_typed = self.flush();
+        if !is_typed || !blocked {
+            self.forward(key, value);
+        }
+    }
+
+    fn on_click(&self, timestamp: u64) {
+        self.call(KeyEvent {
+            timestamp,
+            ..KeyEvent::new(EventTapType::Other, None, KeyModifier::new())
+        });
+        self.flush();
+    }
+}
+
+fn create_virtual_keyboard() -> Result<VirtualDevice, String> {
+    // Every key but the buttons of mice and joysticks.
+    let mut keys = AttributeSet::<KeyCode>::new();
+    for code in (1..0x100).chain(0x160..0x2c0) {
+        keys.insert(KeyCode::new(code));
+    }
+    VirtualDevice::builder()
+        .and_then(|builder| builder.name(VIRTUAL_KEYBOARD_NAME).with_keys(&keys))
+        .and_then(|builder| builder.build())
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::PermissionDenied => "cannot write to /dev/uinput: give the `input` \
+                group access to it with a udev rule such as \
+                `KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\"`"
+                .to_string(),
+            io::ErrorKind::NotFound => {
+                "cannot find /dev/uinput: load the uinput module with `modprobe uinput`".to_string()
+            }
+            _ => format!("cannot create the virtual keyboard: {err}"),
+        })
+}
+
+fn is_keyboard(device: &Device) -> bool {
+    let has_keys = device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(KeyCode::KEY_A) && keys.contains(KeyCode::KEY_ENTER));
+    // Mice with extra keys move the pointer too, which grabbing would stop.
+    has_keys
+        && device.supported_relative_axes().is_none()
+        && device.name() != Some(VIRTUAL_KEYBOARD_NAME)
+}
+
+fn is_pointer(device: &Device) -> bool {
+    device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(KeyCode::BTN_LEFT))
+}
+
+/// The keyboards and the pointers of /dev/input.
+fn open_devices() -> Result<(Vec<Device>, Vec<Device>), String> {
+    let entries =
+        fs::read_dir("/dev/input").map_err(|err| format!("cannot list /dev/input: {err}"))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("event"))
+        })
+        .collect();
+    paths.sort();
+    let (mut keyboards, mut pointers, mut denied) = (Vec::new(), Vec::new(), Vec::new());
+    for path in paths {
+        match Device::open(&path) {
+            Ok(device) if is_keyboard(&device) => keyboards.push(device),
+            Ok(device) if is_pointer(&device) => pointers.push(device),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => denied.push(path),
+            Err(err) => log::debug!("Cannot open {}: {err}", path.display()),
+        }
+    }
+    if keyboards.is_empty() {
+        return Err(match denied.first() {
+            Some(path) => format!(
+                "cannot read {}: add the user to the `input` group, then log in again",
+                path.display()
+            ),
+            None => "found no keyboard in /dev/input".to_string(),
+        });
+    }
+    Ok((keyboards, pointers))
+}
+
+/// Grabs the keyboard so only goxkey gets its keys, once the keys held
+/// at start are released: the apps would never get their release.
+fn grab(device: &mut Device) -> io::Result<()> {
+    while device.get_key_state()?.iter().next().is_some() {
+        thread::sleep(Duration::from_millis(50));
+    }
+    device.grab()
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn read_events(mut device: Device, is_keyboard: bool, sender: mpsc::Sender<DeviceEvent>) {
+    let name = device.name().unwrap_or("a device").to_string();
+    thread::spawn(move || loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events,
+            Err(err) => {
+                log::warn!("Stopped reading {name}: {err}");
+                return;
+            }
+        };
+        for event in events {
+            if event.event_type() != EventType::KEY {
+                continue;
+            }
+            let timestamp = millis(event.timestamp());
+            let event = match (is_keyboard, event.value()) {
+                (true, value) => DeviceEvent::Key(KeyCode::new(event.code()), value, timestamp),
+                (false, PRESSED) => DeviceEvent::Click(timestamp),
+                (false, _) => continue,
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Reads the keyboards of /dev/input, for sessions that are neither X11
+/// nor have a Wayland compositor with input methods, like the console.
+/// Blocks until every keyboard is gone.
+///
+/// The keyboards are grabbed, and the keys the engine does not block are
+/// typed again on a uinput keyboard. The keys are read with the keymap of
+/// the system, see [`system_keymap`].
+pub fn run_event_listener(callback: &CallbackFn) -> Result<(), String> {
+    let xkb = xkbcommon_dl::xkbcommon_option().ok_or("cannot load libxkbcommon")?;
+    let keymap = system_keymap(xkb)?;
+    let missing: String = VIETNAMESE_LETTERS
+        .chars()
+        .filter(|&c| keymap.key_for_char(c).is_none())
+        .collect();
+    if !missing.is_empty() {
+        log::warn!(
+            "The keymap has no keys for {missing}, words with them cannot be typed on the \
+             console; set a keymap that has them, or use IBus, Fcitx5 or a Wayland compositor"
+        );
+    }
+    let device = create_virtual_keyboard()?;
+    let (keyboards, pointers) = open_devices()?;
+    let (sender, receiver) = mpsc::channel();
+    let mut grabbed = 0;
+    for mut keyboard in keyboards {
+        if let Err(err) = grab(&mut keyboard) {
+            let name = keyboard.name().unwrap_or("a keyboard");
+            log::warn!("Cannot grab {name}, another program may have: {err}");
+            continue;
+        }
+        read_events(keyboard, true, sender.clone());
+        grabbed += 1;
+    }
+    if grabbed == 0 {
+        return Err("cannot grab any keyboard".to_string());
+    }
+    for pointer in pointers {
+        read_events(pointer, false, sender.clone());
+    }
+    drop(sender);
+
+    let mut listener = Listener {
+        callback,
+        injector: Injector {
+            device: RefCell::new(device),
+            keymap,
+            pending: RefCell::new(Vec::new()),
+        },
+        modifiers: KeyModifier::new(),
+        modifier_keys: ModifierKeys::empty(),
+        forwarded_keys: HashSet::new(),
+    };
+    for event in receiver {
+        match event {
+            DeviceEvent::Key(key, value, timestamp) => listener.on_key(key, value, timestamp),
+            DeviceEvent::Click(timestamp) => listener.on_click(timestamp),
+        }
+    }
+    Ok(())
+}
+
+/// The keymap of the `XKB_DEFAULT_*` environment variables when one is set,
+/// or else the one localed sets, read from it or from the files it and
+/// Debian write it to. Falls back to the xkb defaults.
+fn system_keymap(xkb: &'static xkbcommon_dl::XkbCommon) -> Result<Keymap, String> {
+    let is_overridden = XKB_DEFAULT_VARS
+        .iter()
+        .any(|name| env::var_os(name).is_some());
+    let names = if is_overridden {
+        None
+    } else {
+        localed_keymap_names().or_else(|| {
+            KEYBOARD_FILES
+                .iter()
+                .filter_map(|path| fs::read_to_string(path).ok())
+                .find_map(|contents| parse_keyboard_file(&contents))
+        })
+    };
+    if let Some(names) = names {
+        match Keymap::from_rule_names(xkb, &names) {
+            Some(keymap) => return Ok(keymap),
+            None => log::warn!("Cannot compile the keymap of the system {names:?}"),
+        }
+    }
+    Keymap::from_defaults(xkb).ok_or_else(|| "cannot compile the xkb keymap".to_string())
+}
+
+/// The keymap localed holds, if it runs.
+fn localed_keymap_names() -> Option<KeymapNames> {
+    let connection = zbus::blocking::Connection::system().ok()?;
+    let locale = zbus::blocking::Proxy::new(
+        &connection,
+        "org.freedesktop.locale1",
+        "/org/freedesktop/locale1",
+        "org.freedesktop.locale1",
+    )
+    .ok()?;
+    let property = |name: &str| locale.get_property::<String>(name).ok();
+    let names = KeymapNames {
+        model: property("X11Model")?,
+        layout: property("X11Layout")?,
+        variant: property("X11Variant")?,
+        options: property("X11Options")?,
+    };
+    (!names.layout.is_empty()).then_some(names)
+}
+
+/// The keymap of the `XKB*` variables of a shell style file, like
+/// `/etc/vconsole.conf`, if it sets a layout.
+fn parse_keyboard_file(contents: &str) -> Option<KeymapNames> {
+    let mut names = KeymapNames::default();
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let value = value
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'')
+            .to_string();
+        match key.trim() {
+            "XKBMODEL" => names.model = value,
+            "XKBLAYOUT" => names.layout = value,
+            "XKBVARIANT" => names.variant = value,
+            "XKBOPTIONS" => names.options = value,
+            _ => {}
+        }
+    }
+    (!names.layout.is_empty()).then_some(names)
+}
+
+#[test]
+fn test_parse_keyboard_file() {
+    let contents = "# KEYBOARD CONFIGURATION FILE\nXKBMODEL=\"pc105\"\nXKBLAYOUT=\"us,vn\"\n\
+                    XKBVARIANT=\"\"\nXKBOPTIONS='grp:alt_shift_toggle'\nBACKSPACE=\"guess\"\n";
+    assert_eq!(
+        parse_keyboard_file(contents),
+        Some(KeymapNames {
+            model: "pc105".to_string(),
+            layout: "us,vn".to_string(),
+            variant: String::new(),
+            options: "grp:alt_shift_toggle".to_string(),
+        })
+    );
+    assert_eq!(parse_keyboard_file("KEYMAP=us\nFONT=eurlatgr\n"), None);
+}
+
+/// Types `s`, which the callback answers with `á`, a character the US
+/// keymap has no key for. Needs write access to /dev/uinput.
+#[test]
+fn test_untyped_edit_ends_the_word() {
+    use std::rc::Rc;
+
+    let Some(xkb) = xkbcommon_dl::xkbcommon_option() else {
+        return;
+    };
+    let Ok(device) = create_virtual_keyboard() else {
+        return;
+    };
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let callback = {
+        let events = events.clone();
+        move |handle: Handle, event: &KeyEvent| {
+            events.borrow_mut().push(event.event_type);
+            if event.event_type != EventTapType::KeyDown {
+                return false;
+            }
+            super::send_string(handle, "á").unwrap();
+            true
+        }
+    };
+    let mut listener = Listener {
+        callback: &callback,
+        injector: Injector {
+            device: RefCell::new(device),
+            keymap: Keymap::from_names(xkb, "us", "").unwrap(),
+            pending: RefCell::new(Vec::new()),
+        },
+        modifiers: KeyModifier::new(),
+        modifier_keys: ModifierKeys::empty(),
+        forwarded_keys: HashSet::new(),
+    };
+    listener.on_key(KeyCode::KEY_S, PRESSED, 0);
+    // The app gets the key instead, and the engine a new word.
+    assert!(listener.forwarded_keys.contains(&KeyCode::KEY_S));
+    assert_eq!(
+        *events.borrow(),
+        [EventTapType::KeyDown, EventTapType::Other]
+    );
+    listener.on_key(KeyCode::KEY_S, RELEASED, 0);
+    assert!(listener.forwarded_keys.is_empty());
+}
+
+#[test]
+fn test_edit_events() {
+    let Some(xkb) = xkbcommon_dl::xkbcommon_option() else {
+        return;
+    };
+    let keymap = Keymap::from_defaults(xkb).unwrap();
+    let taps = |injections: &[Injection]| -> Vec<(u16, i32)> {
+        edit_events(&keymap, injections)
+            .unwrap()
+            .iter()
+            .map(|event| (event.code(), event.value()))
+            .collect()
+    };
+    let (a, backspace, shift) = (
+        KeyCode::KEY_A.code(),
+        KeyCode::KEY_BACKSPACE.code(),
+        KeyCode::KEY_LEFTSHIFT.code(),
+    );
+    assert_eq!(
+        taps(&[Injection::Backspace(1), Injection::Text("aA".to_string())]),
+        [
+            (backspace, PRESSED),
+            (backspace, RELEASED),
+            (a, PRESSED),
+            (a, RELEASED),
+            (shift, PRESSED),
+            (a, PRESSED),
+            (a, RELEASED),
+            (shift, RELEASED),
+        ]
+    );
+    // The US keymap has no key for `á`, nothing is typed.
+    assert_eq!(
+        edit_events(&keymap, &[Injection::Text("aá".to_string())]).err(),
+        Some('á')
+    );
+}
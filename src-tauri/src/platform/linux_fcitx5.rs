@@ -0,0 +1,148 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+
+use zbus::blocking::connection;
+use zbus::interface;
+
+use super::super::CallbackFn;
+use super::linux_ibus::{request, run_engine, Event, Request};
+use super::watch_bus_name;
+
+const BUS_NAME: &str = "com.goxgox.app.Fcitx5";
+const FCITX5_BUS_NAME: &str = "org.fcitx.Fcitx5";
+const OBJECT_PATH: &str = "/com/goxgox/app/Fcitx5";
+
+/// The edits of a key, flattened for the addon to read: the characters
+/// before the preedit to erase, the text to commit and the new preedit.
+type Edits = (u32, String, String);
+
+/// The engine the goxkey addon of Fcitx5 calls. It sends the keys the
+/// same way IBus does, and applies the edits itself: the preedit, and the
+/// erased characters through the surrounding text when the app has it.
+struct Fcitx5Engine {
+    requests: mpsc::Sender<Request>,
+}
+
+impl Fcitx5Engine {
+    async fn edits(&self, event: Event) -> (bool, Edits) {
+        match request(&self.requests, event).await {
+            Some(reply) => {
+                let edits = reply.edits;
+                let backspaces = edits.backspaces as u32;
+                (reply.handled, (backspaces, edits.commit, edits.preedit))
+            }
+            None => (false, (0, String::new(), String::new())),
+        }
+    }
+}
+
+#[interface(name = "com.goxgox.app.Fcitx5.Engine")]
+impl Fcitx5Engine {
+    /// Returns whether the app must not get the key, then its edits.
+    async fn process_key_event(
+        &self,
+        keyval: u32,
+        keycode: u32,
+        state: u32,
+    ) -> (bool, u32, String, String) {
+        let event = Event::Key {
+            keyval,
+            keycode,
+            state,
+        };
+        let (handled, (backspaces, commit, preedit)) = self.edits(event).await;
+        (handled, backspaces, commit, preedit)
+    }
+
+    /// Ends the word, when the text field lost the focus.
+    async fn reset(&self) -> Edits {
+        self.edits(Event::Reset).await.1
+    }
+}
+
+fn serve(
+    builder: connection::Builder<'_>,
+    requests: mpsc::Sender<Request>,
+) -> zbus::Result<connection::Connection> {
+    builder
+        .serve_at(OBJECT_PATH, Fcitx5Engine { requests })?
+        .name(BUS_NAME)?
+        .build()
+}
+
+/// Keeps `running` telling whether the engine of the goxkey addon runs.
+/// Fails when Fcitx5 does not.
+pub(super) fn watch_engine(running: &'static AtomicBool) -> Result<(), String> {
+    let connection = zbus::blocking::Connection::session()
+        .map_err(|err| format!("cannot connect to the session bus: {err}"))?;
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&connection).map_err(|err| err.to_string())?;
+    if !dbus
+        .name_has_owner(FCITX5_BUS_NAME.try_into().unwrap())
+        .unwrap_or(false)
+    {
+        return Err("Fcitx5 does not run".to_string());
+    }
+    watch_bus_name(connection, BUS_NAME, running)
+        .map_err(|err| format!("cannot watch the Fcitx5 engine: {err}"))
+}
+
+/// Runs as the engine of the goxkey addon of Fcitx5, which D-Bus starts
+/// on the session bus the first time the addon calls it.
+pub fn run_event_listener(callback: &CallbackFn) -> Result<(), String> {
+    run_engine(callback, |requests| {
+        serve(connection::Builder::session()?, requests)
+    })
+}
+
+/// Plays the addon on a private bus and types `as ` into the engine.
+/// Needs `dbus-daemon`.
+#[test]
+fn test_engine_on_private_bus() {
+    let Some((mut daemon, address)) = super::private_bus() else {
+        return;
+    };
+    let engine_address = address.clone();
+    std::thread::spawn(move || {
+        run_engine(&super::fake_telex, |requests| {
+            serve(
+                connection::Builder::address(engine_address.as_str())?,
+                requests,
+            )
+        })
+        .unwrap();
+    });
+
+    let connection = connection::Builder::address(address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&connection).unwrap();
+    for _ in 0..50 {
+        if dbus.name_has_owner(BUS_NAME.try_into().unwrap()).unwrap() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let engine = zbus::blocking::Proxy::new(
+        &connection,
+        BUS_NAME,
+        OBJECT_PATH,
+        "com.goxgox.app.Fcitx5.Engine",
+    )
+    .unwrap();
+    let process_key = |keyval: char, keycode: u32| -> (bool, u32, String, String) {
+        engine
+            .call("ProcessKeyEvent", &(keyval as u32, keycode, 0u32))
+            .unwrap()
+    };
+    let edits = |backspaces, commit: &str, preedit: &str| {
+        (true, backspaces, commit.to_string(), preedit.to_string())
+    };
+    assert_eq!(process_key('a', 30), edits(0, "", "a"));
+    assert_eq!(process_key('s', 31), edits(0, "", "á"));
+    assert_eq!(process_key(' ', 57), edits(0, "á ", ""));
+    assert_eq!(process_key('b', 48), edits(0, "", "b"));
+    let reset: Edits = engine.call("Reset", &()).unwrap();
+    assert_eq!(reset, (0, "b".to_string(), String::new()));
+    daemon.kill().unwrap();
+}
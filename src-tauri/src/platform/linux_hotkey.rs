@@ -0,0 +1,329 @@
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::{c_int, c_uint};
+use std::process::Command;
+
+use x11::keysym::*;
+use x11::xlib::{self, KeySym};
+
+use super::super::{KeyModifier, KEY_DELETE, KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB};
+use super::config_dir;
+use super::linux_window::{close_display, open_display, take_error};
+
+/// The GNOME settings that hold key bindings.
+const GNOME_SCHEMAS: [&str; 4] = [
+    "org.gnome.desktop.wm.keybindings",
+    "org.gnome.mutter.keybindings",
+    "org.gnome.shell.keybindings",
+    "org.gnome.settings-daemon.plugins.media-keys",
+];
+
+/// A key combination, by the keysym its key has without shift.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Shortcut {
+    modifiers: KeyModifier,
+    keysym: KeySym,
+}
+
+fn unshifted(keysym: KeySym) -> KeySym {
+    match keysym as c_uint {
+        XK_A..=XK_Z => keysym + (XK_a - XK_A) as KeySym,
+        _ => keysym,
+    }
+}
+
+fn hotkey_keysym(key: char) -> Option<KeySym> {
+    let keysym = match key {
+        KEY_ENTER => XK_Return,
+        KEY_SPACE => XK_space,
+        KEY_TAB => XK_Tab,
+        KEY_DELETE => XK_BackSpace,
+        KEY_ESCAPE => XK_Escape,
+        // Latin-1 keysyms are the code of their character.
+        c if c.is_ascii_graphic() => c.to_ascii_lowercase() as c_uint,
+        _ => return None,
+    };
+    Some(keysym as KeySym)
+}
+
+/// Reads a GNOME accelerator, like `<Super>space` or `<Primary><Shift>a`.
+fn parse_gnome_accelerator(accelerator: &str) -> Option<Shortcut> {
+    let mut modifiers = KeyModifier::new();
+    let mut rest = accelerator;
+    while let Some(tail) = rest.strip_prefix('<') {
+        let (name, tail) = tail.split_once('>')?;
+        match name.to_ascii_lowercase().as_str() {
+            "super" | "mod4" => modifiers.add_super(),
+            "primary" | "control" | "ctrl" | "ctl" => modifiers.add_control(),
+            "alt" | "mod1" => modifiers.add_alt(),
+            "shift" => modifiers.add_shift(),
+            _ => return None,
+        }
+        rest = tail;
+    }
+    let name = CString::new(rest).ok()?;
+    let keysym = unsafe { xlib::XStringToKeysym(name.as_ptr()) };
+    (keysym != 0).then(|| Shortcut {
+        modifiers,
+        keysym: unshifted(keysym),
+    })
+}
+
+/// `switch-input-source` reads as "Switch input source".
+fn gnome_setting_name(key: &str) -> String {
+    let name = key.replace('-', " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+/// Finds the name of the setting bound to `shortcut` in the output of
+/// `gsettings list-recursively`: a schema, a key and a value per line.
+fn find_gnome_shortcut(listing: &str, shortcut: Shortcut) -> Option<String> {
+    listing.lines().find_map(|line| {
+        let mut parts = line.splitn(3, ' ');
+        let (_, key, value) = (parts.next()?, parts.next()?, parts.next()?);
+        // The accelerators are the quoted strings of the value, which is
+        // either one of them or a list.
+        value
+            .split('\'')
+            .skip(1)
+            .step_by(2)
+            .filter_map(parse_gnome_accelerator)
+            .any(|bound| bound == shortcut)
+            .then(|| gnome_setting_name(key))
+    })
+}
+
+fn gnome_shortcut(shortcut: Shortcut) -> Option<String> {
+    GNOME_SCHEMAS.iter().find_map(|schema| {
+        let output = Command::new("gsettings")
+            .args(["list-recursively", schema])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        find_gnome_shortcut(&String::from_utf8_lossy(&output.stdout), shortcut)
+    })
+}
+
+/// Reads a KDE shortcut, like `Meta+Space` or `Ctrl+Alt+T`.
+fn parse_kde_shortcut(text: &str) -> Option<Shortcut> {
+    let (modifier_names, key) = match text.strip_suffix("++") {
+        Some(modifier_names) => (modifier_names, "+"),
+        None => text.rsplit_once('+').unwrap_or(("", text)),
+    };
+    let mut modifiers = KeyModifier::new();
+    for name in modifier_names.split('+').filter(|name| !name.is_empty()) {
+        match name {
+            "Meta" => modifiers.add_super(),
+            "Ctrl" => modifiers.add_control(),
+            "Alt" => modifiers.add_alt(),
+            "Shift" => modifiers.add_shift(),
+            _ => return None,
+        }
+    }
+    let keysym = match key {
+        "Space" => XK_space,
+        "Return" | "Enter" => XK_Return,
+        "Tab" => XK_Tab,
+        "Backspace" => XK_BackSpace,
+        "Esc" | "Escape" => XK_Escape,
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_graphic() => c.to_ascii_lowercase() as c_uint,
+                _ => return None,
+            }
+        }
+    };
+    Some(Shortcut {
+        modifiers,
+        keysym: keysym as KeySym,
+    })
+}
+
+/// Finds the name of the action bound to `shortcut` in `kglobalshortcutsrc`,
+/// whose entries are `action=shortcuts,default shortcuts,name`, the
+/// shortcuts separated by tabs.
+fn find_kde_shortcut(config: &str, shortcut: Shortcut) -> Option<String> {
+    config.lines().find_map(|line| {
+        let (action, value) = line.split_once('=')?;
+        if action == "_k_friendly_name" {
+            return None;
+        }
+        let mut fields = value.split(',');
+        let current = fields.next()?;
+        let name = fields
+            .nth(1)
+            .filter(|name| !name.is_empty())
+            .unwrap_or(action);
+        current
+            .split('\t')
+            .filter_map(parse_kde_shortcut)
+            .any(|bound| bound == shortcut)
+            .then(|| name.to_string())
+    })
+}
+
+fn kde_shortcut(shortcut: Shortcut) -> Option<String> {
+    let config = fs::read_to_string(config_dir()?.join("kglobalshortcutsrc")).ok()?;
+    find_kde_shortcut(&config, shortcut)
+}
+
+fn is_desktop(name: &str) -> bool {
+    env::var("XDG_CURRENT_DESKTOP").is_ok_and(|desktops| desktops.split(':').any(|d| d == name))
+}
+
+/// Whether another X client grabbed the combination, which the X server
+/// refuses to grab twice.
+fn is_grabbed_on_x11(shortcut: Shortcut) -> bool {
+    let mut mask = 0;
+    for (modifier, modifier_mask) in [
+        (KeyModifier::MODIFIER_SHIFT, xlib::ShiftMask),
+        (KeyModifier::MODIFIER_CONTROL, xlib::ControlMask),
+        (KeyModifier::MODIFIER_ALT, xlib::Mod1Mask),
+        (KeyModifier::MODIFIER_SUPER, xlib::Mod4Mask),
+    ] {
+        if shortcut.modifiers.contains(modifier) {
+            mask |= modifier_mask;
+        }
+    }
+    unsafe {
+        let Some(display) = open_display() else {
+            return false;
+        };
+        let keycode = xlib::XKeysymToKeycode(display, shortcut.keysym) as c_int;
+        let root = xlib::XDefaultRootWindow(display);
+        let mut is_grabbed = false;
+        if keycode != 0 {
+            xlib::XGrabKey(
+                display,
+                keycode,
+                mask,
+                root,
+                xlib::False,
+                xlib::GrabModeAsync,
+                xlib::GrabModeAsync,
+            );
+            xlib::XSync(display, xlib::False);
+            is_grabbed = take_error(display) == Some(xlib::BadAccess);
+            xlib::XUngrabKey(display, keycode, mask, root);
+            xlib::XSync(display, xlib::False);
+        }
+        close_display(display);
+        is_grabbed
+    }
+}
+
+pub fn check_hotkey_conflict(modifiers: KeyModifier, key: Option<char>) -> Result<(), String> {
+    let key = key.ok_or_else(|| "Hotkey needs a key".to_string())?;
+    let keysym = hotkey_keysym(key).ok_or_else(|| "Unsupported key".to_string())?;
+    let mut modifiers = modifiers;
+    modifiers.remove(KeyModifier::MODIFIER_CAPSLOCK);
+    let shortcut = Shortcut { modifiers, keysym };
+    if is_desktop("GNOME") {
+        if let Some(name) = gnome_shortcut(shortcut) {
+            return Err(format!("Hotkey used by GNOME: {name}"));
+        }
+    }
+    if is_desktop("KDE") {
+        if let Some(name) = kde_shortcut(shortcut) {
+            return Err(format!("Hotkey used by KDE: {name}"));
+        }
+    }
+    if env::var_os("DISPLAY").is_some() && is_grabbed_on_x11(shortcut) {
+        return Err("Hotkey unavailable, another app uses it".to_string());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_find_gnome_shortcut() {
+    let listing = "\
+org.gnome.desktop.wm.keybindings close ['<Alt>F4']
+org.gnome.desktop.wm.keybindings switch-input-source ['<Super>space', 'XF86Keyboard']
+org.gnome.desktop.wm.keybindings switch-windows @as []
+org.gnome.settings-daemon.plugins.media-keys terminal '<Primary><Alt>t'
+";
+    let shortcut = |modifiers, keysym: c_uint| Shortcut {
+        modifiers,
+        keysym: keysym as KeySym,
+    };
+    assert_eq!(
+        find_gnome_shortcut(listing, shortcut(KeyModifier::MODIFIER_SUPER, XK_space)),
+        Some("Switch input source".to_string())
+    );
+    let control_alt = KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_ALT;
+    assert_eq!(
+        find_gnome_shortcut(listing, shortcut(control_alt, XK_t)),
+        Some("Terminal".to_string())
+    );
+    assert_eq!(
+        find_gnome_shortcut(listing, shortcut(KeyModifier::MODIFIER_CONTROL, XK_space)),
+        None
+    );
+}
+
+#[test]
+fn test_find_kde_shortcut() {
+    let config = "\
+[kwin]
+_k_friendly_name=KWin
+Switch to Desktop 1=Ctrl+F1,Ctrl+F1,Switch to Desktop 1
+[KDE Keyboard Layout Switcher]
+Switch to Next Keyboard Layout=Meta+Alt+K\tMeta+Space,Meta+Alt+K,Switch to Next Keyboard Layout
+[org.kde.konsole.desktop]
+_launch=Ctrl+Alt+T,none,Konsole
+";
+    let shortcut = |modifiers, keysym: c_uint| Shortcut {
+        modifiers,
+        keysym: keysym as KeySym,
+    };
+    let control_alt = KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_ALT;
+    assert_eq!(
+        find_kde_shortcut(config, shortcut(control_alt, XK_t)),
+        Some("Konsole".to_string())
+    );
+    assert_eq!(
+        find_kde_shortcut(config, shortcut(KeyModifier::MODIFIER_SUPER, XK_space)),
+        Some("Switch to Next Keyboard Layout".to_string())
+    );
+    assert_eq!(
+        find_kde_shortcut(config, shortcut(KeyModifier::MODIFIER_CONTROL, XK_F2)),
+        None
+    );
+}
+
+/// Needs an X server, such as Xvfb.
+#[test]
+fn test_is_grabbed_on_x11() {
+    if env::var_os("DISPLAY").is_none() {
+        return;
+    }
+    let shortcut = Shortcut {
+        modifiers: KeyModifier::MODIFIER_CONTROL | KeyModifier::MODIFIER_ALT,
+        keysym: XK_F12 as KeySym,
+    };
+    assert!(!is_grabbed_on_x11(shortcut));
+    unsafe {
+        let display = xlib::XOpenDisplay(std::ptr::null());
+        let keycode = xlib::XKeysymToKeycode(display, shortcut.keysym) as c_int;
+        let root = xlib::XDefaultRootWindow(display);
+        let mask = xlib::ControlMask | xlib::Mod1Mask;
+        xlib::XGrabKey(
+            display,
+            keycode,
+            mask,
+            root,
+            xlib::False,
+            xlib::GrabModeAsync,
+            xlib::GrabModeAsync,
+        );
+        xlib::XSync(display, xlib::False);
+        assert!(is_grabbed_on_x11(shortcut));
+        xlib::XCloseDisplay(display);
+    }
+}
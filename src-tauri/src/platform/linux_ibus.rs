@@ -0,0 +1,539 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+
+use futures_channel::oneshot;
+use x11::keysym::XK_BackSpace;
+use zbus::blocking::connection;
+use zbus::object_server::{ObjectServer, SignalEmitter};
+use zbus::zvariant::{OwnedObjectPath, StructureBuilder, Value};
+use zbus::{fdo, interface};
+
+use super::super::{CallbackFn, EventTapType, KeyEvent, KeyModifier, ModifierKeys};
+use super::linux_composer::{Composer, Edits, Keymap};
+use super::linux_x11::{
+    is_keypad_keysym, keysym_to_char, keysym_to_modifier, keysym_to_modifier_key,
+    keysym_to_pressed_key,
+};
+use super::Handle;
+use super::{config_dir, watch_bus_name};
+
+/// The name of the engine in the IBus component file.
+const ENGINE_NAME: &str = "goxkey";
+const BUS_NAME: &str = "org.freedesktop.IBus.GoxKey";
+
+const IBUS_SHIFT_MASK: u32 = 1 << 0;
+const IBUS_LOCK_MASK: u32 = 1 << 1;
+const IBUS_CONTROL_MASK: u32 = 1 << 2;
+const IBUS_MOD1_MASK: u32 = 1 << 3;
+const IBUS_MOD4_MASK: u32 = 1 << 6;
+const IBUS_SUPER_MASK: u32 = 1 << 26;
+const IBUS_RELEASE_MASK: u32 = 1 << 30;
+
+/// The evdev code of the backspace key.
+const KEY_BACKSPACE: u32 = 14;
+
+pub(super) enum Event {
+    Key {
+        keyval: u32,
+        keycode: u32,
+        state: u32,
+    },
+    /// The text field lost the focus, or its cursor moved.
+    Reset,
+}
+
+/// An event from the input method framework, handled on the thread of the
+/// event listener.
+pub(super) struct Request {
+    event: Event,
+    reply: oneshot::Sender<Reply>,
+}
+
+pub(super) struct Reply {
+    /// Whether the app must not get the key.
+    pub(super) handled: bool,
+    pub(super) edits: Edits,
+}
+
+/// Sends `event` to the event listener and waits for its reply, `None`
+/// once it stopped. The wait lets the bus executor run in the meantime.
+pub(super) async fn request(requests: &mpsc::Sender<Request>, event: Event) -> Option<Reply> {
+    let (reply, receiver) = oneshot::channel();
+    requests.send(Request { event, reply }).ok()?;
+    receiver.await.ok()
+}
+
+fn state_to_modifiers(state: u32) -> KeyModifier {
+    let mut modifiers = KeyModifier::new();
+    modifiers.apply(
+        state & (IBUS_MOD4_MASK | IBUS_SUPER_MASK) != 0,
+        state & IBUS_CONTROL_MASK != 0,
+        state & IBUS_MOD1_MASK != 0,
+        state & IBUS_SHIFT_MASK != 0,
+        state & IBUS_LOCK_MASK != 0,
+    );
+    modifiers
+}
+
+struct Engine<'a> {
+    callback: &'a CallbackFn,
+    /// To read the keys without their modifiers, as the frameworks only
+    /// send the symbol they type.
+    keymap: Option<Keymap>,
+    composer: Composer,
+    modifiers: KeyModifier,
+    modifier_keys: ModifierKeys,
+    /// The keys held down, whose next presses are repeats.
+    held_keys: HashSet<u32>,
+    /// The keys whose press the app did not get, nor should their release.
+    handled_keys: HashSet<u32>,
+    /// The frameworks do not tell when a key was typed, the keys are timed
+    /// from here when they arrive.
+    started: Instant,
+}
+
+impl Engine<'_> {
+    /// Calls back with `event`, at the modifiers held.
+    fn call(&self, event: KeyEvent) -> bool {
+        let handle = Handle::Composer(&self.composer);
+        let event = KeyEvent {
+            modifiers: self.modifiers,
+            modifier_keys: self.modifier_keys,
+            timestamp: self.started.elapsed().as_millis() as u64,
+            ..event
+        };
+        (self.callback)(handle, &event)
+    }
+
+    fn process_key(&mut self, keyval: u32, keycode: u32, state: u32) -> bool {
+        let is_release = state & IBUS_RELEASE_MASK != 0;
+        let is_repeat = if is_release {
+            self.held_keys.remove(&keycode);
+            false
+        } else {
+            !self.held_keys.insert(keycode)
+        };
+        let x_keycode = keycode + 8;
+        let key_event = |event_type, key| KeyEvent {
+            keycode: Some(x_keycode),
+            is_repeat,
+            ..KeyEvent::new(event_type, key, KeyModifier::new())
+        };
+        if let Some((modifier, is_lock)) = keysym_to_modifier(keyval.into()) {
+            // The state is the one from before the key.
+            let mut modifiers = state_to_modifiers(state);
+            match (is_release, is_lock) {
+                (false, true) => modifiers.toggle(modifier),
+                (false, false) => modifiers.insert(modifier),
+                (true, false) => modifiers.remove(modifier),
+                (true, true) => {}
+            }
+            let mut modifier_keys = self.modifier_keys;
+            if let Some(modifier_key) = keysym_to_modifier_key(keyval.into()) {
+                modifier_keys.set(modifier_key, !is_release);
+            }
+            if modifiers != self.modifiers || modifier_keys != self.modifier_keys {
+                self.modifiers = modifiers;
+                self.modifier_keys = modifier_keys;
+                self.call(key_event(EventTapType::FlagsChanged, None));
+            }
+            return false;
+        }
+        let base_keysym = self
+            .keymap
+            .as_ref()
+            .map(|keymap| keymap.base_keysym(x_keycode))
+            // The keyval is read with Num Lock, which the keypad needs.
+            .filter(|&keysym| keysym != 0 && !is_keypad_keysym(keysym.into()))
+            .unwrap_or(keyval);
+        let pressed_key = keysym_to_pressed_key(base_keysym.into(), x_keycode);
+        if is_release {
+            self.call(key_event(EventTapType::KeyUp, Some(pressed_key)));
+            return self.handled_keys.remove(&keycode);
+        }
+        self.modifiers = state_to_modifiers(state);
+        let typed = keysym_to_char(keyval.into());
+        let blocked = self.call(key_event(EventTapType::KeyDown, Some(pressed_key)));
+        let handled = blocked || !self.composer.type_key(pressed_key, typed, self.modifiers);
+        if handled {
+            self.handled_keys.insert(keycode);
+        }
+        handled
+    }
+
+    fn reset(&mut self) {
+        self.composer.finish("");
+        self.call(KeyEvent::new(EventTapType::Other, None, KeyModifier::new()));
+    }
+
+    fn handle(&mut self, event: Event) -> Reply {
+        let handled = match event {
+            Event::Key {
+                keyval,
+                keycode,
+                state,
+            } => self.process_key(keyval, keycode, state),
+            Event::Reset => {
+                self.reset();
+                false
+            }
+        };
+        Reply {
+            handled,
+            edits: self.composer.take_edits(),
+        }
+    }
+}
+
+/// An `IBusText`, as IBus serializes it: a class name, attachments, the
+/// text and its `IBusAttrList`.
+fn ibus_text(text: &str, underline: bool) -> zbus::zvariant::Result<Value<'static>> {
+    let attachments = || HashMap::<String, Value<'static>>::new();
+    let mut attributes = Vec::new();
+    if underline && !text.is_empty() {
+        // IBUS_ATTR_TYPE_UNDERLINE, IBUS_ATTR_UNDERLINE_SINGLE, then the
+        // range it covers, in characters.
+        let attribute = StructureBuilder::new()
+            .add_field("IBusAttribute")
+            .add_field(attachments())
+            .add_field(1u32)
+            .add_field(1u32)
+            .add_field(0u32)
+            .add_field(text.chars().count() as u32)
+            .build()?;
+        attributes.push(Value::new(attribute));
+    }
+    let attribute_list = StructureBuilder::new()
+        .add_field("IBusAttrList")
+        .add_field(attachments())
+        .add_field(attributes)
+        .build()?;
+    let text = StructureBuilder::new()
+        .add_field("IBusText")
+        .add_field(attachments())
+        .add_field(text.to_string())
+        .append_field(Value::new(attribute_list))
+        .build()?;
+    Ok(Value::new(text))
+}
+
+struct Factory {
+    requests: mpsc::Sender<Request>,
+    next_engine: AtomicU32,
+}
+
+#[interface(name = "org.freedesktop.IBus.Factory")]
+impl Factory {
+    async fn create_engine(
+        &self,
+        #[zbus(object_server)] server: &ObjectServer,
+        name: &str,
+    ) -> fdo::Result<OwnedObjectPath> {
+        if name != ENGINE_NAME {
+            return Err(fdo::Error::InvalidArgs(format!("unknown engine `{name}`")));
+        }
+        let id = self.next_engine.fetch_add(1, Ordering::Relaxed);
+        let path = OwnedObjectPath::try_from(format!("/org/freedesktop/IBus/Engine/{id}"))
+            .map_err(zbus::Error::from)?;
+        let engine = EngineObject {
+            requests: self.requests.clone(),
+        };
+        server.at(&path, engine).await?;
+        Ok(path)
+    }
+}
+
+/// The engine IBus talks to. Every one of them is handled by the same
+/// `Engine`, as only the focused text field sends keys.
+struct EngineObject {
+    requests: mpsc::Sender<Request>,
+}
+
+impl EngineObject {
+    async fn send_edits(emitter: &SignalEmitter<'_>, edits: Edits) -> zbus::Result<()> {
+        // The preedit cannot reach the text before it, the app erases it.
+        for _ in 0..edits.backspaces {
+            let keysym = XK_BackSpace;
+            Self::forward_key_event(emitter, keysym, KEY_BACKSPACE, 0).await?;
+            Self::forward_key_event(emitter, keysym, KEY_BACKSPACE, IBUS_RELEASE_MASK).await?;
+        }
+        if !edits.commit.is_empty() {
+            Self::commit_text(emitter, ibus_text(&edits.commit, false)?).await?;
+        }
+        let cursor = edits.preedit.chars().count() as u32;
+        let visible = !edits.preedit.is_empty();
+        // IBUS_ENGINE_PREEDIT_CLEAR: the word is committed when the focus
+        // moves, by `focus_out`.
+        let text = ibus_text(&edits.preedit, true)?;
+        Self::update_preedit_text(emitter, text, cursor, visible, 0).await
+    }
+
+    async fn end_word(&self, emitter: &SignalEmitter<'_>) {
+        if let Some(reply) = request(&self.requests, Event::Reset).await {
+            if let Err(err) = Self::send_edits(emitter, reply.edits).await {
+                log::error!("Cannot send the edits to IBus: {err}");
+            }
+        }
+    }
+}
+
+#[interface(name = "org.freedesktop.IBus.Engine")]
+impl EngineObject {
+    async fn process_key_event(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        keyval: u32,
+        keycode: u32,
+        state: u32,
+    ) -> bool {
+        let event = Event::Key {
+            keyval,
+            keycode,
+            state,
+        };
+        let Some(reply) = request(&self.requests, event).await else {
+            return false;
+        };
+        if let Err(err) = Self::send_edits(&emitter, reply.edits).await {
+            log::error!("Cannot send the edits to IBus: {err}");
+        }
+        reply.handled
+    }
+
+    async fn focus_out(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        self.end_word(&emitter).await;
+    }
+
+    async fn reset(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        self.end_word(&emitter).await;
+    }
+
+    fn focus_in(&self) {}
+
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn set_cursor_location(&self, _x: i32, _y: i32, _width: i32, _height: i32) {}
+
+    fn set_capabilities(&self, _capabilities: u32) {}
+
+    fn set_surrounding_text(&self, _text: Value<'_>, _cursor: u32, _anchor: u32) {}
+
+    fn set_content_type(&self, _purpose: u32, _hints: u32) {}
+
+    #[zbus(signal)]
+    async fn commit_text(emitter: &SignalEmitter<'_>, text: Value<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn update_preedit_text(
+        emitter: &SignalEmitter<'_>,
+        text: Value<'_>,
+        cursor_pos: u32,
+        visible: bool,
+        mode: u32,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn forward_key_event(
+        emitter: &SignalEmitter<'_>,
+        keyval: u32,
+        keycode: u32,
+        state: u32,
+    ) -> zbus::Result<()>;
+}
+
+/// The file ibus-daemon writes its address to, named after the machine
+/// and the display it serves.
+fn bus_file_name(machine_id: &str, wayland_display: Option<&str>, display: Option<&str>) -> String {
+    if let Some(wayland_display) = wayland_display {
+        return format!("{machine_id}-unix-{wayland_display}");
+    }
+    // `host:number.screen`, the host being empty for local displays.
+    let display = display.unwrap_or(":0");
+    let (host, number) = display.split_once(':').unwrap_or((display, "0"));
+    let host = if host.is_empty() { "unix" } else { host };
+    let number = number.split('.').next().unwrap_or(number);
+    format!("{machine_id}-{host}-{number}")
+}
+
+fn ibus_address() -> Result<String, String> {
+    if let Ok(address) = env::var("IBUS_ADDRESS") {
+        return Ok(address);
+    }
+    let path = match env::var_os("IBUS_ADDRESS_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let machine_id = fs::read_to_string("/etc/machine-id")
+                .or_else(|_| fs::read_to_string("/var/lib/dbus/machine-id"))
+                .map_err(|err| format!("cannot read the machine id: {err}"))?;
+            let config_dir = config_dir().ok_or("cannot find the config directory")?;
+            let wayland_display = env::var("WAYLAND_DISPLAY").ok();
+            let display = env::var("DISPLAY").ok();
+            config_dir.join("ibus").join("bus").join(bus_file_name(
+                machine_id.trim(),
+                wayland_display.as_deref(),
+                display.as_deref(),
+            ))
+        }
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|err| format!("cannot read {}: {err}", path.display()))?;
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("IBUS_ADDRESS="))
+        .map(str::to_string)
+        .ok_or_else(|| format!("no IBus address in {}", path.display()))
+}
+
+/// Serves the engine factory on the bus at `address`. IBus asks it for an
+/// engine once goxkey is picked as the input method.
+fn serve(address: &str, requests: mpsc::Sender<Request>) -> zbus::Result<connection::Connection> {
+    let factory = Factory {
+        requests,
+        next_engine: AtomicU32::new(1),
+    };
+    connection::Builder::address(address)?
+        .serve_at("/org/freedesktop/IBus/Factory", factory)?
+        .name(BUS_NAME)?
+        .build()
+}
+
+/// Handles the requests of the objects `serve` puts on the bus, on the
+/// calling thread as the callback has to run there.
+pub(super) fn run_engine<F>(callback: &CallbackFn, serve: F) -> Result<(), String>
+where
+    F: FnOnce(mpsc::Sender<Request>) -> zbus::Result<connection::Connection>,
+{
+    let keymap = xkbcommon_dl::xkbcommon_option().and_then(Keymap::from_defaults);
+    if keymap.is_none() {
+        log::warn!("Cannot load the xkb keymap, keys are read with their modifiers");
+    }
+    let (sender, receiver) = mpsc::channel();
+    let _connection = serve(sender).map_err(|err| format!("cannot serve the engine: {err}"))?;
+    let mut engine = Engine {
+        callback,
+        keymap,
+        composer: Composer::default(),
+        modifiers: KeyModifier::new(),
+        modifier_keys: ModifierKeys::empty(),
+        held_keys: HashSet::new(),
+        handled_keys: HashSet::new(),
+        started: Instant::now(),
+    };
+    for request in receiver {
+        let _ = request.reply.send(engine.handle(request.event));
+    }
+    Ok(())
+}
+
+/// Keeps `running` telling whether the goxkey engine of ibus-daemon runs.
+/// Fails when ibus-daemon does not.
+pub(super) fn watch_engine(running: &'static AtomicBool) -> Result<(), String> {
+    let address = ibus_address()?;
+    let connection = connection::Builder::address(address.as_str())
+        .and_then(|builder| builder.build())
+        .map_err(|err| format!("cannot connect to ibus-daemon: {err}"))?;
+    watch_bus_name(connection, BUS_NAME, running)
+        .map_err(|err| format!("cannot watch the IBus engine: {err}"))
+}
+
+/// Runs as the engine of ibus-daemon, which sends the keys of the focused
+/// text field and shows the word being typed as preedit text.
+pub fn run_event_listener(callback: &CallbackFn) -> Result<(), String> {
+    let address = ibus_address()?;
+    run_engine(callback, |requests| serve(&address, requests))
+}
+
+#[test]
+fn test_bus_file_name() {
+    assert_eq!(bus_file_name("id", None, Some(":0")), "id-unix-0");
+    assert_eq!(bus_file_name("id", None, Some(":1.0")), "id-unix-1");
+    assert_eq!(bus_file_name("id", None, Some("host:2")), "id-host-2");
+    assert_eq!(
+        bus_file_name("id", Some("wayland-0"), Some(":0")),
+        "id-unix-wayland-0"
+    );
+}
+
+/// Plays ibus-daemon on a private bus: asks for an engine and types
+/// `as ` into it. Needs `dbus-daemon`.
+#[test]
+fn test_engine_on_private_bus() {
+    use zbus::zvariant::Structure;
+
+    let Some((mut daemon, address)) = super::private_bus() else {
+        return;
+    };
+    let engine_address = address.clone();
+    std::thread::spawn(move || {
+        run_engine(&super::fake_telex, |requests| {
+            serve(&engine_address, requests)
+        })
+        .unwrap();
+    });
+
+    let connection = connection::Builder::address(address.as_str())
+        .unwrap()
+        .build()
+        .unwrap();
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&connection).unwrap();
+    for _ in 0..50 {
+        if dbus.name_has_owner(BUS_NAME.try_into().unwrap()).unwrap() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let factory = zbus::blocking::Proxy::new(
+        &connection,
+        BUS_NAME,
+        "/org/freedesktop/IBus/Factory",
+        "org.freedesktop.IBus.Factory",
+    )
+    .unwrap();
+    let path: OwnedObjectPath = factory.call("CreateEngine", &(ENGINE_NAME,)).unwrap();
+    let engine =
+        zbus::blocking::Proxy::new(&connection, BUS_NAME, path, "org.freedesktop.IBus.Engine")
+            .unwrap();
+    let mut signals = engine.receive_all_signals().unwrap();
+    let text_of = |value: Value| {
+        let text = Structure::try_from(value).unwrap();
+        String::try_from(text.fields()[2].try_clone().unwrap()).unwrap()
+    };
+    let mut next_signal = || {
+        let message = signals.next().unwrap();
+        let name = message.header().member().unwrap().to_string();
+        let body = message.body();
+        let text = match name.as_str() {
+            "CommitText" => body.deserialize::<(Value,)>().unwrap().0,
+            _ => body.deserialize::<(Value, u32, bool, u32)>().unwrap().0,
+        };
+        (name, text_of(text))
+    };
+
+    for (keyval, keycode) in [('a', 30), ('s', 31), (' ', 57)] {
+        let handled: bool = engine
+            .call("ProcessKeyEvent", &(keyval as u32, keycode as u32, 0u32))
+            .unwrap();
+        assert!(handled, "{keyval}");
+    }
+    assert_eq!(
+        next_signal(),
+        ("UpdatePreeditText".to_string(), "a".to_string())
+    );
+    assert_eq!(
+        next_signal(),
+        ("UpdatePreeditText".to_string(), "á".to_string())
+    );
+    assert_eq!(next_signal(), ("CommitText".to_string(), "á ".to_string()));
+    assert_eq!(
+        next_signal(),
+        ("UpdatePreeditText".to_string(), String::new())
+    );
+    daemon.kill().unwrap();
+}
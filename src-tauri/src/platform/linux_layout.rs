@@ -0,0 +1,203 @@
+use std::env;
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_int, c_uchar, c_uint};
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+
+use once_cell::sync::Lazy;
+use x11::xlib::{self, Display};
+
+use super::linux_composer::Keymap;
+use super::linux_window::{close_display, open_display};
+use crate::layout::KeyboardLayout;
+
+/// The device Xkb requests refer to for the core keyboard.
+const XKB_USE_CORE_KBD: c_uint = 0x0100;
+
+type LayoutCallback = Box<dyn Fn() + Send>;
+
+/// The layout the keys were last read with, as the backend reading them
+/// reported it.
+static REPORTED_LAYOUT: Lazy<Mutex<Option<KeyboardLayout>>> = Lazy::new(|| Mutex::new(None));
+
+static CALLBACKS: Lazy<Mutex<Vec<LayoutCallback>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Tells that the keys are read with `layout` from now on.
+pub(super) fn report_layout(layout: KeyboardLayout) {
+    let mut reported = REPORTED_LAYOUT.lock().unwrap();
+    if reported.as_ref() == Some(&layout) {
+        return;
+    }
+    log::debug!("Keyboard layout changed to {}", layout.name());
+    *reported = Some(layout);
+    drop(reported);
+    for callback in CALLBACKS.lock().unwrap().iter() {
+        callback();
+    }
+}
+
+/// The layout names of `setxkbmap`, which the X server compiled its keymap
+/// from: the layouts and their variants, separated by commas.
+unsafe fn rule_names(display: *mut Display) -> Option<(String, String)> {
+    let name = CString::new("_XKB_RULES_NAMES").unwrap();
+    let property = xlib::XInternAtom(display, name.as_ptr(), xlib::True);
+    if property == 0 {
+        return None;
+    }
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut item_count = 0;
+    let mut bytes_after = 0;
+    let mut data: *mut c_uchar = ptr::null_mut();
+    let status = xlib::XGetWindowProperty(
+        display,
+        xlib::XDefaultRootWindow(display),
+        property,
+        0,
+        1024,
+        xlib::False,
+        xlib::XA_STRING,
+        &mut actual_type,
+        &mut actual_format,
+        &mut item_count,
+        &mut bytes_after,
+        &mut data,
+    );
+    if status != xlib::Success as c_int || data.is_null() {
+        return None;
+    }
+    let bytes = std::slice::from_raw_parts(data, item_count as usize).to_vec();
+    xlib::XFree(data as *mut _);
+    // Rules, model, layouts, variants and options, each ended by a nul.
+    let mut names = bytes
+        .split(|&byte| byte == 0)
+        .map(|name| String::from_utf8_lossy(name).into_owned());
+    let layout = names.nth(2)?;
+    let variant = names.next().unwrap_or_default();
+    Some((layout, variant))
+}
+
+/// The layout of the X server, at the group the keyboard is in.
+unsafe fn x11_layout(display: *mut Display) -> Option<KeyboardLayout> {
+    let (layout, variant) = rule_names(display)?;
+    let keymap = Keymap::from_names(xkbcommon_dl::xkbcommon_option()?, &layout, &variant)?;
+    let mut state: xlib::XkbStateRec = mem::zeroed();
+    if xlib::XkbGetState(display, XKB_USE_CORE_KBD, &mut state) == xlib::Success as c_int {
+        keymap.update_mask(0, 0, 0, state.group.into());
+    }
+    Some(keymap.layout())
+}
+
+/// The layout the keys are read with: the last one reported, or the one
+/// of the X server, or the system default.
+pub fn keyboard_layout() -> KeyboardLayout {
+    if let Some(layout) = REPORTED_LAYOUT.lock().unwrap().clone() {
+        return layout;
+    }
+    let x11 = unsafe {
+        open_display().and_then(|display| {
+            let layout = x11_layout(display);
+            close_display(display);
+            layout
+        })
+    };
+    x11.or_else(|| {
+        xkbcommon_dl::xkbcommon_option()
+            .and_then(Keymap::from_defaults)
+            .map(|keymap| keymap.layout())
+    })
+    .unwrap_or_default()
+}
+
+/// Calls `callback` every time the keys are read with another layout.
+///
+/// Wayland backends report the keymaps of the compositor themselves. In an
+/// X11 session, the X server tells when it switched group or keymap.
+pub fn watch_keyboard_layout<F>(callback: F) -> Result<(), String>
+where
+    F: Fn() + Send + 'static,
+{
+    CALLBACKS.lock().unwrap().push(Box::new(callback));
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        return Ok(());
+    }
+    let display = unsafe { open_display() }.ok_or("cannot open the X display")?;
+    let mut event_base = 0;
+    unsafe {
+        let (mut opcode, mut error_base) = (0, 0);
+        let (mut major, mut minor) = (1, 0);
+        let has_xkb = xlib::XkbQueryExtension(
+            display,
+            &mut opcode,
+            &mut event_base,
+            &mut error_base,
+            &mut major,
+            &mut minor,
+        );
+        if has_xkb == 0 {
+            close_display(display);
+            return Err("the X server has no XKB extension".to_string());
+        }
+        let events = xlib::XkbNewKeyboardNotifyMask | xlib::XkbStateNotifyMask;
+        xlib::XkbSelectEvents(display, XKB_USE_CORE_KBD, events, events);
+        // Only the group matters, not every modifier press.
+        xlib::XkbSelectEventDetails(
+            display,
+            XKB_USE_CORE_KBD,
+            xlib::XkbStateNotify as c_uint,
+            xlib::XkbGroupStateMask,
+            xlib::XkbGroupStateMask,
+        );
+    }
+    // The display pointer is not `Send`, but the thread is its only user.
+    let display = display as usize;
+    thread::spawn(move || unsafe {
+        let display = display as *mut Display;
+        let mut event: xlib::XEvent = mem::zeroed();
+        loop {
+            xlib::XNextEvent(display, &mut event);
+            if event.get_type() != event_base {
+                continue;
+            }
+            let xkb_event = &*(&event as *const xlib::XEvent as *const xlib::XkbAnyEvent);
+            if matches!(
+                xkb_event.xkb_type,
+                xlib::XkbNewKeyboardNotify | xlib::XkbStateNotify
+            ) {
+                if let Some(layout) = x11_layout(display) {
+                    report_layout(layout);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Needs libxkbcommon and the xkb data files.
+#[test]
+fn test_report_layout() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let Some(xkb) = xkbcommon_dl::xkbcommon_option() else {
+        return;
+    };
+    let changes = Arc::new(AtomicUsize::new(0));
+    let counter = changes.clone();
+    CALLBACKS.lock().unwrap().push(Box::new(move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }));
+    let keymap = Keymap::from_names(xkb, "us,ru", "").unwrap();
+    report_layout(keymap.layout());
+    report_layout(keymap.layout());
+    assert_eq!(changes.load(Ordering::Relaxed), 1);
+    assert_eq!(keyboard_layout().name(), "English (US)");
+    assert!(keyboard_layout().is_latin());
+    keymap.update_mask(0, 0, 0, 1);
+    report_layout(keymap.layout());
+    assert_eq!(changes.load(Ordering::Relaxed), 2);
+    assert_eq!(keyboard_layout().name(), "Russian");
+    assert!(!keyboard_layout().is_latin());
+}
@@ -0,0 +1,357 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileExt;
+use std::rc::Rc;
+
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_keyboard, wl_registry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle, WEnum};
+use wayland_protocols_misc::zwp_input_method_v2::client::{
+    zwp_input_method_keyboard_grab_v2::{self, ZwpInputMethodKeyboardGrabV2},
+    zwp_input_method_manager_v2::ZwpInputMethodManagerV2,
+    zwp_input_method_v2::{self, ZwpInputMethodV2},
+};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::{
+    zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+    zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+};
+use xkbcommon_dl::XkbCommon;
+
+use super::super::{CallbackFn, EventTapType, KeyEvent, KeyModifier, ModifierKeys};
+use super::linux_composer::{Composer, Keymap};
+use super::linux_layout::report_layout;
+use super::linux_x11::{keysym_to_modifier_key, keysym_to_pressed_key};
+use super::Handle;
+
+/// The evdev code of the backspace key.
+const KEY_BACKSPACE: u32 = 14;
+
+/// An input method client: the compositor sends it the keys typed in the
+/// focused text field, and it sends back the text to show there.
+pub struct Listener {
+    /// Only valid while `run_event_listener` runs.
+    callback: *const CallbackFn,
+    xkb: &'static XkbCommon,
+    queue: QueueHandle<Self>,
+    input_method: ZwpInputMethodV2,
+    /// Held while a text field is active only, so the keys of the other
+    /// apps reach them untouched.
+    grab: Option<ZwpInputMethodKeyboardGrabV2>,
+    virtual_keyboard: ZwpVirtualKeyboardV1,
+    keymap: Option<Keymap>,
+    /// The layout of the keymap the keys are typed in.
+    group: u32,
+    composer: Rc<Composer>,
+    modifiers: KeyModifier,
+    modifier_keys: ModifierKeys,
+    /// The time of the last key, in milliseconds, for the events that come
+    /// without one.
+    time: u32,
+    /// The number of `done` events, which every commit has to refer to.
+    serial: u32,
+    is_active: bool,
+    pending_active: Option<bool>,
+    /// The keys sent to the app, whose release has to follow them.
+    forwarded_keys: HashSet<u32>,
+    error: Option<String>,
+}
+
+impl Listener {
+    /// Calls back with `event`, at the modifiers held and the time of the
+    /// last key.
+    fn call(&self, event: KeyEvent) -> bool {
+        let handle = Handle::Composer(Rc::as_ptr(&self.composer));
+        let event = KeyEvent {
+            modifiers: self.modifiers,
+            modifier_keys: self.modifier_keys,
+            timestamp: self.time.into(),
+            ..event
+        };
+        unsafe { (*self.callback)(handle, &event) }
+    }
+
+    fn send_edits(&self, time: u32) {
+        let edits = self.composer.take_edits();
+        for _ in 0..edits.backspaces {
+            self.virtual_keyboard.key(time, KEY_BACKSPACE, 1);
+            self.virtual_keyboard.key(time, KEY_BACKSPACE, 0);
+        }
+        if !edits.commit.is_empty() {
+            self.input_method.commit_string(edits.commit);
+        }
+        let cursor = edits.preedit.len() as i32;
+        self.input_method
+            .set_preedit_string(edits.preedit, cursor, cursor);
+        self.input_method.commit(self.serial);
+    }
+
+    /// The compositor does not repeat keys to input methods, apps repeat
+    /// the ones they get themselves.
+    fn on_key(&mut self, time: u32, key: u32, is_press: bool) {
+        let Some(keymap) = &self.keymap else {
+            return;
+        };
+        self.time = time;
+        // Keymaps number the keys from the evdev codes plus 8, which are
+        // the X keycodes too.
+        let keycode = key + 8;
+        let keysym = keymap.pressed_keysym(keycode);
+        let pressed_key = keysym_to_pressed_key(keysym.into(), keycode);
+        let typed = keymap.key_char(keycode);
+        if let Some(modifier_key) = keysym_to_modifier_key(keysym.into()) {
+            self.modifier_keys.set(modifier_key, is_press);
+        }
+        if !is_press {
+            let event = KeyEvent {
+                keycode: Some(keycode),
+                ..KeyEvent::new(EventTapType::KeyUp, Some(pressed_key), KeyModifier::new())
+            };
+            self.call(event);
+            self.send_edits(time);
+            if self.forwarded_keys.remove(&key) {
+                self.virtual_keyboard.key(time, key, 0);
+            }
+            return;
+        }
+        let event = KeyEvent {
+            keycode: Some(keycode),
+            ..KeyEvent::new(EventTapType::KeyDown, Some(pressed_key), KeyModifier::new())
+        };
+        let blocked = self.call(event);
+        let forward = !blocked && self.composer.type_key(pressed_key, typed, self.modifiers);
+        self.send_edits(time);
+        if forward {
+            self.forwarded_keys.insert(key);
+            self.virtual_keyboard.key(time, key, 1);
+        }
+    }
+
+    fn on_modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        let Some(keymap) = &self.keymap else {
+            return;
+        };
+        keymap.update_mask(depressed, latched, locked, group);
+        if group != self.group {
+            self.group = group;
+            report_layout(keymap.layout());
+        }
+        self.virtual_keyboard
+            .modifiers(depressed, latched, locked, group);
+        let modifiers = keymap.modifiers();
+        if modifiers != self.modifiers {
+            self.modifiers = modifiers;
+            self.call(KeyEvent::new(EventTapType::FlagsChanged, None, modifiers));
+            self.send_edits(0);
+        }
+    }
+
+    fn grab_keyboard(&mut self) {
+        if self.grab.is_none() {
+            self.grab = Some(self.input_method.grab_keyboard(&self.queue, ()));
+        }
+    }
+
+    fn release_keyboard(&mut self) {
+        if let Some(grab) = self.grab.take() {
+            grab.release();
+        }
+        // The keys held from now on are released to the app directly.
+        for key in self.forwarded_keys.drain() {
+            self.virtual_keyboard.key(self.time, key, 0);
+        }
+    }
+
+    fn on_done(&mut self) {
+        self.serial += 1;
+        match self.pending_active.take() {
+            Some(true) => {
+                self.is_active = true;
+                self.grab_keyboard();
+            }
+            Some(false) if self.is_active => {
+                // The compositor drops the preedit of a text field that
+                // lost the focus, the engine has to start a new word too.
+                self.is_active = false;
+                self.release_keyboard();
+                self.call(KeyEvent::new(EventTapType::Other, None, KeyModifier::new()));
+                self.composer.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Listener {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwpInputMethodV2, ()> for Listener {
+    fn event(
+        listener: &mut Self,
+        _: &ZwpInputMethodV2,
+        event: zwp_input_method_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_input_method_v2::Event::Activate => listener.pending_active = Some(true),
+            zwp_input_method_v2::Event::Deactivate => listener.pending_active = Some(false),
+            zwp_input_method_v2::Event::Done => listener.on_done(),
+            zwp_input_method_v2::Event::Unavailable => {
+                listener.error = Some("another input method is already running".to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwpInputMethodKeyboardGrabV2, ()> for Listener {
+    fn event(
+        listener: &mut Self,
+        _: &ZwpInputMethodKeyboardGrabV2,
+        event: zwp_input_method_keyboard_grab_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_input_method_keyboard_grab_v2::Event::Keymap { format, fd, size } => {
+                if format != WEnum::Value(wl_keyboard::KeymapFormat::XkbV1) {
+                    listener.error = Some("the compositor sent an unknown keymap".to_string());
+                    return;
+                }
+                let file = File::from(fd);
+                let mut text = vec![0; size as usize];
+                let keymap = file
+                    .read_exact_at(&mut text, 0)
+                    .ok()
+                    .and_then(|_| Keymap::from_text(listener.xkb, &text));
+                let Some(keymap) = keymap else {
+                    listener.error = Some("cannot read the keymap".to_string());
+                    return;
+                };
+                // The keys sent back have to mean the same as the ones read.
+                listener.virtual_keyboard.keymap(
+                    wl_keyboard::KeymapFormat::XkbV1.into(),
+                    file.as_fd(),
+                    size,
+                );
+                report_layout(keymap.layout());
+                listener.keymap = Some(keymap);
+                listener.group = 0;
+            }
+            zwp_input_method_keyboard_grab_v2::Event::Key {
+                time, key, state, ..
+            } => {
+                let is_press = state == WEnum::Value(wl_keyboard::KeyState::Pressed);
+                listener.on_key(time, key, is_press);
+            }
+            zwp_input_method_keyboard_grab_v2::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => listener.on_modifiers(mods_depressed, mods_latched, mods_locked, group),
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(Listener: ignore WlSeat);
+delegate_noop!(Listener: ZwpInputMethodManagerV2);
+delegate_noop!(Listener: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(Listener: ZwpVirtualKeyboardV1);
+
+/// Registers with the compositor as the input method of the seat.
+fn connect(callback: &CallbackFn) -> Result<(EventQueue<Listener>, Listener), String> {
+    let xkb = xkbcommon_dl::xkbcommon_option().ok_or("cannot load libxkbcommon")?;
+    let connection = Connection::connect_to_env()
+        .map_err(|err| format!("cannot connect to the Wayland compositor: {err}"))?;
+    let (globals, queue) = registry_queue_init::<Listener>(&connection)
+        .map_err(|err| format!("cannot list the Wayland globals: {err}"))?;
+    let handle = queue.handle();
+    let seat: WlSeat = globals
+        .bind(&handle, 1..=1, ())
+        .map_err(|_| "the compositor has no seat")?;
+    let input_method_manager: ZwpInputMethodManagerV2 = globals
+        .bind(&handle, 1..=1, ())
+        .map_err(|_| "the compositor does not support input-method-v2")?;
+    let virtual_keyboard_manager: ZwpVirtualKeyboardManagerV1 = globals
+        .bind(&handle, 1..=1, ())
+        .map_err(|_| "the compositor does not support virtual-keyboard-v1")?;
+    let input_method = input_method_manager.get_input_method(&seat, &handle, ());
+    let virtual_keyboard = virtual_keyboard_manager.create_virtual_keyboard(&seat, &handle, ());
+    let listener = Listener {
+        callback,
+        xkb,
+        queue: handle,
+        input_method,
+        grab: None,
+        virtual_keyboard,
+        keymap: None,
+        group: 0,
+        composer: Rc::new(Composer::default()),
+        modifiers: KeyModifier::new(),
+        modifier_keys: ModifierKeys::empty(),
+        time: 0,
+        serial: 0,
+        is_active: false,
+        pending_active: None,
+        forwarded_keys: HashSet::new(),
+        error: None,
+    };
+    Ok((queue, listener))
+}
+
+/// Reads the keys of the focused text field as its input method, which
+/// the compositor only lets one client be. Blocks until the connection
+/// closes.
+///
+/// Only apps that support text-input-v3 have text fields the compositor
+/// activates the input method for. The keyboard is only grabbed then, so
+/// the keys of the other apps reach them without the engine.
+pub fn run_event_listener(callback: &CallbackFn) -> Result<(), String> {
+    let (mut queue, mut listener) = connect(callback)?;
+    loop {
+        queue
+            .blocking_dispatch(&mut listener)
+            .map_err(|err| format!("lost the Wayland connection: {err}"))?;
+        if let Some(error) = listener.error.take() {
+            return Err(error);
+        }
+    }
+}
+
+/// Needs a compositor with input-method-v2, such as sway or a headless
+/// wlroots one, and no other input method running.
+#[test]
+fn test_connect_as_input_method() {
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        return;
+    }
+    let callback = |_: Handle, _: &KeyEvent| false;
+    let (mut queue, mut listener) = connect(&callback).unwrap();
+    queue.roundtrip(&mut listener).unwrap();
+    assert_eq!(listener.error, None);
+    assert!(listener.grab.is_none());
+    // As when a text field gets the focus: the grab comes with the keymap.
+    listener.grab_keyboard();
+    queue.roundtrip(&mut listener).unwrap();
+    queue.roundtrip(&mut listener).unwrap();
+    assert_eq!(listener.error, None);
+    assert!(listener.keymap.is_some());
+    listener.release_keyboard();
+    queue.roundtrip(&mut listener).unwrap();
+    assert_eq!(listener.error, None);
+}
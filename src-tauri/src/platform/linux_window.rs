@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_int, c_uchar, c_ulong};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+
+use once_cell::sync::{Lazy, OnceCell};
+use x11::xlib::{self, Atom, Display, Window, XErrorEvent};
+
+/// The window that has the keyboard focus, as the window manager reports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveWindow {
+    /// The class of `WM_CLASS`, the same for every window of an app.
+    pub class: Option<String>,
+    /// The executable of the process that owns the window.
+    pub exe: Option<PathBuf>,
+}
+
+/// The connections opened by this module, with their last error. Their
+/// errors are expected: a window can close while we read its properties.
+static OWN_DISPLAYS: Lazy<Mutex<HashMap<usize, Option<u8>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+type ErrorHandler = Option<unsafe extern "C" fn(*mut Display, *mut XErrorEvent) -> c_int>;
+
+static PREVIOUS_ERROR_HANDLER: OnceCell<ErrorHandler> = OnceCell::new();
+
+unsafe extern "C" fn error_handler(display: *mut Display, event: *mut XErrorEvent) -> c_int {
+    let is_own = OWN_DISPLAYS
+        .lock()
+        .ok()
+        .and_then(|mut displays| {
+            let last_error = displays.get_mut(&(display as usize))?;
+            *last_error = Some((*event).error_code);
+            Some(())
+        })
+        .is_some();
+    if is_own {
+        log::debug!(
+            "Ignoring X error {} of request {}",
+            (*event).error_code,
+            (*event).request_code
+        );
+        return 0;
+    }
+    // The default handler exits the process, keep that for the connections
+    // of the toolkit.
+    match PREVIOUS_ERROR_HANDLER.get().copied().flatten() {
+        Some(previous) => previous(display, event),
+        None => 0,
+    }
+}
+
+/// Opens a connection whose errors are logged instead of exiting.
+pub(super) unsafe fn open_display() -> Option<*mut Display> {
+    PREVIOUS_ERROR_HANDLER.get_or_init(|| xlib::XSetErrorHandler(Some(error_handler)));
+    let display = xlib::XOpenDisplay(ptr::null());
+    if display.is_null() {
+        return None;
+    }
+    OWN_DISPLAYS.lock().unwrap().insert(display as usize, None);
+    Some(display)
+}
+
+pub(super) unsafe fn close_display(display: *mut Display) {
+    OWN_DISPLAYS.lock().unwrap().remove(&(display as usize));
+    xlib::XCloseDisplay(display);
+}
+
+/// The error code of the last failed request on a connection opened by
+/// `open_display`, once `XSync` made sure it arrived.
+pub(super) fn take_error(display: *mut Display) -> Option<u8> {
+    OWN_DISPLAYS
+        .lock()
+        .unwrap()
+        .get_mut(&(display as usize))
+        .and_then(Option::take)
+}
+
+unsafe fn intern_atom(display: *mut Display, name: &str) -> Atom {
+    let name = CString::new(name).unwrap();
+    xlib::XInternAtom(display, name.as_ptr(), xlib::False)
+}
+
+/// Reads the first item of a 32-bit property, which Xlib stores as a long.
+unsafe fn get_long_property(
+    display: *mut Display,
+    window: Window,
+    property: Atom,
+    property_type: Atom,
+) -> Option<c_ulong> {
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut item_count = 0;
+    let mut bytes_after = 0;
+    let mut data: *mut c_uchar = ptr::null_mut();
+    let status = xlib::XGetWindowProperty(
+        display,
+        window,
+        property,
+        0,
+        1,
+        xlib::False,
+        property_type,
+        &mut actual_type,
+        &mut actual_format,
+        &mut item_count,
+        &mut bytes_after,
+        &mut data,
+    );
+    if status != xlib::Success as c_int || data.is_null() {
+        return None;
+    }
+    let value = (actual_type == property_type && actual_format == 32 && item_count > 0)
+        .then(|| *(data as *const c_ulong));
+    xlib::XFree(data as *mut _);
+    value
+}
+
+unsafe fn get_class(display: *mut Display, window: Window) -> Option<String> {
+    let mut hint = xlib::XClassHint {
+        res_name: ptr::null_mut(),
+        res_class: ptr::null_mut(),
+    };
+    if xlib::XGetClassHint(display, window, &mut hint) == 0 {
+        return None;
+    }
+    let class = (!hint.res_class.is_null()).then(|| {
+        CStr::from_ptr(hint.res_class)
+            .to_string_lossy()
+            .into_owned()
+    });
+    for name in [hint.res_name, hint.res_class] {
+        if !name.is_null() {
+            xlib::XFree(name as *mut _);
+        }
+    }
+    class.filter(|class| !class.is_empty())
+}
+
+fn exe_of_pid(pid: c_ulong) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{pid}/exe")).ok()
+}
+
+/// A connection to query the active window on, shared by the callers.
+struct Query {
+    display: *mut Display,
+    net_active_window: Atom,
+    net_wm_pid: Atom,
+}
+
+// The display is only used behind the mutex below.
+unsafe impl Send for Query {}
+
+static QUERY: Lazy<Mutex<Option<Query>>> = Lazy::new(|| {
+    Mutex::new(unsafe {
+        open_display().map(|display| Query {
+            display,
+            net_active_window: intern_atom(display, "_NET_ACTIVE_WINDOW"),
+            net_wm_pid: intern_atom(display, "_NET_WM_PID"),
+        })
+    })
+});
+
+/// The window that has the focus, or `None` without an X server or an
+/// EWMH window manager to tell.
+pub fn active_window() -> Option<ActiveWindow> {
+    let query = QUERY.lock().unwrap();
+    let query = query.as_ref()?;
+    unsafe {
+        let root = xlib::XDefaultRootWindow(query.display);
+        let window = get_long_property(
+            query.display,
+            root,
+            query.net_active_window,
+            xlib::XA_WINDOW,
+        )
+        .filter(|&window| window != 0)?;
+        let pid = get_long_property(query.display, window, query.net_wm_pid, xlib::XA_CARDINAL);
+        Some(ActiveWindow {
+            class: get_class(query.display, window),
+            exe: pid.and_then(exe_of_pid),
+        })
+    }
+}
+
+/// Calls `callback` on a thread of its own every time the window manager
+/// moves the focus to another window.
+pub fn watch_active_window<F>(callback: F) -> Result<(), String>
+where
+    F: Fn() + Send + 'static,
+{
+    let display = unsafe { open_display() }.ok_or("cannot open the X display")?;
+    // The display pointer is not `Send`, but the thread is its only user.
+    let display = display as usize;
+    thread::spawn(move || unsafe {
+        let display = display as *mut Display;
+        let net_active_window = intern_atom(display, "_NET_ACTIVE_WINDOW");
+        let root = xlib::XDefaultRootWindow(display);
+        xlib::XSelectInput(display, root, xlib::PropertyChangeMask);
+        let mut event: xlib::XEvent = std::mem::zeroed();
+        loop {
+            xlib::XNextEvent(display, &mut event);
+            if event.get_type() == xlib::PropertyNotify && event.property.atom == net_active_window
+            {
+                callback();
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Plays the window manager's part: maps a window with a class and our
+/// pid, then makes it the active window. Needs an X server, such as Xvfb.
+#[test]
+fn test_active_window() {
+    use std::os::raw::c_long;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    if std::env::var_os("DISPLAY").is_none() {
+        return;
+    }
+    let (sender, receiver) = mpsc::channel();
+    watch_active_window(move || {
+        let _ = sender.send(());
+    })
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    unsafe {
+        let display = xlib::XOpenDisplay(ptr::null());
+        let root = xlib::XDefaultRootWindow(display);
+        let window = xlib::XCreateSimpleWindow(display, root, 0, 0, 10, 10, 0, 0, 0);
+        let name = CString::new("goxkey-test").unwrap();
+        let class = CString::new("GoxkeyTest").unwrap();
+        let mut hint = xlib::XClassHint {
+            res_name: name.as_ptr() as *mut _,
+            res_class: class.as_ptr() as *mut _,
+        };
+        xlib::XSetClassHint(display, window, &mut hint);
+        let pid = std::process::id() as c_long;
+        xlib::XChangeProperty(
+            display,
+            window,
+            intern_atom(display, "_NET_WM_PID"),
+            xlib::XA_CARDINAL,
+            32,
+            xlib::PropModeReplace,
+            &pid as *const c_long as *const c_uchar,
+            1,
+        );
+        xlib::XMapWindow(display, window);
+        let active = window as c_long;
+        xlib::XChangeProperty(
+            display,
+            root,
+            intern_atom(display, "_NET_ACTIVE_WINDOW"),
+            xlib::XA_WINDOW,
+            32,
+            xlib::PropModeReplace,
+            &active as *const c_long as *const c_uchar,
+            1,
+        );
+        xlib::XSync(display, xlib::False);
+
+        receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            active_window(),
+            Some(ActiveWindow {
+                class: Some("GoxkeyTest".to_string()),
+                exe: std::env::current_exe().ok(),
+            })
+        );
+
+        // The window is gone but still the active one: its properties
+        // cannot be read anymore, which must not end the process.
+        xlib::XDestroyWindow(display, window);
+        xlib::XSync(display, xlib::False);
+        assert_eq!(
+            active_window(),
+            Some(ActiveWindow {
+                class: None,
+                exe: None
+            })
+        );
+        xlib::XCloseDisplay(display);
+    }
+}
@@ -0,0 +1,675 @@
+#![allow(non_upper_case_globals)]
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::ffi::{c_char, CStr, CString};
+use std::os::raw::{c_int, c_uint};
+use std::ptr;
+
+use x11::keysym::*;
+use x11::xinput;
+use x11::xlib::{self, Display, KeyCode, KeySym};
+use x11::xrecord;
+use x11::xtest;
+
+use super::super::{
+    CallbackFn, EventTapType, KeyEvent, KeyModifier, ModifierKeys, PressedKey, KEY_DELETE,
+    KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB, RAW_ARROW_DOWN, RAW_ARROW_LEFT, RAW_ARROW_RIGHT,
+    RAW_ARROW_UP, RAW_KEYPAD_DIGITS,
+};
+use super::Handle;
+
+/// X keycodes are in the same range as the macOS ones the engine knows,
+/// like the globe key. Keys only known by their keycode are reported past
+/// that range so they are never mistaken for one of those.
+const RAW_KEYCODE_OFFSET: u16 = 0x100;
+
+/// The key and button device events of XInput 1 from its first event, the
+/// press and release of keys and the press of buttons.
+const DEVICE_KEY_PRESS: c_int = 1;
+const DEVICE_BUTTON_PRESS: c_int = 3;
+
+/// The bit of the device of an XInput 1 event telling more events follow.
+const MORE_EVENTS: u8 = 0x80;
+
+/// An edit to type into the focused window.
+enum Injection {
+    Backspace(usize),
+    Text(String),
+}
+
+/// Types the edits of the engine with XTest.
+///
+/// XRecord only lets us watch the key events, not block them: by the time
+/// the engine sees a key, the focused window already got it. So the edits
+/// requested while handling a key are queued, and only typed once we know
+/// whether the key had to be blocked, in which case it is erased first.
+pub struct Injector {
+    display: *mut Display,
+    pending: RefCell<Vec<Injection>>,
+    /// The input devices XTest types on, whose keys are not the user's, so
+    /// they never reach the engine.
+    xtest_devices: Vec<u8>,
+    /// Whether the last device event came from XTest. The server records
+    /// it just before the core event of the same key.
+    is_injected: Cell<bool>,
+    /// Keycodes with no symbols in the keyboard mapping, used to type the
+    /// characters the layout has no key for.
+    spare_keycodes: Vec<KeyCode>,
+    next_spare: Cell<usize>,
+    /// The spare keycodes bound by the last edits, unbound again by the
+    /// next ones, once the window has read them.
+    mapped_spares: RefCell<Vec<KeyCode>>,
+}
+
+impl Injector {
+    fn new(display: *mut Display, xtest_devices: Vec<u8>) -> Self {
+        Self {
+            display,
+            pending: RefCell::new(Vec::new()),
+            xtest_devices,
+            is_injected: Cell::new(false),
+            spare_keycodes: unsafe { spare_keycodes(display) },
+            next_spare: Cell::new(0),
+            mapped_spares: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn queue_backspace(&self, count: usize) {
+        self.pending.borrow_mut().push(Injection::Backspace(count));
+    }
+
+    pub fn queue_string(&self, string: &str) {
+        self.pending
+            .borrow_mut()
+            .push(Injection::Text(string.to_string()));
+    }
+
+    /// Types the queued edits, after erasing the key being handled when the
+    /// engine asked to block it.
+    fn flush(&self, erase_key: bool) {
+        let pending = self.pending.take();
+        unsafe {
+            self.unmap_spare_keycodes();
+            if erase_key {
+                self.tap_keysym(XK_BackSpace as KeySym);
+            }
+            for injection in pending {
+                match injection {
+                    Injection::Backspace(count) => {
+                        for _ in 0..count {
+                            self.tap_keysym(XK_BackSpace as KeySym);
+                        }
+                    }
+                    Injection::Text(text) => {
+                        for c in text.chars() {
+                            self.tap_keysym(char_to_keysym(c));
+                        }
+                    }
+                }
+            }
+            xlib::XSync(self.display, xlib::False);
+        }
+    }
+
+    unsafe fn tap_keysym(&self, keysym: KeySym) {
+        let mut keycode = xlib::XKeysymToKeycode(self.display, keysym);
+        if keycode == 0 || xlib::XkbKeycodeToKeysym(self.display, keycode, 0, 0) != keysym {
+            let Some(spare) = self.map_spare_keycode(keysym) else {
+                log::warn!("No spare keycode to type keysym {keysym:#x}");
+                return;
+            };
+            keycode = spare;
+        }
+        xtest::XTestFakeKeyEvent(self.display, keycode as c_uint, xlib::True, 0);
+        xtest::XTestFakeKeyEvent(self.display, keycode as c_uint, xlib::False, 0);
+        xlib::XFlush(self.display);
+    }
+
+    /// Binds `keysym` to one of the spare keycodes, using them in turn so a
+    /// key typed just before is not remapped before the window reads it.
+    unsafe fn map_spare_keycode(&self, keysym: KeySym) -> Option<KeyCode> {
+        let index = self.next_spare.get();
+        let keycode = *self.spare_keycodes.get(index)?;
+        self.next_spare.set((index + 1) % self.spare_keycodes.len());
+        // Same symbol with and without shift, in case the user holds it.
+        let mut keysyms = [keysym, keysym];
+        xlib::XChangeKeyboardMapping(
+            self.display,
+            keycode as c_int,
+            keysyms.len() as c_int,
+            keysyms.as_mut_ptr(),
+            1,
+        );
+        xlib::XSync(self.display, xlib::False);
+        let mut mapped_spares = self.mapped_spares.borrow_mut();
+        if !mapped_spares.contains(&keycode) {
+            mapped_spares.push(keycode);
+        }
+        Some(keycode)
+    }
+
+    /// Leaves the spare keycodes bound so far without symbols again, as
+    /// other programs expect to find them.
+    unsafe fn unmap_spare_keycodes(&self) {
+        let mapped_spares = self.mapped_spares.take();
+        if mapped_spares.is_empty() {
+            return;
+        }
+        for keycode in mapped_spares {
+            let mut keysyms = [0 as KeySym];
+            xlib::XChangeKeyboardMapping(
+                self.display,
+                keycode as c_int,
+                keysyms.len() as c_int,
+                keysyms.as_mut_ptr(),
+                1,
+            );
+        }
+        xlib::XSync(self.display, xlib::False);
+    }
+
+    /// Notes the input device of the device event XRecord reported.
+    fn on_device_event(&self, device: u8) {
+        self.is_injected
+            .set(self.xtest_devices.contains(&(device & !MORE_EVENTS)));
+    }
+
+    /// Whether the key or button reported by XRecord was typed by us.
+    fn is_injected(&self) -> bool {
+        self.is_injected.get()
+    }
+}
+
+impl Drop for Injector {
+    fn drop(&mut self) {
+        unsafe { self.unmap_spare_keycodes() };
+    }
+}
+
+/// The first event of XInput, and the ids of the devices XTest types on,
+/// named like `Virtual core XTEST keyboard`.
+unsafe fn xtest_devices(display: *mut Display) -> Result<(c_int, Vec<u8>), String> {
+    let name = CString::new("XInputExtension").unwrap();
+    let (mut opcode, mut event_base, mut error_base) = (0, 0, 0);
+    if xlib::XQueryExtension(
+        display,
+        name.as_ptr(),
+        &mut opcode,
+        &mut event_base,
+        &mut error_base,
+    ) == 0
+    {
+        return Err("the X server does not support the XInput extension".to_string());
+    }
+    let mut count = 0;
+    let devices = xinput::XListInputDevices(display, &mut count);
+    if devices.is_null() {
+        return Err("cannot list the X input devices".to_string());
+    }
+    let ids: Vec<u8> = std::slice::from_raw_parts(devices, count as usize)
+        .iter()
+        .filter(|device| {
+            !device.name.is_null()
+                && CStr::from_ptr(device.name)
+                    .to_string_lossy()
+                    .contains(" XTEST ")
+        })
+        .map(|device| device.id as u8)
+        .collect();
+    xinput::XFreeDeviceList(devices);
+    if ids.is_empty() {
+        return Err("cannot find the XTEST input devices".to_string());
+    }
+    Ok((event_base, ids))
+}
+
+unsafe fn spare_keycodes(display: *mut Display) -> Vec<KeyCode> {
+    let (mut min, mut max, mut per_keycode) = (0, 0, 0);
+    xlib::XDisplayKeycodes(display, &mut min, &mut max);
+    let count = max - min + 1;
+    let keysyms = xlib::XGetKeyboardMapping(display, min as KeyCode, count, &mut per_keycode);
+    if keysyms.is_null() {
+        return Vec::new();
+    }
+    let mapping = std::slice::from_raw_parts(keysyms, (count * per_keycode) as usize);
+    let spare = mapping
+        .chunks(per_keycode as usize)
+        .enumerate()
+        .filter(|(_, symbols)| symbols.iter().all(|&keysym| keysym == 0))
+        .map(|(index, _)| (min + index as c_int) as KeyCode)
+        .collect();
+    xlib::XFree(keysyms as *mut _);
+    spare
+}
+
+fn char_to_keysym(c: char) -> KeySym {
+    match c {
+        '\n' => XK_Return as KeySym,
+        '\t' => XK_Tab as KeySym,
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as KeySym,
+        c => 0x0100_0000 | c as KeySym,
+    }
+}
+
+/// Maps the keysym of a key, without modifiers, to what the engine expects.
+pub(super) fn keysym_to_pressed_key(keysym: KeySym, keycode: u32) -> PressedKey {
+    match keysym as c_uint {
+        XK_Return | XK_KP_Enter => PressedKey::Char(KEY_ENTER),
+        XK_space => PressedKey::Char(KEY_SPACE),
+        XK_Tab | XK_ISO_Left_Tab => PressedKey::Char(KEY_TAB),
+        XK_BackSpace => PressedKey::Char(KEY_DELETE),
+        XK_Escape => PressedKey::Char(KEY_ESCAPE),
+        XK_Left => PressedKey::Raw(RAW_ARROW_LEFT),
+        XK_Right => PressedKey::Raw(RAW_ARROW_RIGHT),
+        XK_Up => PressedKey::Raw(RAW_ARROW_UP),
+        XK_Down => PressedKey::Raw(RAW_ARROW_DOWN),
+        XK_KP_0..=XK_KP_9 => {
+            PressedKey::Raw(RAW_KEYPAD_DIGITS[(keysym as c_uint - XK_KP_0) as usize])
+        }
+        _ => keysym_to_char(keysym)
+            .map(PressedKey::Char)
+            .unwrap_or(PressedKey::Raw(RAW_KEYCODE_OFFSET + keycode as u16)),
+    }
+}
+
+/// Whether a keysym is one of the numeric keypad, whose keys only type
+/// digits with Num Lock on.
+pub(super) fn is_keypad_keysym(keysym: KeySym) -> bool {
+    (XK_KP_Space as KeySym..=XK_KP_Equal as KeySym).contains(&keysym)
+}
+
+pub(super) fn keysym_to_char(keysym: KeySym) -> Option<char> {
+    match keysym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(keysym as u32),
+        0x0100_0000..=0x0110_ffff => char::from_u32((keysym - 0x0100_0000) as u32),
+        _ => None,
+    }
+}
+
+/// The modifier a key sets, and whether it is a lock that toggles on press.
+pub(super) fn keysym_to_modifier(keysym: KeySym) -> Option<(KeyModifier, bool)> {
+    match keysym as c_uint {
+        XK_Shift_L | XK_Shift_R => Some((KeyModifier::MODIFIER_SHIFT, false)),
+        XK_Control_L | XK_Control_R => Some((KeyModifier::MODIFIER_CONTROL, false)),
+        XK_Alt_L | XK_Alt_R | XK_Meta_L | XK_Meta_R | XK_ISO_Level3_Shift => {
+            Some((KeyModifier::MODIFIER_ALT, false))
+        }
+        XK_Super_L | XK_Super_R | XK_Hyper_L | XK_Hyper_R => {
+            Some((KeyModifier::MODIFIER_SUPER, false))
+        }
+        XK_Caps_Lock => Some((KeyModifier::MODIFIER_CAPSLOCK, true)),
+        _ => None,
+    }
+}
+
+/// The modifier key a keysym is the one of, on its side of the keyboard.
+pub(super) fn keysym_to_modifier_key(keysym: KeySym) -> Option<ModifierKeys> {
+    match keysym as c_uint {
+        XK_Shift_L => Some(ModifierKeys::LEFT_SHIFT),
+        XK_Shift_R => Some(ModifierKeys::RIGHT_SHIFT),
+        XK_Control_L => Some(ModifierKeys::LEFT_CONTROL),
+        XK_Control_R => Some(ModifierKeys::RIGHT_CONTROL),
+        XK_Alt_L | XK_Meta_L => Some(ModifierKeys::LEFT_ALT),
+        XK_Alt_R | XK_Meta_R | XK_ISO_Level3_Shift => Some(ModifierKeys::RIGHT_ALT),
+        XK_Super_L | XK_Hyper_L => Some(ModifierKeys::LEFT_SUPER),
+        XK_Super_R | XK_Hyper_R => Some(ModifierKeys::RIGHT_SUPER),
+        _ => None,
+    }
+}
+
+struct Listener<'a> {
+    callback: &'a CallbackFn,
+    injector: Injector,
+    /// The first event of XInput, which its device events follow.
+    device_event_base: c_int,
+    modifiers: Cell<KeyModifier>,
+    modifier_keys: Cell<ModifierKeys>,
+    /// The keys held down, whose next presses are repeats.
+    held_keys: RefCell<HashSet<KeyCode>>,
+    /// The last key released and when. Servers may send a release before
+    /// each repeat too, at the time of the repeat.
+    last_release: Cell<Option<(KeyCode, u32)>>,
+}
+
+impl Listener<'_> {
+    fn handle(&self) -> Handle {
+        Handle::X11(&self.injector)
+    }
+
+    /// `state` is the core state of the event, whose bits 13 and 14 are
+    /// the group of the layout the key was typed in, and `time` the one of
+    /// the server in milliseconds.
+    unsafe fn on_event(&self, event_type: c_int, keycode: KeyCode, state: u16, time: u32) {
+        if self.injector.is_injected() {
+            return;
+        }
+        if event_type == xlib::ButtonPress {
+            let event = KeyEvent {
+                timestamp: time.into(),
+                ..KeyEvent::new(EventTapType::Other, None, KeyModifier::new())
+            };
+            (self.callback)(self.handle(), &event);
+            self.injector.flush(false);
+            return;
+        }
+        let is_press = event_type == xlib::KeyPress;
+        let is_repeat = if is_press {
+            let is_held = !self.held_keys.borrow_mut().insert(keycode);
+            is_held || self.last_release.get() == Some((keycode, time))
+        } else {
+            self.held_keys.borrow_mut().remove(&keycode);
+            self.last_release.set(Some((keycode, time)));
+            false
+        };
+        let group = c_int::from((state >> 13) & 0x3);
+        let keysym = match xlib::XkbKeycodeToKeysym(self.injector.display, keycode, group, 0) {
+            // Keys like Return only have the first group.
+            0 => xlib::XkbKeycodeToKeysym(self.injector.display, keycode, 0, 0),
+            keysym => keysym,
+        };
+        // Num Lock picks the level of the keypad keys, so read them with
+        // the state.
+        let (mut keypad_keysym, mut consumed) = (0, 0);
+        let keysym = if is_keypad_keysym(keysym)
+            && xlib::XkbLookupKeySym(
+                self.injector.display,
+                keycode,
+                state.into(),
+                &mut consumed,
+                &mut keypad_keysym,
+            ) != 0
+        {
+            keypad_keysym
+        } else {
+            keysym
+        };
+        if let Some((modifier, is_lock)) = keysym_to_modifier(keysym) {
+            let mut modifiers = self.modifiers.get();
+            match (event_type, is_lock) {
+                (xlib::KeyPress, true) => modifiers.toggle(modifier),
+                (xlib::KeyPress, false) => modifiers.insert(modifier),
+                (_, false) => modifiers.remove(modifier),
+                _ => return,
+            }
+            self.modifiers.set(modifiers);
+            let mut modifier_keys = self.modifier_keys.get();
+            if let Some(modifier_key) = keysym_to_modifier_key(keysym) {
+                modifier_keys.set(modifier_key, is_press);
+            }
+            self.modifier_keys.set(modifier_keys);
+            let event = KeyEvent {
+                modifier_keys,
+                keycode: Some(keycode.into()),
+                is_repeat,
+                timestamp: time.into(),
+                ..KeyEvent::new(EventTapType::FlagsChanged, None, modifiers)
+            };
+            (self.callback)(self.handle(), &event);
+            self.injector.flush(false);
+            return;
+        }
+        let modifiers = self.modifiers.get();
+        let pressed_key = keysym_to_pressed_key(keysym, keycode.into());
+        let event_type = if is_press {
+            EventTapType::KeyDown
+        } else {
+            EventTapType::KeyUp
+        };
+        let event = KeyEvent {
+            modifier_keys: self.modifier_keys.get(),
+            keycode: Some(keycode.into()),
+            is_repeat,
+            timestamp: time.into(),
+            ..KeyEvent::new(event_type, Some(pressed_key), modifiers)
+        };
+        let blocked = (self.callback)(self.handle(), &event);
+        // Only a key that typed a character left something to erase; a
+        // blocked shortcut like the toggle hotkey typed nothing.
+        let typed_text = is_press
+            && (matches!(pressed_key, PressedKey::Char(c) if !c.is_control())
+                || pressed_key.keypad_digit().is_some())
+            && !modifiers.intersects(
+                KeyModifier::MODIFIER_CONTROL
+                    | KeyModifier::MODIFIER_SUPER
+                    | KeyModifier::MODIFIER_ALT,
+            );
+        self.injector.flush(blocked && typed_text);
+    }
+}
+
+unsafe extern "C" fn record_callback(
+    closure: *mut c_char,
+    data: *mut xrecord::XRecordInterceptData,
+) {
+    let listener = &*(closure as *const Listener);
+    let intercepted = &*data;
+    if intercepted.category == xrecord::XRecordFromServer && intercepted.data_len > 0 {
+        // The data is a raw protocol event of 32 bytes: its type, then its
+        // detail, which is the keycode for key events, the time at byte 4
+        // and the state at byte 28. Device events end with their device.
+        let event = std::slice::from_raw_parts(intercepted.data, intercepted.data_len as usize * 4);
+        if event.len() >= 32 {
+            let event_type = (event[0] & 0x7f) as c_int;
+            let device_event = event_type - listener.device_event_base;
+            if (DEVICE_KEY_PRESS..=DEVICE_BUTTON_PRESS).contains(&device_event) {
+                listener.injector.on_device_event(event[31]);
+            } else {
+                let time = u32::from_ne_bytes([event[4], event[5], event[6], event[7]]);
+                let state = u16::from_ne_bytes([event[28], event[29]]);
+                listener.on_event(event_type, event[1], state, time);
+            }
+        }
+    }
+    xrecord::XRecordFreeData(data);
+}
+
+/// Watches the keyboard and mouse of the X server with XRecord, and types
+/// the edits of `callback` with XTest. Blocks until the connection closes.
+pub fn run_event_listener(callback: &CallbackFn) -> Result<(), String> {
+    unsafe {
+        // XRecord needs a connection of its own to deliver events on, and
+        // another one to set it up and to type with.
+        let control = xlib::XOpenDisplay(ptr::null());
+        let data = xlib::XOpenDisplay(ptr::null());
+        if control.is_null() || data.is_null() {
+            return Err("cannot open the X display".to_string());
+        }
+        let (mut major, mut minor) = (0, 0);
+        if xrecord::XRecordQueryVersion(control, &mut major, &mut minor) == 0 {
+            return Err("the X server does not support the RECORD extension".to_string());
+        }
+        let (mut event_base, mut error_base) = (0, 0);
+        if xtest::XTestQueryExtension(
+            control,
+            &mut event_base,
+            &mut error_base,
+            &mut major,
+            &mut minor,
+        ) == 0
+        {
+            return Err("the X server does not support the XTEST extension".to_string());
+        }
+        let (device_event_base, xtest_devices) = xtest_devices(control)?;
+        xlib::XSynchronize(control, xlib::True);
+
+        // The device events tell the keys we typed apart: the server
+        // records the one of the device a key came from before its core
+        // event, and XTest types on devices of its own.
+        let range = xrecord::XRecordAllocRange();
+        (*range).device_events.first = xlib::KeyPress as u8;
+        (*range).device_events.last = xlib::ButtonPress as u8;
+        let device_range = xrecord::XRecordAllocRange();
+        (*device_range).device_events.first = (device_event_base + DEVICE_KEY_PRESS) as u8;
+        (*device_range).device_events.last = (device_event_base + DEVICE_BUTTON_PRESS) as u8;
+        let mut ranges = [range, device_range];
+        let mut clients = [xrecord::XRecordAllClients];
+        let context = xrecord::XRecordCreateContext(
+            control,
+            0,
+            clients.as_mut_ptr(),
+            clients.len() as c_int,
+            ranges.as_mut_ptr(),
+            ranges.len() as c_int,
+        );
+        xlib::XFree(range as *mut _);
+        xlib::XFree(device_range as *mut _);
+        if context == 0 {
+            return Err("cannot create an XRecord context".to_string());
+        }
+
+        let listener = Listener {
+            callback,
+            injector: Injector::new(control, xtest_devices),
+            device_event_base,
+            modifiers: Cell::new(KeyModifier::new()),
+            modifier_keys: Cell::new(ModifierKeys::empty()),
+            held_keys: RefCell::new(HashSet::new()),
+            last_release: Cell::new(None),
+        };
+        let status = xrecord::XRecordEnableContext(
+            data,
+            context,
+            Some(record_callback),
+            &listener as *const Listener as *mut c_char,
+        );
+        xrecord::XRecordFreeContext(control, context);
+        // The injector unbinds the spare keycodes on the display first.
+        drop(listener);
+        xlib::XCloseDisplay(data);
+        xlib::XCloseDisplay(control);
+        if status == 0 {
+            return Err("cannot enable the XRecord context".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_keysym_to_pressed_key() {
+    assert_eq!(
+        keysym_to_pressed_key(XK_a as KeySym, 38),
+        PressedKey::Char('a')
+    );
+    assert_eq!(
+        keysym_to_pressed_key(XK_Return as KeySym, 36),
+        PressedKey::Char(KEY_ENTER)
+    );
+    assert_eq!(
+        keysym_to_pressed_key(XK_Left as KeySym, 113),
+        PressedKey::Raw(RAW_ARROW_LEFT)
+    );
+    assert_eq!(
+        keysym_to_pressed_key(0x0100_1ec7, 10),
+        PressedKey::Char('ệ')
+    );
+    assert_eq!(
+        keysym_to_pressed_key(XK_F1 as KeySym, 67),
+        PressedKey::Raw(RAW_KEYCODE_OFFSET + 67)
+    );
+    assert_eq!(
+        keysym_to_pressed_key(XK_KP_7 as KeySym, 79).keypad_digit(),
+        Some('7')
+    );
+    assert_eq!(
+        keysym_to_pressed_key(XK_KP_Home as KeySym, 79),
+        PressedKey::Raw(RAW_KEYCODE_OFFSET + 79)
+    );
+}
+
+#[test]
+fn test_keysym_to_modifier_key() {
+    assert_eq!(
+        keysym_to_modifier_key(XK_Shift_R as KeySym),
+        Some(ModifierKeys::RIGHT_SHIFT)
+    );
+    assert_eq!(
+        keysym_to_modifier_key(XK_ISO_Level3_Shift as KeySym),
+        Some(ModifierKeys::RIGHT_ALT)
+    );
+    assert_eq!(keysym_to_modifier_key(XK_Caps_Lock as KeySym), None);
+}
+
+#[test]
+fn test_char_to_keysym() {
+    for c in ['a', 'Z', '~', 'đ', 'ệ', 'é'] {
+        assert_eq!(keysym_to_char(char_to_keysym(c)), Some(c));
+    }
+    assert_eq!(char_to_keysym('\n'), XK_Return as KeySym);
+}
+
+/// Types `a`, then `s`, which the callback answers by typing `x`: only the
+/// user's keys must reach the callback. Needs an X server, such as Xvfb,
+/// with a keyboard of its own to type the user's keys on.
+#[test]
+fn test_listener_ignores_injected_keys() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// The use of the keyboards that are not the core one in XInput 1.
+    const IS_X_EXTENSION_KEYBOARD: c_int = 3;
+
+    if std::env::var_os("DISPLAY").is_none() {
+        return;
+    }
+    let display = unsafe { xlib::XOpenDisplay(ptr::null()) };
+    let keyboard = unsafe {
+        let mut count = 0;
+        let devices = xinput::XListInputDevices(display, &mut count);
+        let keyboard = std::slice::from_raw_parts(devices, count as usize)
+            .iter()
+            .find(|device| {
+                device.use_ == IS_X_EXTENSION_KEYBOARD
+                    && !CStr::from_ptr(device.name)
+                        .to_string_lossy()
+                        .contains(" XTEST ")
+            })
+            .map(|device| xinput::XOpenDevice(display, device.id));
+        xinput::XFreeDeviceList(devices);
+        keyboard
+    };
+    let Some(keyboard) = keyboard.filter(|keyboard| !keyboard.is_null()) else {
+        return;
+    };
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let callback = move |handle: Handle, event: &KeyEvent| {
+            if let (EventTapType::KeyDown, Some(PressedKey::Char(c))) =
+                (event.event_type, event.key)
+            {
+                sender.send(c).unwrap();
+                if c == 's' {
+                    super::send_string(handle, "x").unwrap();
+                }
+            }
+            false
+        };
+        run_event_listener(&callback).unwrap();
+    });
+    std::thread::sleep(Duration::from_millis(300));
+    unsafe {
+        for keysym in [XK_a, XK_s] {
+            let keycode = xlib::XKeysymToKeycode(display, keysym as KeySym) as c_uint;
+            for is_press in [xlib::True, xlib::False] {
+                xtest::XTestFakeDeviceKeyEvent(
+                    display,
+                    keyboard,
+                    keycode,
+                    is_press,
+                    ptr::null_mut(),
+                    0,
+                    0,
+                );
+            }
+        }
+        xlib::XSync(display, xlib::False);
+        xinput::XCloseDevice(display, keyboard);
+        xlib::XCloseDisplay(display);
+    }
+    let typed: Vec<char> = receiver.iter().take(2).collect();
+    assert_eq!(typed, vec!['a', 's']);
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_millis(300)),
+        Err(mpsc::RecvTimeoutError::Timeout)
+    );
+}
@@ -18,29 +18,36 @@ use core_graphics::{
     },
     sys,
 };
+use foreign_types::ForeignType;
 use objc::{class, msg_send, sel, sel_impl};
 
 pub use macos_ext::SystemTray;
 pub use macos_ext::SystemTrayMenuItemKey;
 use once_cell::sync::Lazy;
 
-use crate::input::KEYBOARD_LAYOUT_CHARACTER_MAP;
+use crate::input::INPUT_STATE;
+use crate::layout::{KeyboardLayout, Level};
+use rdev::{Keyboard, KeyboardState};
 use accessibility::{AXAttribute, AXUIElement};
 use accessibility_sys::{kAXFocusedUIElementAttribute, kAXSelectedTextAttribute};
 use core_foundation::{
+    array::{CFArray, CFArrayRef},
+    base::{CFRelease, TCFType},
     runloop::{kCFRunLoopCommonModes, CFRunLoop},
-    string::CFString,
+    string::{CFString, CFStringRef},
 };
 
 pub use self::macos_ext::Handle;
 use self::macos_ext::{
-    kAXTrustedCheckOptionPrompt, new_tap, AXIsProcessTrustedWithOptions,
-    CGEventCreateKeyboardEvent, CGEventKeyboardSetUnicodeString, CGEventTapPostEvent,
+    kAXTrustedCheckOptionPrompt, kTISPropertyInputSourceLanguages, kTISPropertyLocalizedName,
+    new_tap, AXIsProcessTrustedWithOptions, CGEventCreateKeyboardEvent, CGEventGetTimestamp,
+    CGEventKeyboardSetUnicodeString, CGEventTapPostEvent, TISCopyCurrentKeyboardInputSource,
+    TISGetInputSourceProperty,
 };
 
 use super::{
-    CallbackFn, EventTapType, KeyModifier, PressedKey, KEY_DELETE, KEY_ENTER, KEY_ESCAPE,
-    KEY_SPACE, KEY_TAB,
+    CallbackFn, EventTapType, KeyEvent, KeyModifier, ModifierKeys, PressedKey, KEY_DELETE,
+    KEY_ENTER, KEY_ESCAPE, KEY_SPACE, KEY_TAB,
 };
 
 pub const SYMBOL_SHIFT: &str = "⇧";
@@ -54,10 +61,24 @@ const CARBON_SHIFT: u32 = 1 << 9;
 const CARBON_OPTION: u32 = 1 << 11;
 const CARBON_CONTROL: u32 = 1 << 12;
 
+/// The device dependent bits of the event flags, which tell the left and
+/// right modifier keys apart.
+const DEVICE_MODIFIER_KEYS: [(u64, ModifierKeys); 8] = [
+    (0x0001, ModifierKeys::LEFT_CONTROL),
+    (0x0002, ModifierKeys::LEFT_SHIFT),
+    (0x0004, ModifierKeys::RIGHT_SHIFT),
+    (0x0008, ModifierKeys::LEFT_SUPER),
+    (0x0010, ModifierKeys::RIGHT_SUPER),
+    (0x0020, ModifierKeys::LEFT_ALT),
+    (0x0040, ModifierKeys::RIGHT_ALT),
+    (0x2000, ModifierKeys::RIGHT_CONTROL),
+];
+
 impl From<CGEventType> for EventTapType {
     fn from(value: CGEventType) -> Self {
         match value {
             CGEventType::KeyDown => EventTapType::KeyDown,
+            CGEventType::KeyUp => EventTapType::KeyUp,
             CGEventType::FlagsChanged => EventTapType::FlagsChanged,
             _ => EventTapType::Other,
         }
@@ -113,64 +134,155 @@ pub fn get_home_dir() -> Option<PathBuf> {
 }
 
 // List of keycode: https://eastmanreference.com/complete-list-of-applescript-key-codes
-fn get_char(keycode: CGKeyCode) -> Option<PressedKey> {
-    if let Some(key_map) = unsafe { KEYBOARD_LAYOUT_CHARACTER_MAP.get() } {
-        return match keycode {
-            0 => Some(PressedKey::Char(key_map[&'a'])),
-            1 => Some(PressedKey::Char(key_map[&'s'])),
-            2 => Some(PressedKey::Char(key_map[&'d'])),
-            3 => Some(PressedKey::Char(key_map[&'f'])),
-            4 => Some(PressedKey::Char(key_map[&'h'])),
-            5 => Some(PressedKey::Char(key_map[&'g'])),
-            6 => Some(PressedKey::Char(key_map[&'z'])),
-            7 => Some(PressedKey::Char(key_map[&'x'])),
-            8 => Some(PressedKey::Char(key_map[&'c'])),
-            9 => Some(PressedKey::Char(key_map[&'v'])),
-            11 => Some(PressedKey::Char(key_map[&'b'])),
-            12 => Some(PressedKey::Char(key_map[&'q'])),
-            13 => Some(PressedKey::Char(key_map[&'w'])),
-            14 => Some(PressedKey::Char(key_map[&'e'])),
-            15 => Some(PressedKey::Char(key_map[&'r'])),
-            16 => Some(PressedKey::Char(key_map[&'y'])),
-            17 => Some(PressedKey::Char(key_map[&'t'])),
-            31 => Some(PressedKey::Char(key_map[&'o'])),
-            32 => Some(PressedKey::Char(key_map[&'u'])),
-            34 => Some(PressedKey::Char(key_map[&'i'])),
-            35 => Some(PressedKey::Char(key_map[&'p'])),
-            37 => Some(PressedKey::Char(key_map[&'l'])),
-            38 => Some(PressedKey::Char(key_map[&'j'])),
-            40 => Some(PressedKey::Char(key_map[&'k'])),
-            45 => Some(PressedKey::Char(key_map[&'n'])),
-            46 => Some(PressedKey::Char(key_map[&'m'])),
-            18 => Some(PressedKey::Char(key_map[&'1'])),
-            19 => Some(PressedKey::Char(key_map[&'2'])),
-            20 => Some(PressedKey::Char(key_map[&'3'])),
-            21 => Some(PressedKey::Char(key_map[&'4'])),
-            22 => Some(PressedKey::Char(key_map[&'6'])),
-            23 => Some(PressedKey::Char(key_map[&'5'])),
-            25 => Some(PressedKey::Char(key_map[&'9'])),
-            26 => Some(PressedKey::Char(key_map[&'7'])),
-            28 => Some(PressedKey::Char(key_map[&'8'])),
-            29 => Some(PressedKey::Char(key_map[&'0'])),
-            27 => Some(PressedKey::Char(key_map[&'-'])),
-            33 => Some(PressedKey::Char(key_map[&'['])),
-            30 => Some(PressedKey::Char(key_map[&']'])),
-            41 => Some(PressedKey::Char(key_map[&';'])),
-            43 => Some(PressedKey::Char(key_map[&','])),
-            24 => Some(PressedKey::Char(key_map[&'='])),
-            42 => Some(PressedKey::Char(key_map[&'\\'])),
-            44 => Some(PressedKey::Char(key_map[&'/'])),
-            39 => Some(PressedKey::Char(key_map[&'\''])),
-            47 => Some(PressedKey::Char(key_map[&'.'])),
-            36 | 52 => Some(PressedKey::Char(KEY_ENTER)), // ENTER
-            49 => Some(PressedKey::Char(KEY_SPACE)),      // SPACE
-            48 => Some(PressedKey::Char(KEY_TAB)),        // TAB
-            51 => Some(PressedKey::Char(KEY_DELETE)),     // DELETE
-            53 => Some(PressedKey::Char(KEY_ESCAPE)),     // ESC
-            _ => Some(PressedKey::Raw(keycode)),
+/// The keys that type characters, with the rdev key at the same position.
+const CHARACTER_KEYS: [(CGKeyCode, rdev::Key); 48] = {
+    use rdev::Key::*;
+    [
+        (0, KeyA),
+        (1, KeyS),
+        (2, KeyD),
+        (3, KeyF),
+        (4, KeyH),
+        (5, KeyG),
+        (6, KeyZ),
+        (7, KeyX),
+        (8, KeyC),
+        (9, KeyV),
+        (10, IntlBackslash),
+        (11, KeyB),
+        (12, KeyQ),
+        (13, KeyW),
+        (14, KeyE),
+        (15, KeyR),
+        (16, KeyY),
+        (17, KeyT),
+        (18, Num1),
+        (19, Num2),
+        (20, Num3),
+        (21, Num4),
+        (22, Num6),
+        (23, Num5),
+        (24, Equal),
+        (25, Num9),
+        (26, Num7),
+        (27, Minus),
+        (28, Num8),
+        (29, Num0),
+        (30, RightBracket),
+        (31, KeyO),
+        (32, KeyU),
+        (33, LeftBracket),
+        (34, KeyI),
+        (35, KeyP),
+        (37, KeyL),
+        (38, KeyJ),
+        (39, Quote),
+        (40, KeyK),
+        (41, SemiColon),
+        (42, BackSlash),
+        (43, Comma),
+        (44, Slash),
+        (45, KeyN),
+        (46, KeyM),
+        (47, Dot),
+        (50, BackQuote),
+    ]
+};
+
+/// Reads what the character keys type at each level of the current input
+/// source, with Option as AltGr. Levels that type the same as the base one
+/// are left out.
+pub fn keyboard_layout() -> KeyboardLayout {
+    use rdev::EventType::{KeyPress, KeyRelease};
+    use rdev::Key::{Alt, ShiftLeft};
+
+    let (name, language) = input_source();
+    let mut layout = KeyboardLayout::new(name);
+    if let Some(language) = language {
+        layout.set_language(language);
+    }
+    let Some(mut keyboard) = Keyboard::new() else {
+        return layout;
+    };
+    for (keycode, key) in CHARACTER_KEYS {
+        let mut base = None;
+        for level in Level::ALL {
+            let modifiers: &[rdev::Key] = match level {
+                Level::Base => &[],
+                Level::Shift => &[ShiftLeft],
+                Level::AltGr => &[Alt],
+                Level::ShiftAltGr => &[ShiftLeft, Alt],
+            };
+            for modifier in modifiers {
+                keyboard.add(&KeyPress(*modifier));
+            }
+            let typed = keyboard.add(&KeyPress(key));
+            for modifier in modifiers {
+                keyboard.add(&KeyRelease(*modifier));
+            }
+            // Dead keys would otherwise compose with the next key.
+            keyboard.reset();
+            let Some(c) = typed.and_then(|s| s.chars().last()) else {
+                continue;
+            };
+            if level == Level::Base {
+                base = Some(c);
+            } else if base == Some(c) {
+                continue;
+            }
+            layout.set_key_char(keycode.into(), level, c);
+        }
+    }
+    layout
+}
+
+/// The name and the main language of the selected input source, like
+/// "ABC" and "en".
+fn input_source() -> (String, Option<String>) {
+    unsafe {
+        let source = TISCopyCurrentKeyboardInputSource();
+        if source.is_null() {
+            return (String::new(), None);
+        }
+        let name = TISGetInputSourceProperty(source, kTISPropertyLocalizedName);
+        let name = if name.is_null() {
+            String::new()
+        } else {
+            CFString::wrap_under_get_rule(name as CFStringRef).to_string()
         };
+        let languages = TISGetInputSourceProperty(source, kTISPropertyInputSourceLanguages);
+        let language = (!languages.is_null())
+            .then(|| CFArray::<CFString>::wrap_under_get_rule(languages as CFArrayRef))
+            .and_then(|languages| languages.get(0).map(|language| language.to_string()));
+        CFRelease(source);
+        (name, language)
+    }
+}
+
+pub fn add_layout_change_callback<F>(cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+    macos_ext::add_input_source_change_callback(cb);
+}
+
+fn get_char(keycode: CGKeyCode) -> Option<PressedKey> {
+    match keycode {
+        36 | 52 => Some(PressedKey::Char(KEY_ENTER)), // ENTER
+        49 => Some(PressedKey::Char(KEY_SPACE)),      // SPACE
+        48 => Some(PressedKey::Char(KEY_TAB)),        // TAB
+        51 => Some(PressedKey::Char(KEY_DELETE)),     // DELETE
+        53 => Some(PressedKey::Char(KEY_ESCAPE)),     // ESC
+        _ => {
+            // The tap runs on the thread that handles the keys.
+            let layout = unsafe {
+                INPUT_STATE.apply_keyboard_layout();
+                INPUT_STATE.keyboard_layout()
+            };
+            let c = layout.key_char(keycode.into(), Level::Base);
+            Some(c.map_or(PressedKey::Raw(keycode), PressedKey::Char))
+        }
     }
-    None
 }
 
 pub fn is_in_text_selection() -> bool {
@@ -199,6 +311,11 @@ pub fn is_in_text_selection() -> bool {
 }
 
 pub fn send_backspace(handle: Handle, count: usize) -> Result<(), ()> {
+    // Add an extra backspace to compensate the initial text selection deletion.
+    // This is useful in applications like chrome, where the URL bar uses text selection
+    // for autocompletion, causing the first backspace to delete the selection instead of
+    // the character behind the cursor.
+    let count = if is_in_text_selection() { count + 1 } else { count };
     let null_event_source = ptr::null_mut() as *mut sys::CGEventSource;
     let (event_bs_down, event_bs_up) = unsafe {
         (
@@ -376,6 +493,7 @@ pub fn run_event_listener(callback: &CallbackFn) {
         CGEventTapOptions::Default,
         vec![
             CGEventType::KeyDown,
+            CGEventType::KeyUp,
             CGEventType::RightMouseDown,
             CGEventType::LeftMouseDown,
             CGEventType::OtherMouseDown,
@@ -409,28 +527,56 @@ pub fn run_event_listener(callback: &CallbackFn) {
             {
                 modifiers = KeyModifier::MODIFIER_NONE;
             }
+            let modifier_keys = DEVICE_MODIFIER_KEYS
+                .iter()
+                .filter(|(bit, _)| flags.bits() & bit != 0)
+                .fold(ModifierKeys::empty(), |keys, &(_, key)| keys | key);
+            let timestamp = unsafe { CGEventGetTimestamp(event.as_ptr()) } / 1_000_000;
 
             let event_tap_type: EventTapType = EventTapType::from(event.get_type());
             match event_tap_type {
-                EventTapType::KeyDown => {
+                EventTapType::KeyDown | EventTapType::KeyUp => {
                     let source_state_id =
                         event.get_integer_value_field(EventField::EVENT_SOURCE_STATE_ID);
                     if source_state_id == 1 {
                         let key_code = event
                             .get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE)
                             as CGKeyCode;
-
-                        if callback(proxy, event_tap_type, get_char(key_code), modifiers) {
+                        let is_repeat = event
+                            .get_integer_value_field(EventField::KEYBOARD_EVENT_AUTOREPEAT)
+                            != 0;
+                        let key_event = KeyEvent {
+                            modifier_keys,
+                            keycode: Some(key_code.into()),
+                            is_repeat,
+                            timestamp,
+                            ..KeyEvent::new(event_tap_type, get_char(key_code), modifiers)
+                        };
+
+                        if callback(proxy, &key_event) {
                             // block the key if already processed
                             return None;
                         }
                     }
                 }
                 EventTapType::FlagsChanged => {
-                    callback(proxy, event_tap_type, None, modifiers);
+                    let key_code = event
+                        .get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE)
+                        as CGKeyCode;
+                    let key_event = KeyEvent {
+                        modifier_keys,
+                        keycode: Some(key_code.into()),
+                        timestamp,
+                        ..KeyEvent::new(event_tap_type, None, modifiers)
+                    };
+                    callback(proxy, &key_event);
                 }
                 _ => {
-                    callback(proxy, event_tap_type, None, KeyModifier::new());
+                    let key_event = KeyEvent {
+                        timestamp,
+                        ..KeyEvent::new(event_tap_type, None, KeyModifier::new())
+                    };
+                    callback(proxy, &key_event);
                 }
             }
             Some(event.to_owned())
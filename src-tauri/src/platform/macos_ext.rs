@@ -152,6 +152,8 @@ extern "C" {
         length: libc::c_ulong,
         string: *const u16,
     );
+    /// Nanoseconds since the system started.
+    pub(crate) fn CGEventGetTimestamp(event: sys::CGEventRef) -> u64;
 }
 
 pub mod new_tap {
@@ -345,6 +347,17 @@ extern "C" {
     pub static NSWorkspaceDidActivateApplicationNotification: CFStringRef;
 }
 
+pub type TISInputSourceRef = *mut c_void;
+
+#[link(name = "Carbon", kind = "framework")]
+extern "C" {
+    pub static kTISNotifySelectedKeyboardInputSourceChanged: CFStringRef;
+    pub static kTISPropertyLocalizedName: CFStringRef;
+    pub static kTISPropertyInputSourceLanguages: CFStringRef;
+    pub fn TISCopyCurrentKeyboardInputSource() -> TISInputSourceRef;
+    pub fn TISGetInputSourceProperty(source: TISInputSourceRef, key: CFStringRef) -> *mut c_void;
+}
+
 pub fn add_app_change_callback<F>(cb: F)
 where
     F: Fn() + Send + 'static,
@@ -362,3 +375,23 @@ where
         ];
     }
 }
+
+/// Calls `cb` when the user switches to another input source, which the
+/// system only tells through the distributed notification center.
+pub fn add_input_source_change_callback<F>(cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+    unsafe {
+        let notification_center: id =
+            msg_send![class!(NSDistributedNotificationCenter), defaultCenter];
+        let cb_obj = Callback::from(Box::new(cb));
+
+        let _: id = msg_send![notification_center,
+            addObserver:cb_obj
+            selector:sel!(call)
+            name:kTISNotifySelectedKeyboardInputSourceChanged
+            object:nil
+        ];
+    }
+}
@@ -6,18 +6,24 @@ mod os;
 use std::fmt::Display;
 
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 pub use os::{
-    add_app_change_callback, ensure_accessibility_permission, get_active_app_identifier,
-    get_active_app_name, get_home_dir, is_in_text_selection, is_launch_on_login, run_event_listener,
-    send_backspace, send_string, update_launch_on_login, check_hotkey_conflict, Handle,
-    SYMBOL_ALT, SYMBOL_CTRL, SYMBOL_SHIFT, SYMBOL_SUPER,
+    add_app_change_callback, add_layout_change_callback, ensure_accessibility_permission,
+    get_active_app_identifier, get_active_app_name, get_home_dir, is_in_text_selection,
+    is_launch_on_login, run_event_listener, send_backspace, send_string, update_launch_on_login,
+    check_hotkey_conflict, keyboard_layout, Handle, SYMBOL_ALT, SYMBOL_CTRL, SYMBOL_SHIFT,
+    SYMBOL_SUPER,
 };
 
 #[cfg(target_os = "macos")]
 pub use os::set_background_app;
 
+#[cfg(target_os = "linux")]
+pub use os::{framework_engine, init_threads, run_framework_engine, Framework};
+
 #[cfg(target_os = "macos")]
 pub use os::SystemTray;
+#[cfg(target_os = "macos")]
 pub use os::SystemTrayMenuItemKey;
 
 pub const RAW_KEY_GLOBE: u16 = 0xb3;
@@ -25,6 +31,9 @@ pub const RAW_ARROW_DOWN: u16 = 0x7d;
 pub const RAW_ARROW_UP: u16 = 0x7e;
 pub const RAW_ARROW_LEFT: u16 = 0x7b;
 pub const RAW_ARROW_RIGHT: u16 = 0x7c;
+/// The digits of the numeric keypad, from 0 to 9.
+pub const RAW_KEYPAD_DIGITS: [u16; 10] =
+    [0x52, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5b, 0x5c];
 pub const KEY_ENTER: char = '\x13';
 pub const KEY_SPACE: char = '\u{0020}';
 pub const KEY_TAB: char = '\x09';
@@ -42,6 +51,20 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The modifier keys, left and right apart.
+    pub struct ModifierKeys: u32 {
+        const LEFT_SHIFT    = 0b00000001;
+        const RIGHT_SHIFT   = 0b00000010;
+        const LEFT_CONTROL  = 0b00000100;
+        const RIGHT_CONTROL = 0b00001000;
+        const LEFT_ALT      = 0b00010000;
+        const RIGHT_ALT     = 0b00100000;
+        const LEFT_SUPER    = 0b01000000;
+        const RIGHT_SUPER   = 0b10000000;
+    }
+}
+
 impl Display for KeyModifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.is_super() {
@@ -124,17 +147,66 @@ impl KeyModifier {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PressedKey {
     Char(char),
     Raw(u16),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl PressedKey {
+    /// The digit typed by a key of the numeric keypad, which is reported
+    /// raw so it is not mistaken for the digits above the letters.
+    pub fn keypad_digit(&self) -> Option<char> {
+        match self {
+            PressedKey::Raw(raw_keycode) => RAW_KEYPAD_DIGITS
+                .iter()
+                .position(|keycode| keycode == raw_keycode)
+                .and_then(|digit| char::from_digit(digit as u32, 10)),
+            PressedKey::Char(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EventTapType {
     KeyDown,
+    KeyUp,
     FlagsChanged,
     Other,
 }
 
-pub type CallbackFn = dyn Fn(os::Handle, EventTapType, Option<PressedKey>, KeyModifier) -> bool;
+/// What a platform reports about a key, or about a click that moved the
+/// cursor away from the word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub event_type: EventTapType,
+    /// The key as the engine reads it, for presses and releases.
+    pub key: Option<PressedKey>,
+    pub modifiers: KeyModifier,
+    /// The modifier keys held down, which `modifiers` does not tell apart.
+    pub modifier_keys: ModifierKeys,
+    /// The physical key, as the platform numbers it: the virtual keycode
+    /// on macOS, the X keycode on Linux.
+    pub keycode: Option<u32>,
+    /// Whether the OS repeats a key that is held down.
+    pub is_repeat: bool,
+    /// When the event happened, in milliseconds. Each platform counts from
+    /// another origin, so only the time between two events means anything.
+    pub timestamp: u64,
+}
+
+impl KeyEvent {
+    pub fn new(event_type: EventTapType, key: Option<PressedKey>, modifiers: KeyModifier) -> Self {
+        Self {
+            event_type,
+            key,
+            modifiers,
+            modifier_keys: ModifierKeys::empty(),
+            keycode: None,
+            is_repeat: false,
+            timestamp: 0,
+        }
+    }
+}
+
+pub type CallbackFn = dyn Fn(os::Handle, &KeyEvent) -> bool;
@@ -1,6 +1,7 @@
 // TODO: Implement this
 
 use super::{CallbackFn, KeyModifier};
+use crate::layout::KeyboardLayout;
 
 pub const SYMBOL_SHIFT: &str = "⇧";
 pub const SYMBOL_CTRL: &str = "⌃";
@@ -28,6 +29,17 @@ pub fn run_event_listener(callback: &CallbackFn) {
     todo!()
 }
 
+/// Read on the main thread at startup, so it must not panic like the rest.
+pub fn keyboard_layout() -> KeyboardLayout {
+    KeyboardLayout::default()
+}
+
+pub fn add_layout_change_callback<F>(_cb: F)
+where
+    F: Fn() + Send + 'static,
+{
+}
+
 pub fn ensure_accessibility_permission() -> bool {
     true
 }
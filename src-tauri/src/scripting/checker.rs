@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::compiler::LetterModification;
+use super::diagnostic::{Diagnostic, Span};
+use super::parser::{Block, BlockSource, FunctionCall, FunctionCallSource, Program, SourceMap};
+
+/// A built-in function that can be called from an `on … end` block.
+pub struct Function {
+    pub name: &'static str,
+    /// The identifiers accepted as arguments.
+    pub arguments: &'static [&'static str],
+    pub min_arguments: usize,
+    pub max_arguments: usize,
+    /// Whether the function accepts a `for` list of target letters.
+    pub takes_keys: bool,
+}
+
+const TONES: [&str; 5] = ["acute", "grave", "hook_above", "tilde", "underdot"];
+const LETTER_MODIFICATIONS: [&str; 4] = ["circumflex", "breve", "horn", "dyet"];
+
+pub const FUNCTIONS: [Function; 5] = [
+    Function {
+        name: "add_tone",
+        arguments: &TONES,
+        min_arguments: 1,
+        max_arguments: 1,
+        takes_keys: false,
+    },
+    Function {
+        name: "remove_tone",
+        arguments: &[],
+        min_arguments: 0,
+        max_arguments: 0,
+        takes_keys: false,
+    },
+    Function {
+        name: "letter_mod",
+        arguments: &LETTER_MODIFICATIONS,
+        min_arguments: 1,
+        max_arguments: LETTER_MODIFICATIONS.len(),
+        takes_keys: true,
+    },
+    Function {
+        name: "insert_uw",
+        arguments: &[],
+        min_arguments: 0,
+        max_arguments: 0,
+        takes_keys: false,
+    },
+    Function {
+        name: "reset_inserted_uw",
+        arguments: &[],
+        min_arguments: 0,
+        max_arguments: 0,
+        takes_keys: false,
+    },
+];
+
+fn find_function(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+fn quoted_list(names: impl IntoIterator<Item = &'static str>) -> String {
+    names
+        .into_iter()
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+struct Checker<'a> {
+    source: &'a str,
+    diagnostics: Vec<Diagnostic>,
+    /// The first place each trigger was bound, to report conflicts.
+    bindings: HashMap<String, Range<usize>>,
+}
+
+impl Checker<'_> {
+    fn span(&self, range: &Range<usize>) -> Span {
+        Span::from_offsets(self.source, range.start, range.end)
+    }
+
+    fn error(&mut self, message: String, range: &Range<usize>) {
+        self.diagnostics
+            .push(Diagnostic::new(message, self.span(range)));
+    }
+
+    fn warning(&mut self, message: String, range: &Range<usize>) {
+        self.diagnostics
+            .push(Diagnostic::warning(message, self.span(range)));
+    }
+
+    fn check_block(&mut self, block: &Block, source: &BlockSource) {
+        for (key, range) in block.key_list.iter().zip(&source.keys) {
+            let trigger = key.to_lowercase();
+            match self.bindings.get(&trigger) {
+                Some(first) => {
+                    let first = self.span(first).start;
+                    self.error(format!("key `{key}` is already bound at {first}"), range);
+                }
+                None => {
+                    self.bindings.insert(trigger, range.clone());
+                }
+            }
+        }
+        let calls: Vec<_> = block
+            .function_call_list
+            .iter()
+            .zip(&source.function_calls)
+            .collect();
+        for (index, (call, call_source)) in calls.iter().enumerate() {
+            self.check_function_call(call, call_source);
+            if let Some(reason) = unreachable_reason(&calls[..index], call) {
+                self.warning(
+                    format!("`{}` is never reached, {reason}", call.identifier),
+                    &call_source.range,
+                );
+            }
+        }
+    }
+
+    fn check_function_call(&mut self, call: &FunctionCall, source: &FunctionCallSource) {
+        let Some(function) = find_function(&call.identifier) else {
+            self.error(
+                format!(
+                    "unknown function `{}`, expected one of {}",
+                    call.identifier,
+                    quoted_list(FUNCTIONS.iter().map(|f| f.name))
+                ),
+                &source.identifier,
+            );
+            return;
+        };
+        let arguments = call.identifier_list.as_deref().unwrap_or_default();
+        for (index, (argument, range)) in arguments.iter().zip(&source.identifiers).enumerate() {
+            if index == function.max_arguments {
+                let message = match function.max_arguments {
+                    0 => format!("`{}` takes no arguments", function.name),
+                    1 => format!("`{}` takes a single argument", function.name),
+                    max => format!("`{}` takes at most {max} arguments", function.name),
+                };
+                self.error(message, range);
+                break;
+            }
+            if !function.arguments.contains(&argument.as_str()) {
+                self.error(
+                    format!(
+                        "`{argument}` is not a valid argument for `{}`, expected one of {}",
+                        function.name,
+                        quoted_list(function.arguments.iter().copied())
+                    ),
+                    range,
+                );
+            }
+        }
+        if arguments.len() < function.min_arguments {
+            self.error(
+                format!("`{}` needs an argument", function.name),
+                &source.range,
+            );
+        }
+        let keys = call.key_list.as_deref().unwrap_or_default();
+        if !function.takes_keys {
+            if let Some(range) = source.keys.first() {
+                self.error(
+                    format!("`{}` does not take a `for` list", function.name),
+                    range,
+                );
+            }
+            return;
+        }
+        let modifications: Vec<_> = arguments
+            .iter()
+            .filter_map(|argument| LetterModification::from_identifier(argument))
+            .collect();
+        for (key, range) in keys.iter().zip(&source.keys) {
+            let mut chars = key.chars();
+            let (Some(letter), None) = (chars.next(), chars.next()) else {
+                self.error(format!("`{key}` is not a single key"), range);
+                continue;
+            };
+            let letter = letter.to_ascii_lowercase();
+            if !modifications.is_empty()
+                && !modifications.iter().any(|m| m.targets().contains(&letter))
+            {
+                self.error(
+                    format!("`{key}` cannot be modified by {}", arguments.join(" or ")),
+                    range,
+                );
+            }
+        }
+    }
+}
+
+/// Why `call` can never run after the `previous` calls of the same `or`
+/// chain, if it cannot.
+fn unreachable_reason(
+    previous: &[(&FunctionCall, &FunctionCallSource)],
+    call: &FunctionCall,
+) -> Option<&'static str> {
+    previous.iter().find_map(|(previous, _)| {
+        if previous.identifier == "insert_uw" {
+            Some("`insert_uw` before it always applies")
+        } else if *previous == call {
+            Some("the same call comes before it")
+        } else if previous.identifier == "add_tone" && call.identifier == "add_tone" {
+            Some("the `add_tone` before it only fails when there is no vowel to put a tone on")
+        } else {
+            None
+        }
+    })
+}
+
+/// Checks a parsed program for mistakes the parser cannot see: unknown
+/// functions and arguments, keys bound twice and `or` branches that can
+/// never run. Imports are checked when they are resolved.
+pub fn check(program: &Program, source_map: &SourceMap, source: &str) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        source,
+        diagnostics: Vec::new(),
+        bindings: HashMap::new(),
+    };
+    for (block, block_source) in program.block_list.iter().flatten().zip(&source_map.blocks) {
+        checker.check_block(block, block_source);
+    }
+    checker.diagnostics
+}
+
+#[cfg(test)]
+fn check_source(source: &str) -> Vec<String> {
+    let (program, source_map) = super::parser::parse_script_with_source_map(source).unwrap();
+    check(&program, &source_map, source)
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect()
+}
+
+#[test]
+fn test_check_valid_program() {
+    let source = r#"
+import telex
+
+on s or ': add_tone(acute) end
+on a or e or o: letter_mod(circumflex for a or e or o) end
+on w:
+  reset_inserted_uw() or
+  letter_mod(horn or breve for u or o or a) or
+  insert_uw()
+end
+"#;
+    assert_eq!(check_source(source), Vec::<String>::new());
+}
+
+#[test]
+fn test_registry_matches_compiler() {
+    use super::compiler::Tone;
+
+    for tone in TONES {
+        assert!(Tone::from_identifier(tone).is_some(), "{tone}");
+    }
+    for modification in LETTER_MODIFICATIONS {
+        assert!(
+            LetterModification::from_identifier(modification).is_some(),
+            "{modification}"
+        );
+    }
+}
+
+#[test]
+fn test_check_unknown_function() {
+    assert_eq!(
+        check_source("import telex\non s: add_tones(acute) end"),
+        vec![
+            "2:7: unknown function `add_tones`, expected one of `add_tone`, `remove_tone`, \
+             `letter_mod`, `insert_uw`, `reset_inserted_uw`",
+        ]
+    );
+}
+
+#[test]
+fn test_check_bad_arguments() {
+    assert_eq!(
+        check_source(
+            "on s: add_tone(sharp) end\n\
+             on f: add_tone(grave or acute) end\n\
+             on z: remove_tone(acute) end\n\
+             on j: add_tone() end\n\
+             on x: add_tone(tilde for a) end"
+        ),
+        vec![
+            "1:16: `sharp` is not a valid argument for `add_tone`, expected one of `acute`, \
+             `grave`, `hook_above`, `tilde`, `underdot`",
+            "2:25: `add_tone` takes a single argument",
+            "3:19: `remove_tone` takes no arguments",
+            "4:7: `add_tone` needs an argument",
+            "5:26: `add_tone` does not take a `for` list",
+        ]
+    );
+}
+
+#[test]
+fn test_check_letter_mod_targets() {
+    assert_eq!(
+        check_source(r#"on a: letter_mod(circumflex for a or u or "ee") end"#),
+        vec![
+            "1:38: `u` cannot be modified by circumflex",
+            "1:43: `ee` is not a single key",
+        ]
+    );
+}
+
+#[test]
+fn test_check_conflicting_keys() {
+    assert_eq!(
+        check_source("on s: add_tone(acute) end\non S or r: add_tone(hook_above) end"),
+        vec!["2:4: key `S` is already bound at 1:4"]
+    );
+}
+
+#[test]
+fn test_check_unreachable_branches() {
+    assert_eq!(
+        check_source(
+            "on w: insert_uw() or letter_mod(horn for u) end\n\
+             on s: add_tone(acute) or add_tone(grave) end\n\
+             on z: remove_tone() or remove_tone() end"
+        ),
+        vec![
+            "1:22: warning: `letter_mod` is never reached, `insert_uw` before it always applies",
+            "2:26: warning: `add_tone` is never reached, the `add_tone` before it only fails \
+             when there is no vowel to put a tone on",
+            "3:24: warning: `remove_tone` is never reached, the same call comes before it",
+        ]
+    );
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use super::parser::{FunctionCall, Program};
+
+/// A tone mark, in the order they are laid out in the vowel table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    Acute,
+    Grave,
+    HookAbove,
+    Tilde,
+    Underdot,
+}
+
+impl Tone {
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        Some(match identifier {
+            "acute" => Self::Acute,
+            "grave" => Self::Grave,
+            "hook_above" => Self::HookAbove,
+            "tilde" => Self::Tilde,
+            "underdot" => Self::Underdot,
+            _ => return None,
+        })
+    }
+}
+
+/// A modification of a base letter, for example `a` → `â` (circumflex).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LetterModification {
+    Circumflex,
+    Breve,
+    Horn,
+    Dyet,
+}
+
+impl LetterModification {
+    pub fn from_identifier(identifier: &str) -> Option<Self> {
+        Some(match identifier {
+            "circumflex" => Self::Circumflex,
+            "breve" => Self::Breve,
+            "horn" => Self::Horn,
+            "dyet" => Self::Dyet,
+            _ => return None,
+        })
+    }
+
+    /// The base letters this modification can be applied to.
+    pub fn targets(&self) -> &'static [char] {
+        match self {
+            Self::Circumflex => &['a', 'e', 'o'],
+            Self::Breve => &['a'],
+            Self::Horn => &['o', 'u'],
+            Self::Dyet => &['d'],
+        }
+    }
+}
+
+/// A compiled function call. A block runs its transforms in order and stops
+/// at the first one that applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transform {
+    AddTone(Tone),
+    RemoveTone,
+    ModifyLetter {
+        modifications: Vec<LetterModification>,
+        targets: Vec<char>,
+    },
+    InsertUw,
+    ResetInsertedUw,
+}
+
+/// A typing method compiled from a goxscript program: for each trigger, the
+/// chain of transforms to try when it is typed.
+///
+/// A trigger is usually a single key. Multi-character triggers such as
+/// `"dd"` fire on their last key, when the keys typed just before it match
+/// the rest of the trigger.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputMethod {
+    rules: HashMap<String, Vec<Transform>>,
+}
+
+impl InputMethod {
+    pub fn transforms_for(&self, key: char) -> Option<&[Transform]> {
+        self.rules.get(&key.to_string()).map(|t| t.as_slice())
+    }
+
+    /// The transforms of the longest trigger completed by typing `key` after
+    /// the keys in `typed`.
+    pub fn transforms_after(&self, typed: &[char], key: char) -> Option<&[Transform]> {
+        self.rules
+            .iter()
+            .filter(|(trigger, _)| {
+                let mut trigger: Vec<char> = trigger.chars().collect();
+                trigger.pop() == Some(key) && typed.ends_with(&trigger)
+            })
+            .max_by_key(|(trigger, _)| trigger.chars().count())
+            .map(|(_, transforms)| transforms.as_slice())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    UnresolvedImport(String),
+    UnknownFunction(String),
+    UnknownArgument { function: String, argument: String },
+    MissingArgument(String),
+    UnexpectedArgument(String),
+    InvalidKey(String),
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnresolvedImport(module) => {
+                write!(
+                    f,
+                    "cannot compile `import {module}` before imports are resolved"
+                )
+            }
+            Self::UnknownFunction(function) => write!(f, "unknown function `{function}`"),
+            Self::UnknownArgument { function, argument } => {
+                write!(f, "`{argument}` is not a valid argument for `{function}`")
+            }
+            Self::MissingArgument(function) => write!(f, "`{function}` needs an argument"),
+            Self::UnexpectedArgument(function) => write!(f, "`{function}` takes no arguments"),
+            Self::InvalidKey(key) => write!(f, "`{key}` is not a single key"),
+        }
+    }
+}
+
+fn trigger(key: &str) -> Result<String, CompileError> {
+    if key.is_empty() {
+        return Err(CompileError::InvalidKey(key.to_string()));
+    }
+    Ok(key.to_lowercase())
+}
+
+fn single_key(key: &str) -> Result<char, CompileError> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c.to_ascii_lowercase()),
+        _ => Err(CompileError::InvalidKey(key.to_string())),
+    }
+}
+
+fn compile_arguments<T>(
+    call: &FunctionCall,
+    parse: fn(&str) -> Option<T>,
+) -> Result<Vec<T>, CompileError> {
+    let arguments = call
+        .identifier_list
+        .as_ref()
+        .filter(|list| !list.is_empty())
+        .ok_or_else(|| CompileError::MissingArgument(call.identifier.clone()))?;
+    arguments
+        .iter()
+        .map(|argument| {
+            parse(argument).ok_or_else(|| CompileError::UnknownArgument {
+                function: call.identifier.clone(),
+                argument: argument.clone(),
+            })
+        })
+        .collect()
+}
+
+fn expect_no_arguments(call: &FunctionCall) -> Result<(), CompileError> {
+    if call.identifier_list.is_some() || call.key_list.is_some() {
+        return Err(CompileError::UnexpectedArgument(call.identifier.clone()));
+    }
+    Ok(())
+}
+
+fn compile_call(call: &FunctionCall) -> Result<Transform, CompileError> {
+    match call.identifier.as_str() {
+        "add_tone" => {
+            let tones = compile_arguments(call, Tone::from_identifier)?;
+            if tones.len() != 1 || call.key_list.is_some() {
+                return Err(CompileError::UnexpectedArgument(call.identifier.clone()));
+            }
+            Ok(Transform::AddTone(tones[0]))
+        }
+        "remove_tone" => {
+            expect_no_arguments(call)?;
+            Ok(Transform::RemoveTone)
+        }
+        "letter_mod" => {
+            let modifications = compile_arguments(call, LetterModification::from_identifier)?;
+            let targets = match &call.key_list {
+                Some(keys) => keys
+                    .iter()
+                    .map(|key| single_key(key))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => Vec::new(),
+            };
+            Ok(Transform::ModifyLetter {
+                modifications,
+                targets,
+            })
+        }
+        "insert_uw" => {
+            expect_no_arguments(call)?;
+            Ok(Transform::InsertUw)
+        }
+        "reset_inserted_uw" => {
+            expect_no_arguments(call)?;
+            Ok(Transform::ResetInsertedUw)
+        }
+        _ => Err(CompileError::UnknownFunction(call.identifier.clone())),
+    }
+}
+
+/// Compiles a parsed goxscript program into an `InputMethod`.
+///
+/// Imports must have been resolved first, see `resolver::resolve_imports`.
+/// When several blocks are bound to the same key, the last one wins.
+///
+/// # Example
+///
+/// ```
+/// let (_, program) = parse_program("on s: add_tone(acute) end").unwrap();
+/// let method = compile(&program).unwrap();
+/// assert_eq!(method.transforms_for('s'), Some(&[Transform::AddTone(Tone::Acute)][..]));
+/// ```
+pub fn compile(program: &Program) -> Result<InputMethod, CompileError> {
+    if let Some(import) = program.import_list.iter().flatten().next() {
+        return Err(CompileError::UnresolvedImport(import.identifier.clone()));
+    }
+    let mut rules = HashMap::new();
+    for block in program.block_list.iter().flatten() {
+        let transforms = block
+            .function_call_list
+            .iter()
+            .map(compile_call)
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in &block.key_list {
+            rules.insert(trigger(key)?, transforms.clone());
+        }
+    }
+    Ok(InputMethod { rules })
+}
+
+#[cfg(test)]
+use super::parser::parse_program;
+
+#[test]
+fn test_compile_full_program() {
+    let (_, program) = parse_program(
+        r#"
+        on s or ': add_tone(acute) end
+
+        on a or e or o or 6:
+          letter_mod(circumflex for a or e or o)
+        end
+
+        on w or 7 or 8:
+          reset_inserted_uw() or
+          letter_mod(horn or breve for u or o) or
+          insert_uw()
+        end
+        "#,
+    )
+    .unwrap();
+    let method = compile(&program).unwrap();
+    assert_eq!(
+        method.transforms_for('\''),
+        Some(&[Transform::AddTone(Tone::Acute)][..])
+    );
+    assert_eq!(
+        method.transforms_for('6'),
+        Some(
+            &[Transform::ModifyLetter {
+                modifications: vec![LetterModification::Circumflex],
+                targets: vec!['a', 'e', 'o'],
+            }][..]
+        )
+    );
+    assert_eq!(
+        method.transforms_for('w'),
+        Some(
+            &[
+                Transform::ResetInsertedUw,
+                Transform::ModifyLetter {
+                    modifications: vec![LetterModification::Horn, LetterModification::Breve],
+                    targets: vec!['u', 'o'],
+                },
+                Transform::InsertUw,
+            ][..]
+        )
+    );
+    assert_eq!(method.transforms_for('x'), None);
+}
+
+#[test]
+fn test_compile_multi_character_trigger() {
+    let (_, program) = parse_program(
+        r#"
+        on d: add_tone(acute) end
+        on "dd": letter_mod(dyet for d) end
+        "#,
+    )
+    .unwrap();
+    let method = compile(&program).unwrap();
+    let dyet = [Transform::ModifyLetter {
+        modifications: vec![LetterModification::Dyet],
+        targets: vec!['d'],
+    }];
+    assert_eq!(
+        method.transforms_for('d'),
+        Some(&[Transform::AddTone(Tone::Acute)][..])
+    );
+    assert_eq!(method.transforms_after(&['a', 'd'], 'd'), Some(&dyet[..]));
+    assert_eq!(
+        method.transforms_after(&['a'], 'd'),
+        Some(&[Transform::AddTone(Tone::Acute)][..])
+    );
+}
+
+#[test]
+fn test_compile_unknown_function() {
+    let (_, program) = parse_program("on s: add_tones(acute) end").unwrap();
+    assert_eq!(
+        compile(&program),
+        Err(CompileError::UnknownFunction("add_tones".to_string()))
+    );
+}
+
+#[test]
+fn test_compile_unknown_argument() {
+    let (_, program) = parse_program("on s: add_tone(sharp) end").unwrap();
+    assert_eq!(
+        compile(&program),
+        Err(CompileError::UnknownArgument {
+            function: "add_tone".to_string(),
+            argument: "sharp".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_compile_missing_argument() {
+    let (_, program) = parse_program("on s: add_tone() end").unwrap();
+    assert_eq!(
+        compile(&program),
+        Err(CompileError::MissingArgument("add_tone".to_string()))
+    );
+}
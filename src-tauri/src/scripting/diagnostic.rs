@@ -0,0 +1,118 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+/// A position in a goxscript source. Lines and columns start at 1, columns
+/// are counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Converts a byte offset into `source` to a line and column.
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn from_offsets(source: &str, start: usize, end: usize) -> Self {
+        Self {
+            start: Position::from_offset(source, start),
+            end: Position::from_offset(source, end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The script cannot be used.
+    Error,
+    /// The script works, but probably not the way its author meant.
+    Warning,
+}
+
+/// A problem found in a goxscript source, with the span the editor should
+/// underline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "{}: {}", self.span.start, self.message),
+            Severity::Warning => write!(f, "{}: warning: {}", self.span.start, self.message),
+        }
+    }
+}
+
+#[test]
+fn test_position_from_offset() {
+    let source = "on a:\n  hello()\nend";
+    assert_eq!(
+        Position::from_offset(source, 0),
+        Position { line: 1, column: 1 }
+    );
+    assert_eq!(
+        Position::from_offset(source, 8),
+        Position { line: 2, column: 3 }
+    );
+    assert_eq!(
+        Position::from_offset(source, source.len()),
+        Position { line: 3, column: 4 }
+    );
+}
+
+#[test]
+fn test_position_counts_characters() {
+    assert_eq!(
+        Position::from_offset("đâ x", "đâ ".len()),
+        Position { line: 1, column: 4 }
+    );
+}
@@ -0,0 +1,402 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use super::parser::{is_key_char, Block, FunctionCall, Program, SourceMap};
+
+const INDENT: &str = "  ";
+
+/// The comments of a source, grouped by the top level item they are written
+/// above, or inside of.
+#[derive(Default)]
+struct Comments<'a> {
+    imports: Vec<Vec<&'a str>>,
+    blocks: Vec<Vec<&'a str>>,
+    expects: Vec<Vec<&'a str>>,
+    /// The comments after the last item.
+    trailing: Vec<&'a str>,
+}
+
+impl<'a> Comments<'a> {
+    fn collect(source: &'a str, source_map: &SourceMap) -> Self {
+        // Comments can only be found between the tokens that may contain a
+        // `#`: keys, expectations, and the identifiers around them.
+        let mut tokens: Vec<&Range<usize>> = source_map
+            .imports
+            .iter()
+            .chain(&source_map.expects)
+            .collect();
+        for block in &source_map.blocks {
+            tokens.extend(&block.keys);
+            for call in &block.function_calls {
+                tokens.push(&call.identifier);
+                tokens.extend(&call.identifiers);
+                tokens.extend(&call.keys);
+            }
+        }
+        tokens.sort_by_key(|range| range.start);
+        let mut found = Vec::new();
+        let mut gap_start = 0;
+        for token in tokens {
+            find_comments(source, gap_start..token.start, &mut found);
+            gap_start = token.end;
+        }
+        find_comments(source, gap_start..source.len(), &mut found);
+
+        let mut items: Vec<(&Range<usize>, &mut Vec<&'a str>)> = Vec::new();
+        let mut comments = Comments {
+            imports: vec![Vec::new(); source_map.imports.len()],
+            blocks: vec![Vec::new(); source_map.blocks.len()],
+            expects: vec![Vec::new(); source_map.expects.len()],
+            trailing: Vec::new(),
+        };
+        items.extend(source_map.imports.iter().zip(&mut comments.imports));
+        items.extend(
+            source_map
+                .blocks
+                .iter()
+                .map(|block| &block.range)
+                .zip(&mut comments.blocks),
+        );
+        items.extend(source_map.expects.iter().zip(&mut comments.expects));
+        items.sort_by_key(|(range, _)| range.start);
+        let mut items = items.into_iter().peekable();
+        for (offset, comment) in found {
+            while items.next_if(|(range, _)| range.end <= offset).is_some() {}
+            match items.peek_mut() {
+                Some((_, lines)) => push_line(lines, comment),
+                None => push_line(&mut comments.trailing, comment),
+            }
+        }
+        comments
+    }
+}
+
+/// Finds the comments in `gap`, a part of `source` made of whitespace,
+/// comments and punctuation only. A blank line between two comments is
+/// kept as an empty line.
+fn find_comments<'a>(source: &'a str, gap: Range<usize>, found: &mut Vec<(usize, &'a str)>) {
+    let mut offset = gap.start;
+    for (index, line) in source[gap.clone()].split('\n').enumerate() {
+        match line.find('#') {
+            Some(start) => found.push((offset + start, line[start..].trim_end())),
+            None if index > 0 && offset + line.len() < gap.end && line.trim().is_empty() => {
+                found.push((offset, ""))
+            }
+            None => {}
+        }
+        offset += line.len() + 1;
+    }
+}
+
+/// Adds a comment line, dropping blank lines that would not sit between two
+/// comments.
+fn push_line<'a>(lines: &mut Vec<&'a str>, line: &'a str) {
+    if line.is_empty() {
+        if lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    } else {
+        lines.push(line);
+    }
+}
+
+struct Formatter<'a> {
+    output: String,
+    comments: Comments<'a>,
+    /// Whether the next item starts a new group, after a blank line.
+    new_group: bool,
+}
+
+impl Formatter<'_> {
+    fn comment_lines(&mut self, lines: &[&str]) {
+        let lines = lines.strip_suffix(&[""]).unwrap_or(lines);
+        for line in lines {
+            self.output.push_str(line);
+            self.output.push('\n');
+        }
+    }
+
+    /// Starts a top level item, after the comments written above it.
+    fn item(&mut self, comments: Option<Vec<&str>>, multiline: bool) {
+        let comments = comments.unwrap_or_default();
+        if !self.output.is_empty() && (self.new_group || multiline || !comments.is_empty()) {
+            self.output.push('\n');
+        }
+        self.comment_lines(&comments);
+        self.new_group = multiline;
+    }
+
+    fn program(&mut self, program: &Program) {
+        let mut import_comments = std::mem::take(&mut self.comments.imports).into_iter();
+        for import in program.import_list.iter().flatten() {
+            self.item(import_comments.next(), false);
+            writeln!(self.output, "import {}", import.identifier).unwrap();
+        }
+        self.new_group = true;
+        let mut block_comments = std::mem::take(&mut self.comments.blocks).into_iter();
+        for block in program.block_list.iter().flatten() {
+            let multiline = block.function_call_list.len() > 1;
+            self.item(block_comments.next(), multiline);
+            self.block(block);
+        }
+        self.new_group = true;
+        let mut expect_comments = std::mem::take(&mut self.comments.expects).into_iter();
+        for expect in program.expect_list.iter().flatten() {
+            self.item(expect_comments.next(), false);
+            self.output.push_str("expect ");
+            write_quoted(&mut self.output, &expect.input);
+            self.output.push_str(" => ");
+            write_quoted(&mut self.output, &expect.output);
+            self.output.push('\n');
+        }
+        let trailing = std::mem::take(&mut self.comments.trailing);
+        if !trailing.is_empty() {
+            self.item(Some(trailing), false);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.output.push_str("on ");
+        write_key_list(&mut self.output, &block.key_list);
+        self.output.push(':');
+        if let [call] = block.function_call_list.as_slice() {
+            self.output.push(' ');
+            self.function_call(call);
+            self.output.push_str(" end\n");
+            return;
+        }
+        for (index, call) in block.function_call_list.iter().enumerate() {
+            self.output.push('\n');
+            self.output.push_str(INDENT);
+            self.function_call(call);
+            if index + 1 < block.function_call_list.len() {
+                self.output.push_str(" or");
+            }
+        }
+        self.output.push_str("\nend\n");
+    }
+
+    fn function_call(&mut self, call: &FunctionCall) {
+        self.output.push_str(&call.identifier);
+        self.output.push('(');
+        if let Some(identifiers) = &call.identifier_list {
+            self.output.push_str(&identifiers.join(" or "));
+        }
+        if let Some(keys) = &call.key_list {
+            self.output.push_str(" for ");
+            write_key_list(&mut self.output, keys);
+        }
+        self.output.push(')');
+    }
+}
+
+fn write_key_list(output: &mut String, keys: &[String]) {
+    for (index, key) in keys.iter().enumerate() {
+        if index > 0 {
+            output.push_str(" or ");
+        }
+        write_key(output, key);
+    }
+}
+
+/// Writes a key bare when it is a single character that can be written
+/// that way, and quoted otherwise.
+fn write_key(output: &mut String, key: &str) {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if is_key_char(c) => output.push(c),
+        _ => write_quoted(output, key),
+    }
+}
+
+fn write_quoted(output: &mut String, text: &str) {
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => output.push_str(r"\\"),
+            '"' => output.push_str(r#"\""#),
+            '\n' => output.push_str(r"\n"),
+            '\t' => output.push_str(r"\t"),
+            c if c.is_control() => write!(output, r"\u{{{:x}}}", c as u32).unwrap(),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+/// Turns a program back into goxscript source, in the canonical layout:
+/// imports first, then blocks, then `expect` assertions, one per line.
+/// Blocks with an `or` chain are written with one call per line.
+///
+/// # Example
+///
+/// ```
+/// let program = parse_script("on s :  add_tone(acute)   end").unwrap();
+/// assert_eq!(format_program(&program), "on s: add_tone(acute) end\n");
+/// ```
+pub fn format_program(program: &Program) -> String {
+    format(program, Comments::default())
+}
+
+/// Same as `format_program`, but keeps the comments of the source the
+/// program was parsed from. Comments are written above the top level item
+/// they were found before or inside of, so a comment inside a block moves
+/// above it.
+pub fn format_program_with_comments(
+    program: &Program,
+    source_map: &SourceMap,
+    source: &str,
+) -> String {
+    format(program, Comments::collect(source, source_map))
+}
+
+fn format(program: &Program, comments: Comments) -> String {
+    let mut formatter = Formatter {
+        output: String::new(),
+        comments,
+        new_group: false,
+    };
+    formatter.program(program);
+    formatter.output
+}
+
+#[cfg(test)]
+fn format_source(source: &str) -> String {
+    let (program, source_map) = super::parser::parse_script_with_source_map(source).unwrap();
+    format_program_with_comments(&program, &source_map, source)
+}
+
+/// A small xorshift generator, to build random programs without pulling a
+/// property testing crate.
+#[cfg(test)]
+struct Random(u64);
+
+#[cfg(test)]
+impl Random {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    fn list<T>(&mut self, max: usize, mut item: impl FnMut(&mut Self) -> T) -> Vec<T> {
+        let len = 1 + self.next(max);
+        (0..len).map(|_| item(self)).collect()
+    }
+
+    fn identifier(&mut self) -> String {
+        const IDENTIFIERS: [&str; 6] = ["telex", "add_tone", "acute", "horn", "x1", "_"];
+        IDENTIFIERS[self.next(IDENTIFIERS.len())].to_string()
+    }
+
+    fn key(&mut self) -> String {
+        const CHARS: [char; 14] = [
+            'a', 'z', '7', '#', ':', ')', '"', '\\', ' ', '\n', '\t', 'đ', '\u{1}', 'o',
+        ];
+        self.list(3, |random| CHARS[random.next(CHARS.len())])
+            .into_iter()
+            .collect()
+    }
+
+    fn program(&mut self) -> Program {
+        use super::parser::{Expect, Import};
+
+        let import_list = (self.next(2) == 0).then(|| {
+            self.list(3, |random| Import {
+                identifier: random.identifier(),
+            })
+        });
+        let block_list = (self.next(4) > 0).then(|| {
+            self.list(4, |random| Block {
+                key_list: random.list(3, Random::key),
+                function_call_list: random.list(3, |random| {
+                    let identifier_list =
+                        (random.next(2) == 0).then(|| random.list(3, Random::identifier));
+                    let key_list = identifier_list
+                        .is_some()
+                        .then(|| random.next(2) == 0)
+                        .filter(|&has_keys| has_keys)
+                        .map(|_| random.list(3, Random::key));
+                    FunctionCall {
+                        identifier: random.identifier(),
+                        identifier_list,
+                        key_list,
+                    }
+                }),
+            })
+        });
+        let expect_list = (self.next(2) == 0).then(|| {
+            self.list(2, |random| Expect {
+                input: random.key(),
+                output: random.key(),
+            })
+        });
+        Program {
+            import_list,
+            block_list,
+            expect_list,
+        }
+    }
+}
+
+#[test]
+fn test_format_program() {
+    let source = "import telex   import vni\n\
+                  on s or \"dd\" : add_tone(acute)  end\n\
+                  expect \"ddaay\"=>\"đây\"\n\
+                  on w: reset_inserted_uw() or letter_mod(horn or breve for u or o) or insert_uw() end\n\
+                  on \" \" or \"\\\"\": add_tone(grave) end";
+    assert_eq!(
+        format_source(source),
+        r#"import telex
+import vni
+
+on s or "dd": add_tone(acute) end
+
+on w:
+  reset_inserted_uw() or
+  letter_mod(horn or breve for u or o) or
+  insert_uw()
+end
+
+on " " or "\"": add_tone(grave) end
+
+expect "ddaay" => "đây"
+"#
+    );
+}
+
+#[test]
+fn test_format_keeps_comments() {
+    let source = "# Header\n\n\n# about s\non s: add_tone(acute) end # trailing\n\
+                  on w: # why\n  insert_uw() or # not reached\n  add_tone(grave)\nend\n# the end\n";
+    assert_eq!(
+        format_source(source),
+        "# Header\n\n# about s\non s: add_tone(acute) end\n\n\
+         # trailing\n# why\n# not reached\non w:\n  insert_uw() or\n  add_tone(grave)\nend\n\n\
+         # the end\n"
+    );
+}
+
+#[test]
+fn test_format_is_idempotent() {
+    for (name, source) in super::resolver::BUILTIN_MODULES {
+        assert_eq!(format_source(source), source, "{name}");
+    }
+}
+
+#[test]
+fn test_format_round_trip() {
+    let mut random = Random(0x5eed);
+    for _ in 0..500 {
+        let program = random.program();
+        let formatted = format_program(&program);
+        assert_eq!(
+            super::parser::parse_script(&formatted).as_ref(),
+            Ok(&program),
+            "{formatted}"
+        );
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
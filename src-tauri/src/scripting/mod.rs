@@ -9,8 +9,11 @@
 /// import telex
 /// import vni
 ///
+/// # Tones
 /// on s or ': add_tone(acute) end
 ///
+/// on "dd": letter_mod(dyet for d) end
+///
 /// on a or e or o or 6:
 ///   letter_mod(circumflex for a or e or o)
 /// end
@@ -20,37 +23,203 @@
 ///   letter_mod(horn or breve for u or o) or
 ///   insert_uw()
 /// end
+///
+/// # Tests, run with `goxkey goxscript test <file>`
+/// expect "ddaay" => "đây"
 /// ```
 ///
 /// # Syntax
 /// The following EBNF describes the syntax of the goxscript language:
 ///
 /// ```ebnf
-/// <program> ::= <import_list>? <whitespace> <block_list>?
+/// <program> ::= <import_list>? <whitespace> <item_list>?
 ///
 /// <import_list> ::= <import> ( <whitespace> <import_list> )?
 /// <import> ::= "import" <whitespace> <identifier>
 ///
-/// <block_list> ::= <block> ( <whitespace> <block_list> )?
+/// <item_list> ::= ( <block> | <expect> ) ( <whitespace> <item_list> )?
 /// <block> ::= "on" <whitespace> <key_list> <whitespace> ":" <whitespace> <function_call_list> <whitespace> "end"
 ///
+/// <expect> ::= "expect" <space> <quoted_key> <whitespace> "=>" <whitespace> <quoted_key>
+///
 /// <function_call_list> ::= <function_call> ( <whitespace> "or" <whitespace> <function_call_list> )?
 /// <function_call> ::= <identifier> "(" ( <identifier_list> ( <whitespace> "for" <whitespace> <key_list> )? )? ")"
 ///
 /// <identifier_list> ::= <identifier> ( <whitespace> "or" <whitespace> <identifier_list> )?
 /// <identifier> ::= (<upper_letter> | <lower_letter> | <digit> | "_")+
 ///
-/// <key_list> ::= <key> ( <whitespace> "or" <whitespace> <key_list> )?
-/// <key> ::= <any_character>
+/// <key_list> ::= <key> ( <whitespace> "or" <space> <key_list> )?
+/// <key> ::= <any_character> | <quoted_key>
+/// <quoted_key> ::= "\"" ( <string_character> | <escape> )+ "\""
+/// <escape> ::= "\\" ( "\\" | "\"" | "n" | "t" | "u{" <hex_digit>+ "}" )
 ///
-/// <whitespace> ::= (" " | "\n")*
+/// <whitespace> ::= (" " | "\n" | <comment>)*
+/// <space> ::= (" " | "\n")*
+/// <comment> ::= "#" <any character but "\n">*
 /// <any_character> ::= <upper_letter> | <lower_letter> | <digit> | <punctuation>
 /// <upper_letter> ::= "A" | "B" | "C" | "D" | "E" | "F" | "G" | "H" | "I" | "J" | "K" | "L" | "M" | "N" | "O" |
 ///                    "P" | "Q" | "R" | "S" | "T" | "U" | "V" | "W" | "X" | "Y" | "Z"
 /// <lower_letter> ::= "a" | "b" | "c" | "d" | "e" | "f" | "g" | "h" | "i" | "j" | "k" | "l" | "m" | "n" | "o" |
 ///                    "p" | "q" | "r" | "s" | "t" | "u" | "v" | "w" | "x" | "y" | "z"
 /// <digit> ::= "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9"
-/// <punctuation> ::= "!" | "\"" | "#" | "$" | "%" | "&" | "'" | "(" | ")" | "*" | "+" | "," | "-" | "." | "/" |
+/// <punctuation> ::= "!" | "#" | "$" | "%" | "&" | "'" | "(" | ")" | "*" | "+" | "," | "-" | "." | "/" |
 ///                   ":" | ";" | "<" | "=" | ">" | "?" | "@" | "[" | "\\" | "]" | "^" | "_" | "`" | "{" | "}" | "~"
+/// <string_character> ::= any character but "\"", "\\" and "\n"
 /// ```
+///
+/// A `#` starts a comment everywhere but where a key is expected, so `on #:`
+/// still binds the `#` key. A key written in quotes can be several characters
+/// long, like `"dd"`, or whitespace, like `" "`; a bare `"` key must be
+/// written `"\""`. An `expect` line asserts that typing its input with the
+/// script produces its output.
 pub mod parser;
+pub mod checker;
+pub mod compiler;
+pub mod diagnostic;
+pub mod formatter;
+pub mod resolver;
+pub mod runtime;
+
+use compiler::InputMethod;
+use diagnostic::{Diagnostic, Span};
+use parser::{Program, SourceMap};
+use resolver::ModuleLoader;
+
+use crate::engine::{type_keys, Engine, TypingMethod};
+use crate::hotkey::Hotkey;
+
+/// Parses and checks a goxscript source, then resolves its imports. Returns
+/// the program and its source map along with its warnings, or every problem
+/// found.
+fn load_script(
+    source: &str,
+    modules: &dyn ModuleLoader,
+) -> Result<(Program, SourceMap, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let (program, source_map) = parser::parse_script_with_source_map(source)?;
+    let mut diagnostics = checker::check(&program, &source_map, source);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+    match resolver::resolve_imports(program, &source_map, source, modules) {
+        Ok(program) => Ok((program, source_map, diagnostics)),
+        Err(errors) => {
+            diagnostics.extend(errors);
+            Err(diagnostics)
+        }
+    }
+}
+
+/// Parses and compiles a goxscript source into an input method that can
+/// be given to the engine in place of the built-in Telex/VNI.
+pub fn compile_script(source: &str, modules: &dyn ModuleLoader) -> Result<InputMethod, String> {
+    let (program, _, _) = load_script(source, modules).map_err(|diagnostics| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .map(Diagnostic::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    compiler::compile(&program).map_err(|err| err.to_string())
+}
+
+/// Rewrites a goxscript source in the canonical layout, keeping its
+/// comments. Fails when the source does not parse.
+pub fn format_script(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (program, source_map) = parser::parse_script_with_source_map(source)?;
+    Ok(formatter::format_program_with_comments(
+        &program,
+        &source_map,
+        source,
+    ))
+}
+
+/// The outcome of an `expect` assertion of a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectResult {
+    pub input: String,
+    pub expected: String,
+    pub actual: String,
+    pub span: Span,
+}
+
+impl ExpectResult {
+    pub fn passed(&self) -> bool {
+        self.actual == self.expected
+    }
+}
+
+/// Compiles a goxscript source and runs its `expect` assertions, typing
+/// each input into a fresh engine that uses the script.
+pub fn test_script(
+    source: &str,
+    modules: &dyn ModuleLoader,
+) -> Result<Vec<ExpectResult>, Vec<Diagnostic>> {
+    let (program, source_map, _) = load_script(source, modules)?;
+    let method = compiler::compile(&program).map_err(|err| {
+        vec![Diagnostic::new(
+            err.to_string(),
+            Span::from_offsets(source, 0, 0),
+        )]
+    })?;
+    let results = program
+        .expect_list
+        .into_iter()
+        .flatten()
+        .zip(&source_map.expects)
+        .map(|(expect, range)| {
+            let mut engine = Engine::new(TypingMethod::Telex, Hotkey::from_str("ctrl+space"));
+            engine.set_script(Some(method.clone()));
+            ExpectResult {
+                actual: type_keys(&mut engine, &expect.input),
+                input: expect.input,
+                expected: expect.output,
+                span: Span::from_offsets(source, range.start, range.end),
+            }
+        })
+        .collect();
+    Ok(results)
+}
+
+/// Checks a goxscript source for syntax errors, semantic mistakes and
+/// missing modules, for the script editor.
+pub fn check_script(source: &str, modules: &dyn ModuleLoader) -> Vec<Diagnostic> {
+    match load_script(source, modules) {
+        Ok((_, _, warnings)) => warnings,
+        Err(diagnostics) => diagnostics,
+    }
+}
+
+#[test]
+fn test_script_runs_expects() {
+    let source = "import telex\n\
+                  expect \"ddaay\" => \"đây\"\n\
+                  expect \"vieetj\" => \"viet\"\n";
+    let results = test_script(source, &|_: &str| None).unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].passed());
+    assert!(!results[1].passed());
+    assert_eq!(results[1].actual, "việt");
+    assert_eq!(results[1].span.start.to_string(), "3:1");
+}
+
+#[test]
+fn test_builtin_modules_pass_their_expects() {
+    for (name, source) in resolver::BUILTIN_MODULES {
+        let results = test_script(source, &|_: &str| None).unwrap();
+        assert!(!results.is_empty(), "{name}");
+        for result in results {
+            assert!(result.passed(), "{name}: {result:?}");
+        }
+    }
+}
+
+#[test]
+fn test_script_reports_errors_before_running_expects() {
+    let diagnostics =
+        test_script("import mymod\nexpect \"a\" => \"a\"", &|_: &str| None).unwrap_err();
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "1:1: cannot find module `mymod`"
+    );
+}
@@ -1,13 +1,19 @@
+use std::ops::Range;
+
 use nom::{
-    bytes::complete::{tag, take_while1, take_while_m_n},
-    character::complete::{multispace0, multispace1},
-    combinator::{map, opt},
-    multi::separated_list1,
-    sequence::{delimited, preceded, tuple},
+    branch::alt,
+    bytes::complete::{escaped_transform, is_not, tag, take_till, take_while1, take_while_m_n},
+    character::complete::{char, hex_digit1, multispace1},
+    combinator::{map, map_opt, opt, recognize, value, verify},
+    multi::{many0, many1, separated_list1},
+    sequence::{delimited, pair, preceded, tuple},
     IResult,
 };
 
-/// Represents a program containing a list of imports and blocks.
+use super::diagnostic::{Diagnostic, Position, Span};
+
+/// Represents a program containing a list of imports, blocks and the
+/// `expect` assertions that test them.
 ///
 /// # Example
 ///
@@ -22,13 +28,15 @@ use nom::{
 ///             key_list: None,
 ///         }],
 ///     }]),
+///     expect_list: None,
 /// };
 /// println!("{:?}", program);
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
-    import_list: Option<Vec<Import>>,
-    block_list: Option<Vec<Block>>,
+    pub import_list: Option<Vec<Import>>,
+    pub block_list: Option<Vec<Block>>,
+    pub expect_list: Option<Vec<Expect>>,
 }
 
 /// Represents an import statement with an identifier.
@@ -41,9 +49,9 @@ pub struct Program {
 /// };
 /// println!("{:?}", import);
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Import {
-    identifier: String,
+    pub identifier: String,
 }
 
 /// Represents a block containing a list of keys and function calls.
@@ -61,10 +69,10 @@ pub struct Import {
 /// };
 /// println!("{:?}", block);
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Block {
-    key_list: Vec<String>,
-    function_call_list: Vec<FunctionCall>,
+    pub key_list: Vec<String>,
+    pub function_call_list: Vec<FunctionCall>,
 }
 
 /// Represents a function call with an identifier, and optional lists of identifiers and keys.
@@ -79,14 +87,102 @@ pub struct Block {
 /// };
 /// println!("{:?}", function_call);
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FunctionCall {
-    identifier: String,
-    identifier_list: Option<Vec<String>>,
-    key_list: Option<Vec<String>>,
+    pub identifier: String,
+    pub identifier_list: Option<Vec<String>>,
+    pub key_list: Option<Vec<String>>,
 }
 
-/// Checks if a character is a valid key character (not whitespace).
+/// Represents an `expect "input" => "output"` assertion: typing `input`
+/// with the script should produce `output`.
+///
+/// # Example
+///
+/// ```
+/// let expect = Expect {
+///     input: "ddaay".to_string(),
+///     output: "đây".to_string(),
+/// };
+/// println!("{:?}", expect);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expect {
+    pub input: String,
+    pub output: String,
+}
+
+/// Where the nodes of a `Program` are in its source, as byte ranges. Lists
+/// are in the same order as in the `Program`, so later passes can point
+/// their diagnostics at the right place.
+#[derive(Debug, Default, PartialEq)]
+pub struct SourceMap {
+    pub imports: Vec<Range<usize>>,
+    pub blocks: Vec<BlockSource>,
+    pub expects: Vec<Range<usize>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BlockSource {
+    pub range: Range<usize>,
+    pub keys: Vec<Range<usize>>,
+    pub function_calls: Vec<FunctionCallSource>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionCallSource {
+    pub range: Range<usize>,
+    pub identifier: Range<usize>,
+    pub identifiers: Vec<Range<usize>>,
+    pub keys: Vec<Range<usize>>,
+}
+
+/// Parsed items, along with the byte range of each of them.
+type WithRanges<T> = (Vec<T>, Vec<Range<usize>>);
+
+/// Runs `parser` and also returns the byte range it consumed. Offsets are
+/// computed from the length of the remaining input, so `source_len` must be
+/// the length of the whole source being parsed.
+fn located<'a, O>(
+    source_len: usize,
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (O, Range<usize>)> {
+    move |input: &'a str| {
+        let (rest, output) = parser(input)?;
+        Ok((
+            rest,
+            (output, source_len - input.len()..source_len - rest.len()),
+        ))
+    }
+}
+
+/// Parses a comment, from `#` to the end of the line.
+///
+/// # Example
+///
+/// ```
+/// let result = parse_comment("# telex tones\non s: add_tone(acute) end");
+/// assert_eq!(result.unwrap().0, "\non s: add_tone(acute) end");
+/// ```
+fn parse_comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(char('#'), take_till(|c| c == '\n')))(input)
+}
+
+/// Parses optional whitespace and comments.
+///
+/// Comments are not allowed where a key is expected, since `#` is a valid
+/// key there: `on # or a:` binds `#`.
+fn ws0(input: &str) -> IResult<&str, &str> {
+    recognize(many0(alt((multispace1, parse_comment))))(input)
+}
+
+/// Parses mandatory whitespace and comments.
+fn ws1(input: &str) -> IResult<&str, &str> {
+    recognize(many1(alt((multispace1, parse_comment))))(input)
+}
+
+/// Checks if a character is a valid key character (not whitespace, and not
+/// a `"` which starts a quoted key).
 ///
 /// # Example
 ///
@@ -96,11 +192,45 @@ pub struct FunctionCall {
 /// let result = is_key_char(' ');
 /// assert!(!result);
 /// ```
-fn is_key_char(c: char) -> bool {
-    !c.is_whitespace()
+pub fn is_key_char(c: char) -> bool {
+    !c.is_whitespace() && c != '"'
+}
+
+/// Parses an escape sequence in a quoted key, without its leading `\`.
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        value('\\', char('\\')),
+        value('"', char('"')),
+        value('\n', char('n')),
+        value('\t', char('t')),
+        map_opt(delimited(tag("u{"), hex_digit1, char('}')), |hex: &str| {
+            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+        }),
+    ))(input)
+}
+
+/// Parses a quoted key, which can be several characters long, contain
+/// whitespace and use escape sequences. Quoted keys stay on one line.
+///
+/// # Example
+///
+/// ```
+/// let result = parse_quoted_key(r#""d\"d""#);
+/// assert_eq!(result.unwrap().1, "d\"d".to_string());
+/// ```
+fn parse_quoted_key(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        verify(
+            escaped_transform(is_not("\\\"\n"), '\\', parse_escape),
+            |key: &str| !key.is_empty(),
+        ),
+        char('"'),
+    )(input)
 }
 
-/// Parses a key from the input string.
+/// Parses a key from the input string: either a single character, or a
+/// quoted sequence of characters.
 ///
 /// # Example
 ///
@@ -108,9 +238,14 @@ fn is_key_char(c: char) -> bool {
 /// let result = parse_key("a");
 /// assert!(result.is_ok());
 /// assert_eq!(result.unwrap().1, "a".to_string());
+/// let result = parse_key("\"dd\"");
+/// assert_eq!(result.unwrap().1, "dd".to_string());
 /// ```
 fn parse_key(input: &str) -> IResult<&str, String> {
-    map(take_while_m_n(1, 1, is_key_char), |s: &str| s.to_string())(input)
+    alt((
+        parse_quoted_key,
+        map(take_while_m_n(1, 1, is_key_char), |s: &str| s.to_string()),
+    ))(input)
 }
 
 /// Parses a list of keys from the input string.
@@ -123,7 +258,19 @@ fn parse_key(input: &str) -> IResult<&str, String> {
 /// assert_eq!(result.unwrap().1, vec!["a".to_string(), "b".to_string(), "c".to_string()]);
 /// ```
 fn parse_key_list(input: &str) -> IResult<&str, Vec<String>> {
-    separated_list1(delimited(multispace1, tag("or"), multispace1), parse_key)(input)
+    map(key_list_with_source(input.len()), |(keys, _)| keys)(input)
+}
+
+fn key_list_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, WithRanges<String>> {
+    map(
+        separated_list1(
+            delimited(ws1, tag("or"), multispace1),
+            located(source_len, parse_key),
+        ),
+        |keys| keys.into_iter().unzip(),
+    )
 }
 
 /// Checks if a character is a valid identifier character (alphanumeric or underscore).
@@ -167,12 +314,24 @@ fn parse_identifier(input: &str) -> IResult<&str, String> {
 /// assert_eq!(result.unwrap().1, vec!["abc".to_string(), "def".to_string(), "ghi".to_string()]);
 /// ```
 fn parse_identifier_list(input: &str) -> IResult<&str, Vec<String>> {
-    separated_list1(
-        delimited(multispace1, tag("or"), multispace1),
-        parse_identifier,
+    map(
+        identifier_list_with_source(input.len()),
+        |(identifiers, _)| identifiers,
     )(input)
 }
 
+fn identifier_list_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, WithRanges<String>> {
+    map(
+        separated_list1(
+            delimited(ws1, tag("or"), ws1),
+            located(source_len, parse_identifier),
+        ),
+        |identifiers| identifiers.into_iter().unzip(),
+    )
+}
+
 /// Parses an import statement from the input string.
 ///
 /// # Example
@@ -206,7 +365,16 @@ fn parse_import(input: &str) -> IResult<&str, Import> {
 /// ]);
 /// ```
 fn parse_import_list(input: &str) -> IResult<&str, Vec<Import>> {
-    separated_list1(multispace1, parse_import)(input)
+    map(import_list_with_source(input.len()), |(imports, _)| imports)(input)
+}
+
+fn import_list_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, WithRanges<Import>> {
+    map(
+        separated_list1(ws1, located(source_len, parse_import)),
+        |imports| imports.into_iter().unzip(),
+    )
 }
 
 /// Parses a function call from the input string.
@@ -223,33 +391,49 @@ fn parse_import_list(input: &str) -> IResult<&str, Vec<Import>> {
 /// });
 /// ```
 fn parse_function_call(input: &str) -> IResult<&str, FunctionCall> {
-    let parse_identifier_list = opt(parse_identifier_list);
-    let parse_key_list = map(
-        opt(tuple((
-            multispace1,
-            tag("for"),
-            multispace1,
-            parse_key_list,
-        ))),
-        |x| x.map(|(_, _, _, key_list)| key_list),
-    );
-    let (input, (identifier, _, _, identifier_list, key_list, _, _)) = tuple((
-        parse_identifier,
-        tag("("),
-        multispace0,
-        parse_identifier_list,
-        parse_key_list,
-        multispace0,
-        tag(")"),
-    ))(input)?;
-    Ok((
-        input,
-        FunctionCall {
-            identifier: identifier.to_string(),
-            identifier_list,
-            key_list,
-        },
-    ))
+    map(function_call_with_source(input.len()), |(call, _)| call)(input)
+}
+
+fn function_call_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (FunctionCall, FunctionCallSource)> {
+    move |input: &'a str| {
+        let parse_identifier_list = opt(identifier_list_with_source(source_len));
+        let parse_key_list = opt(preceded(
+            tuple((ws1, tag("for"), multispace1)),
+            key_list_with_source(source_len),
+        ));
+        let (rest, ((identifier, identifier_range), _, _, identifier_list, key_list, _, _)) =
+            tuple((
+                located(source_len, parse_identifier),
+                tag("("),
+                ws0,
+                parse_identifier_list,
+                parse_key_list,
+                ws0,
+                tag(")"),
+            ))(input)?;
+        let (identifier_list, identifiers) =
+            identifier_list.map_or((None, Vec::new()), |(list, ranges)| (Some(list), ranges));
+        let (key_list, keys) =
+            key_list.map_or((None, Vec::new()), |(list, ranges)| (Some(list), ranges));
+        Ok((
+            rest,
+            (
+                FunctionCall {
+                    identifier,
+                    identifier_list,
+                    key_list,
+                },
+                FunctionCallSource {
+                    range: source_len - input.len()..source_len - rest.len(),
+                    identifier: identifier_range,
+                    identifiers,
+                    keys,
+                },
+            ),
+        ))
+    }
 }
 
 /// Parses a list of function calls from the input string.
@@ -273,10 +457,21 @@ fn parse_function_call(input: &str) -> IResult<&str, FunctionCall> {
 /// ]);
 /// ```
 fn parse_function_call_list(input: &str) -> IResult<&str, Vec<FunctionCall>> {
-    separated_list1(
-        delimited(multispace1, tag("or"), multispace1),
-        parse_function_call,
-    )(input)
+    map(function_call_list_with_source(input.len()), |(calls, _)| {
+        calls
+    })(input)
+}
+
+fn function_call_list_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (Vec<FunctionCall>, Vec<FunctionCallSource>)> {
+    map(
+        separated_list1(
+            delimited(ws1, tag("or"), ws1),
+            function_call_with_source(source_len),
+        ),
+        |calls| calls.into_iter().unzip(),
+    )
 }
 
 /// Parses a block from the input string.
@@ -296,26 +491,89 @@ fn parse_function_call_list(input: &str) -> IResult<&str, Vec<FunctionCall>> {
 /// });
 /// ```
 fn parse_block(input: &str) -> IResult<&str, Block> {
-    let (input, (_, _, key_list, _, _, _, function_call_list, _, _)) = tuple((
-        tag("on"),
-        multispace1,
-        parse_key_list,
-        multispace0,
-        tag(":"),
-        multispace1,
-        parse_function_call_list,
+    map(block_with_source(input.len()), |(block, _)| block)(input)
+}
+
+fn block_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (Block, BlockSource)> {
+    move |input: &'a str| {
+        let (rest, (_, _, (key_list, keys), _, _, _, (function_call_list, function_calls), _, _)) =
+            tuple((
+                tag("on"),
+                multispace1,
+                key_list_with_source(source_len),
+                ws0,
+                tag(":"),
+                ws1,
+                function_call_list_with_source(source_len),
+                ws1,
+                tag("end"),
+            ))(input)?;
+        Ok((
+            rest,
+            (
+                Block {
+                    key_list,
+                    function_call_list,
+                },
+                BlockSource {
+                    range: source_len - input.len()..source_len - rest.len(),
+                    keys,
+                    function_calls,
+                },
+            ),
+        ))
+    }
+}
+
+/// Parses an `expect` assertion from the input string.
+///
+/// # Example
+///
+/// ```
+/// let result = parse_expect(r#"expect "ddaay" => "đây""#);
+/// assert_eq!(result.unwrap().1, Expect {
+///     input: "ddaay".to_string(),
+///     output: "đây".to_string(),
+/// });
+/// ```
+fn parse_expect(input: &str) -> IResult<&str, Expect> {
+    let (input, (_, _, input_keys, _, _, _, output)) = tuple((
+        tag("expect"),
         multispace1,
-        tag("end"),
+        parse_quoted_key,
+        ws0,
+        tag("=>"),
+        ws0,
+        parse_quoted_key,
     ))(input)?;
     Ok((
         input,
-        Block {
-            key_list,
-            function_call_list,
+        Expect {
+            input: input_keys,
+            output,
         },
     ))
 }
 
+/// A top level item that can follow the imports of a program.
+enum Item {
+    Block(Block, BlockSource),
+    Expect(Expect, Range<usize>),
+}
+
+fn item_with_source<'a>(source_len: usize) -> impl FnMut(&'a str) -> IResult<&'a str, Item> {
+    alt((
+        map(block_with_source(source_len), |(block, source)| {
+            Item::Block(block, source)
+        }),
+        map(located(source_len, parse_expect), |(expect, range)| {
+            Item::Expect(expect, range)
+        }),
+    ))
+}
+
 /// Parses a program from the input string.
 ///
 /// # Example
@@ -333,25 +591,334 @@ fn parse_block(input: &str) -> IResult<&str, Block> {
 ///             key_list: None,
 ///         }],
 ///     }]),
+///     expect_list: None,
 /// });
 /// ```
 pub fn parse_program(input: &str) -> IResult<&str, Program> {
-    let parse_import_list = opt(parse_import_list);
-    let parse_block_list = opt(separated_list1(multispace1, parse_block));
-    let (input, (_, import_list, _, block_list, _)) = tuple((
-        multispace0,
-        parse_import_list,
-        multispace0,
-        parse_block_list,
-        multispace0,
-    ))(input)?;
-    Ok((
-        input,
-        Program {
-            import_list,
-            block_list,
-        },
-    ))
+    map(program_with_source(input.len()), |(program, _)| program)(input)
+}
+
+fn program_with_source<'a>(
+    source_len: usize,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (Program, SourceMap)> {
+    move |input: &'a str| {
+        let parse_import_list = opt(import_list_with_source(source_len));
+        let parse_item_list = opt(separated_list1(ws1, item_with_source(source_len)));
+        let (rest, (_, import_list, _, item_list, _)) =
+            tuple((ws0, parse_import_list, ws0, parse_item_list, ws0))(input)?;
+        let (import_list, imports) =
+            import_list.map_or((None, Vec::new()), |(list, ranges)| (Some(list), ranges));
+        let mut source_map = SourceMap {
+            imports,
+            ..SourceMap::default()
+        };
+        let mut block_list = Vec::new();
+        let mut expect_list = Vec::new();
+        for item in item_list.into_iter().flatten() {
+            match item {
+                Item::Block(block, source) => {
+                    block_list.push(block);
+                    source_map.blocks.push(source);
+                }
+                Item::Expect(expect, range) => {
+                    expect_list.push(expect);
+                    source_map.expects.push(range);
+                }
+            }
+        }
+        Ok((
+            rest,
+            (
+                Program {
+                    import_list,
+                    block_list: Some(block_list).filter(|list| !list.is_empty()),
+                    expect_list: Some(expect_list).filter(|list| !list.is_empty()),
+                },
+                source_map,
+            ),
+        ))
+    }
+}
+
+/// Explains why `parse_program` stopped before the end of a source, by
+/// walking the grammar again one step at a time from where it stopped.
+struct Diagnoser<'a> {
+    source: &'a str,
+}
+
+impl<'a> Diagnoser<'a> {
+    fn offset(&self, input: &str) -> usize {
+        self.source.len() - input.len()
+    }
+
+    fn position(&self, input: &str) -> Position {
+        Position::from_offset(self.source, self.offset(input))
+    }
+
+    /// The span of the token at the start of `input`, empty at the end of
+    /// the source.
+    fn token_span(&self, input: &str) -> Span {
+        let start = self.offset(input);
+        let length = input.find(char::is_whitespace).unwrap_or(input.len());
+        Span::from_offsets(self.source, start, start + length)
+    }
+
+    fn expected(&self, expected: &str, input: &str) -> Diagnostic {
+        let (found, span) = match input.split_whitespace().next() {
+            Some(token) => (
+                format!("found `{token}`"),
+                self.token_span(input.trim_start()),
+            ),
+            None => ("found end of input".to_string(), self.token_span(input)),
+        };
+        Diagnostic::new(format!("expected {expected}, {found}"), span)
+    }
+
+    fn diagnose(&self, input: &'a str) -> Diagnostic {
+        if starts_with_keyword(input, "on") {
+            match self.diagnose_block(input) {
+                Ok(rest) => self.expected("whitespace after `end`", rest),
+                Err(diagnostic) => diagnostic,
+            }
+        } else if starts_with_keyword(input, "import") {
+            match step(preceded(
+                tag("import"),
+                preceded(multispace1, parse_identifier),
+            )(input))
+            {
+                Ok((rest, _)) => Diagnostic::new(
+                    "imports must come before the first `on` block",
+                    Span::from_offsets(self.source, self.offset(input), self.offset(rest)),
+                ),
+                Err(_) => self.expected("a module name after `import`", &input["import".len()..]),
+            }
+        } else if starts_with_keyword(input, "expect") {
+            match self.diagnose_expect(input) {
+                Ok(rest) => self.expected("whitespace after the expected output", rest),
+                Err(diagnostic) => diagnostic,
+            }
+        } else {
+            self.expected("`import`, `on` or `expect`", input)
+        }
+    }
+
+    fn diagnose_expect(&self, input: &'a str) -> Result<&'a str, Diagnostic> {
+        let input = &input["expect".len()..];
+        let Ok((input, _)) = step(multispace1(input)) else {
+            return Err(self.expected("a quoted input after `expect`", input));
+        };
+        let input = self.diagnose_quoted(input, "a quoted input after `expect`")?;
+        let Ok((input, _)) = step(preceded(ws0, tag("=>"))(input)) else {
+            return Err(self.expected("`=>` after the input", input));
+        };
+        let (input, _) = step(ws0(input)).unwrap();
+        self.diagnose_quoted(input, "a quoted output after `=>`")
+    }
+
+    fn diagnose_quoted(&self, input: &'a str, expected: &str) -> Result<&'a str, Diagnostic> {
+        if !input.starts_with('"') {
+            return Err(self.expected(expected, input));
+        }
+        match step(parse_quoted_key(input)) {
+            Ok((rest, _)) => Ok(rest),
+            Err(_) => Err(Diagnostic::new(
+                quoted_key_problem(input),
+                self.token_span(input),
+            )),
+        }
+    }
+
+    fn diagnose_block(&self, input: &'a str) -> Result<&'a str, Diagnostic> {
+        let opened = self.position(input);
+        let input = &input["on".len()..];
+        let Ok((input, _)) = step(multispace1(input)) else {
+            return Err(self.expected("a key after `on`", input));
+        };
+        let input = self.diagnose_key_list(input, "on")?;
+        let Ok((mut input, _)) = step(preceded(ws0, tag(":"))(input)) else {
+            return Err(self.expected("`:` after the keys", input));
+        };
+        if step(ws1(input)).is_err() {
+            return Err(self.expected("whitespace after `:`", input));
+        }
+        loop {
+            let (rest, _) = step(ws0(input)).unwrap();
+            input = self.diagnose_function_call(rest)?;
+            if let Ok((rest, _)) = step(tuple((ws1, tag("or"), ws1))(input)) {
+                input = rest;
+                continue;
+            }
+            return match step(preceded(ws1, tag("end"))(input)) {
+                Ok((rest, _)) => Ok(rest),
+                Err(_) => {
+                    Err(self.expected(&format!("`end` to close block opened at {opened}"), input))
+                }
+            };
+        }
+    }
+
+    fn diagnose_function_call(&self, input: &'a str) -> Result<&'a str, Diagnostic> {
+        let Ok((input, identifier)) = parse_identifier(input) else {
+            return Err(self.expected("a function call", input));
+        };
+        let opened = self.position(input);
+        let Ok((input, _)) = step(tag("(")(input)) else {
+            return Err(self.expected(&format!("`(` after `{identifier}`"), input));
+        };
+        let (input, _) = step(preceded(ws0, opt(parse_identifier_list))(input)).unwrap();
+        let input = match step(tuple((ws1, tag("for"), multispace1))(input)) {
+            Ok((rest, _)) => self.diagnose_key_list(rest, "for")?,
+            Err(_) => input,
+        };
+        match step(preceded(ws0, tag(")"))(input)) {
+            Ok((rest, _)) => Ok(rest),
+            Err(_) => Err(self.expected(
+                &format!("`)` to close the call to `{identifier}` opened at {opened}"),
+                input,
+            )),
+        }
+    }
+
+    fn diagnose_key_list(&self, mut input: &'a str, keyword: &str) -> Result<&'a str, Diagnostic> {
+        let mut previous = keyword;
+        loop {
+            input = match parse_key(input) {
+                Ok((rest, _)) => rest,
+                Err(_) if input.starts_with('"') => {
+                    return Err(Diagnostic::new(
+                        quoted_key_problem(input),
+                        self.token_span(input),
+                    ))
+                }
+                Err(_) => return Err(self.expected(&format!("a key after `{previous}`"), input)),
+            };
+            match step(tuple((ws1, tag("or"), multispace1))(input)) {
+                Ok((rest, _)) => input = rest,
+                Err(_) => return Ok(input),
+            }
+            previous = "or";
+        }
+    }
+
+    /// Finds the next line starting with `import`, `on` or `expect` after the
+    /// start of `input`, to carry on looking for errors from there.
+    fn resync(&self, input: &'a str) -> Option<&'a str> {
+        let mut rest = input;
+        while let Some(index) = rest.find('\n') {
+            rest = rest[index + 1..].trim_start();
+            if ["on", "import", "expect"]
+                .iter()
+                .any(|keyword| starts_with_keyword(rest, keyword))
+            {
+                return Some(rest);
+            }
+        }
+        None
+    }
+}
+
+/// Pins down the error type of a combinator used outside of a parser.
+fn step<O>(result: IResult<&str, O>) -> IResult<&str, O> {
+    result
+}
+
+/// Explains why `parse_quoted_key` rejected a quoted key.
+fn quoted_key_problem(input: &str) -> &'static str {
+    let mut chars = input.chars().skip(1).peekable();
+    if chars.peek() == Some(&'"') {
+        return "quoted keys cannot be empty";
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\n' => return "unterminated quoted key, expected a closing `\"` on the same line",
+            '\\' => match chars.next() {
+                Some('\\' | '"' | 'n' | 't') => {}
+                Some('u') => {
+                    let digits: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    let valid = digits.strip_prefix('{').is_some_and(|hex| {
+                        u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .is_some()
+                    });
+                    if !valid {
+                        return r"invalid unicode escape, expected `\u{...}` with a hexadecimal code point";
+                    }
+                }
+                _ => {
+                    return r#"unknown escape sequence, expected one of `\\`, `\"`, `\n`, `\t` or `\u{...}`"#
+                }
+            },
+            _ => {}
+        }
+    }
+    "unterminated quoted key, expected a closing `\"` on the same line"
+}
+
+fn starts_with_keyword(input: &str, keyword: &str) -> bool {
+    input
+        .strip_prefix(keyword)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// Parses a complete goxscript source.
+///
+/// Unlike `parse_program`, which stops quietly at the first thing it does not
+/// understand, this fails unless the whole source was consumed, and explains
+/// each error with its position in the source.
+///
+/// # Example
+///
+/// ```
+/// let diagnostics = parse_script("on a:\n  hello()\n").unwrap_err();
+/// assert_eq!(
+///     diagnostics[0].to_string(),
+///     "2:10: expected `end` to close block opened at 1:1, found end of input"
+/// );
+/// ```
+pub fn parse_script(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    parse_script_with_source_map(source).map(|(program, _)| program)
+}
+
+/// Same as `parse_script`, but also returns where each node of the program
+/// is in the source.
+pub fn parse_script_with_source_map(source: &str) -> Result<(Program, SourceMap), Vec<Diagnostic>> {
+    let (rest, parsed) = program_with_source(source.len())(source).map_err(|err| {
+        vec![Diagnostic::new(
+            err.to_string(),
+            Span::from_offsets(source, 0, 0),
+        )]
+    })?;
+    if rest.is_empty() {
+        return Ok(parsed);
+    }
+    let diagnoser = Diagnoser { source };
+    let mut diagnostics = Vec::new();
+    let mut input = rest;
+    loop {
+        diagnostics.push(diagnoser.diagnose(input));
+        let Some(next) = diagnoser.resync(input) else {
+            break;
+        };
+        let (rest, _) = tuple((
+            many0(preceded(
+                ws0,
+                alt((
+                    map(parse_import, |_| ()),
+                    map(parse_block, |_| ()),
+                    map(parse_expect, |_| ()),
+                )),
+            )),
+            ws0,
+        ))(next)
+        .unwrap();
+        if rest.is_empty() {
+            break;
+        }
+        input = rest;
+    }
+    Err(diagnostics)
 }
 
 #[test]
@@ -800,7 +1367,8 @@ fn parse_program_single_block() {
                         identifier_list: None,
                         key_list: None
                     }]
-                }])
+                }]),
+                expect_list: None
             }
     );
 }
@@ -823,7 +1391,8 @@ fn parse_program_single_block_with_import() {
                         identifier_list: None,
                         key_list: None
                     }]
-                }])
+                }]),
+                expect_list: None
             }
     );
 }
@@ -862,7 +1431,8 @@ fn parse_program_multiple_block() {
                             key_list: None
                         }]
                     }
-                ])
+                ]),
+                expect_list: None
             }
     );
 }
@@ -908,7 +1478,8 @@ fn parse_program_multiple_block_with_multiple_import() {
                             key_list: None
                         }]
                     }
-                ])
+                ]),
+                expect_list: None
             }
     );
 }
@@ -990,7 +1561,228 @@ fn parse_full_program_success() {
                             }
                         ]
                     }
-                ])
+                ]),
+                expect_list: None
             }
     );
 }
+
+#[test]
+fn test_parse_script() {
+    let program = parse_script("import telex\n\non a: hello() end\n").unwrap();
+    assert_eq!(program.block_list.unwrap().len(), 1);
+}
+
+#[test]
+fn test_parse_script_rejects_trailing_input() {
+    let diagnostics = parse_script("on a: hello() end\n}").unwrap_err();
+    assert_eq!(
+        diagnostics,
+        vec![Diagnostic::new(
+            "expected `import`, `on` or `expect`, found `}`",
+            Span::from_offsets("on a: hello() end\n}", 18, 19),
+        )]
+    );
+}
+
+#[test]
+fn test_parse_script_missing_end() {
+    let source = "import telex\n\non a:\n  hello()\n";
+    let diagnostics = parse_script(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].message,
+        "expected `end` to close block opened at 3:1, found end of input"
+    );
+    assert_eq!(
+        diagnostics[0].span.start,
+        Position {
+            line: 4,
+            column: 10
+        }
+    );
+}
+
+#[test]
+fn test_parse_script_unclosed_call() {
+    let diagnostics = parse_script("on a: hello(world end").unwrap_err();
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "1:19: expected `)` to close the call to `hello` opened at 1:12, found `end`"
+    );
+}
+
+#[test]
+fn test_parse_script_import_after_block() {
+    let diagnostics = parse_script("on a: hello() end\nimport telex").unwrap_err();
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "2:1: imports must come before the first `on` block"
+    );
+    assert_eq!(
+        diagnostics[0].span.end,
+        Position {
+            line: 2,
+            column: 13
+        }
+    );
+}
+
+#[test]
+fn test_parse_script_reports_every_broken_block() {
+    let source = "on a hello() end\n\non b: hello() end\n\non c: hello( end\n";
+    let diagnostics = parse_script(source).unwrap_err();
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "1:6: expected `:` after the keys, found `hello()`",
+            "5:17: expected `)` to close the call to `hello` opened at 5:12, found end of input",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_program_with_comments() {
+    let input = r#"
+        # Telex, with a tone key for `'`
+        import telex # built-in
+
+        on s or ': # acute
+          add_tone(acute) # the only rule
+        end
+        # trailing comment"#;
+    let result = parse_program(input);
+    assert!(result.is_ok());
+    let (rest, program) = result.unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(
+        program,
+        Program {
+            import_list: Some(vec![Import {
+                identifier: "telex".to_string()
+            }]),
+            block_list: Some(vec![Block {
+                key_list: vec!["s".to_string(), "'".to_string()],
+                function_call_list: vec![FunctionCall {
+                    identifier: "add_tone".to_string(),
+                    identifier_list: Some(vec!["acute".to_string()]),
+                    key_list: None,
+                }],
+            }]),
+            expect_list: None,
+        }
+    );
+}
+
+#[test]
+fn test_parse_hash_key_is_not_a_comment() {
+    let input = "on # or a: hello(abc for # or a) end";
+    let result = parse_block(input);
+    assert!(result.is_ok());
+    let block = result.unwrap().1;
+    assert_eq!(block.key_list, vec!["#", "a"]);
+    assert_eq!(
+        block.function_call_list[0].key_list,
+        Some(vec!["#".to_string(), "a".to_string()])
+    );
+}
+
+#[test]
+fn test_parse_quoted_keys() {
+    let input = r#""dd" or " " or "\"" or "\\" or "\u{111}" or a"#;
+    let result = parse_key_list(input);
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap().1, vec!["dd", " ", "\"", "\\", "đ", "a"]);
+}
+
+#[test]
+fn test_parse_quoted_key_fail() {
+    assert!(parse_quoted_key(r#""""#).is_err());
+    assert!(parse_quoted_key("\"d\nd\"").is_err());
+    assert!(parse_quoted_key(r#""\q""#).is_err());
+    assert!(parse_quoted_key(r#""dd"#).is_err());
+}
+
+#[test]
+fn test_parse_script_quoted_key_errors() {
+    let diagnostics = parse_script(r#"on a or "\q": hello() end"#).unwrap_err();
+    assert_eq!(
+        diagnostics[0].to_string(),
+        r#"1:9: unknown escape sequence, expected one of `\\`, `\"`, `\n`, `\t` or `\u{...}`"#
+    );
+    let diagnostics = parse_script("on \"dd: hello() end").unwrap_err();
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "1:4: unterminated quoted key, expected a closing `\"` on the same line"
+    );
+}
+
+#[test]
+fn test_parse_script_source_map() {
+    let source = "import telex\n\non a or \"dd\": hello(x or y for a) or world() end";
+    let (_, source_map) = parse_script_with_source_map(source).unwrap();
+    assert_eq!(source_map.imports, vec![0..12]);
+    let block = &source_map.blocks[0];
+    assert_eq!(&source[block.range.clone()], &source[14..]);
+    assert_eq!(
+        block
+            .keys
+            .iter()
+            .map(|key| &source[key.clone()])
+            .collect::<Vec<_>>(),
+        vec!["a", "\"dd\""]
+    );
+    let hello = &block.function_calls[0];
+    assert_eq!(&source[hello.range.clone()], "hello(x or y for a)");
+    assert_eq!(&source[hello.identifier.clone()], "hello");
+    assert_eq!(
+        hello
+            .identifiers
+            .iter()
+            .map(|identifier| &source[identifier.clone()])
+            .collect::<Vec<_>>(),
+        vec!["x", "y"]
+    );
+    assert_eq!(&source[hello.keys[0].clone()], "a");
+    assert_eq!(&source[block.function_calls[1].range.clone()], "world()");
+}
+
+#[test]
+fn parse_program_with_expects() {
+    let source = "on s: add_tone(acute) end\n\
+                  expect \"as\" => \"á\"\n\
+                  on j: add_tone(underdot) end\n\
+                  expect \"aj\"=>\"ạ\"";
+    let (program, source_map) = parse_script_with_source_map(source).unwrap();
+    assert_eq!(program.block_list.unwrap().len(), 2);
+    assert_eq!(
+        program.expect_list,
+        Some(vec![
+            Expect {
+                input: "as".to_string(),
+                output: "á".to_string(),
+            },
+            Expect {
+                input: "aj".to_string(),
+                output: "ạ".to_string(),
+            },
+        ])
+    );
+    assert_eq!(source_map.expects, vec![26..45, 75..93]);
+}
+
+#[test]
+fn test_parse_script_expect_errors() {
+    let diagnostics =
+        parse_script("expect \"as\" \"á\"\nexpect as => \"á\"\nexpect \"as\" =>").unwrap_err();
+    let messages: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "1:13: expected `=>` after the input, found `\"á\"`",
+            "2:8: expected a quoted input after `expect`, found `as`",
+            "3:15: expected a quoted output after `=>`, found end of input",
+        ]
+    );
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use super::checker;
+use super::diagnostic::{Diagnostic, Span};
+use super::parser::{parse_script_with_source_map, Block, Program, SourceMap};
+
+/// Modules shipped with goxkey. Their names are reserved: a user module with
+/// the same name is never loaded.
+pub const BUILTIN_MODULES: [(&str, &str); 2] = [
+    ("telex", include_str!("modules/telex.gox")),
+    ("vni", include_str!("modules/vni.gox")),
+];
+
+/// Finds the source of the user modules a script imports.
+pub trait ModuleLoader {
+    fn load(&self, name: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String>> ModuleLoader for F {
+    fn load(&self, name: &str) -> Option<String> {
+        self(name)
+    }
+}
+
+/// Loads `import name` from `name.gox` in a directory.
+pub struct ModuleDir(pub PathBuf);
+
+impl ModuleLoader for ModuleDir {
+    fn load(&self, name: &str) -> Option<String> {
+        fs::read_to_string(self.0.join(format!("{name}.gox"))).ok()
+    }
+}
+
+struct Resolver<'a> {
+    loader: &'a dyn ModuleLoader,
+    /// The modules being resolved, to detect import cycles.
+    stack: Vec<String>,
+    resolved: HashMap<String, Vec<Block>>,
+}
+
+impl Resolver<'_> {
+    fn load_source(&self, name: &str) -> Option<String> {
+        match BUILTIN_MODULES.iter().find(|(builtin, _)| *builtin == name) {
+            Some((_, source)) => Some(source.to_string()),
+            None => self.loader.load(name),
+        }
+    }
+
+    /// The blocks of module `name`, with the blocks of its own imports first.
+    fn resolve_module(&mut self, name: &str) -> Result<Vec<Block>, String> {
+        if self.stack.iter().any(|module| module == name) {
+            let mut cycle = self.stack.clone();
+            cycle.push(name.to_string());
+            return Err(format!("import cycle: {}", cycle.join(" → ")));
+        }
+        if let Some(blocks) = self.resolved.get(name) {
+            return Ok(blocks.clone());
+        }
+        let source = self
+            .load_source(name)
+            .ok_or_else(|| format!("cannot find module `{name}`"))?;
+        let (program, source_map) = parse_script_with_source_map(&source)
+            .map_err(|diagnostics| module_error(name, &diagnostics))?;
+        let errors: Vec<_> = checker::check(&program, &source_map, &source)
+            .into_iter()
+            .filter(Diagnostic::is_error)
+            .collect();
+        if !errors.is_empty() {
+            return Err(module_error(name, &errors));
+        }
+        self.stack.push(name.to_string());
+        let mut blocks = Vec::new();
+        let mut result = Ok(());
+        for import in program.import_list.iter().flatten() {
+            match self.resolve_module(&import.identifier) {
+                Ok(imported) => blocks.extend(imported),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        self.stack.pop();
+        result?;
+        blocks.extend(program.block_list.into_iter().flatten());
+        self.resolved.insert(name.to_string(), blocks.clone());
+        Ok(blocks)
+    }
+}
+
+fn module_error(name: &str, diagnostics: &[Diagnostic]) -> String {
+    let details: Vec<_> = diagnostics.iter().map(Diagnostic::to_string).collect();
+    format!("in module `{name}`: {}", details.join("; "))
+}
+
+/// Replaces the imports of a program by the blocks of the modules it imports.
+///
+/// Blocks are merged in order: the modules in the order they are imported,
+/// each one after its own imports, then the program's own blocks. When
+/// several blocks bind the same key, the last one wins, so a script can
+/// import a base layout and override a few keys of it.
+///
+/// Only the program's own `expect` assertions are kept: the ones of the
+/// modules it imports test those modules, not the program.
+pub fn resolve_imports(
+    program: Program,
+    source_map: &SourceMap,
+    source: &str,
+    loader: &dyn ModuleLoader,
+) -> Result<Program, Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        loader,
+        stack: Vec::new(),
+        resolved: HashMap::new(),
+    };
+    let mut blocks = Vec::new();
+    let mut diagnostics = Vec::new();
+    for (import, range) in program
+        .import_list
+        .iter()
+        .flatten()
+        .zip(&source_map.imports)
+    {
+        match resolver.resolve_module(&import.identifier) {
+            Ok(imported) => blocks.extend(imported),
+            Err(message) => diagnostics.push(Diagnostic::new(
+                message,
+                Span::from_offsets(source, range.start, range.end),
+            )),
+        }
+    }
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    blocks.extend(program.block_list.into_iter().flatten());
+    Ok(Program {
+        import_list: None,
+        block_list: Some(blocks).filter(|blocks| !blocks.is_empty()),
+        expect_list: program.expect_list,
+    })
+}
+
+#[cfg(test)]
+fn resolve_source(source: &str, modules: &[(&str, &str)]) -> Result<Program, Vec<String>> {
+    let modules: HashMap<String, String> = modules
+        .iter()
+        .map(|(name, source)| (name.to_string(), source.to_string()))
+        .collect();
+    let loader = |name: &str| modules.get(name).cloned();
+    let (program, source_map) = parse_script_with_source_map(source).unwrap();
+    resolve_imports(program, &source_map, source, &loader)
+        .map_err(|diagnostics| diagnostics.iter().map(|d| d.to_string()).collect())
+}
+
+#[test]
+fn test_builtin_modules_compile() {
+    for (name, source) in BUILTIN_MODULES {
+        let (program, source_map) = parse_script_with_source_map(source).unwrap();
+        assert_eq!(
+            checker::check(&program, &source_map, source),
+            vec![],
+            "{name}"
+        );
+        assert!(super::compiler::compile(&program).is_ok(), "{name}");
+    }
+}
+
+#[test]
+fn test_resolve_override_order() {
+    let base = "import telex\non s: add_tone(grave) end\non q: remove_tone() end";
+    let program =
+        resolve_source("import base\non q: add_tone(acute) end", &[("base", base)]).unwrap();
+    let method = super::compiler::compile(&program).unwrap();
+    use super::compiler::{Tone, Transform};
+    // The script overrides `base`, which overrides `telex`.
+    assert_eq!(
+        method.transforms_for('q'),
+        Some(&[Transform::AddTone(Tone::Acute)][..])
+    );
+    assert_eq!(
+        method.transforms_for('s'),
+        Some(&[Transform::AddTone(Tone::Grave)][..])
+    );
+    assert_eq!(
+        method.transforms_for('f'),
+        Some(&[Transform::AddTone(Tone::Grave)][..])
+    );
+}
+
+#[test]
+fn test_resolve_missing_module() {
+    assert_eq!(
+        resolve_source("import telex\nimport mymod", &[]),
+        Err(vec!["2:1: cannot find module `mymod`".to_string()])
+    );
+}
+
+#[test]
+fn test_resolve_cycle() {
+    assert_eq!(
+        resolve_source(
+            "import a",
+            &[("a", "import b"), ("b", "import telex\nimport a")]
+        ),
+        Err(vec!["1:1: import cycle: a → b → a".to_string()])
+    );
+}
+
+#[test]
+fn test_resolve_broken_module() {
+    assert_eq!(
+        resolve_source("import a", &[("a", "on s: add_tone(sharp) end")]),
+        Err(vec![
+            "1:1: in module `a`: 1:16: `sharp` is not a valid argument for `add_tone`, \
+             expected one of `acute`, `grave`, `hook_above`, `tilde`, `underdot`"
+                .to_string()
+        ])
+    );
+}
+
+#[test]
+fn test_builtin_modules_cannot_be_shadowed() {
+    let program = resolve_source("import vni", &[("vni", "on s: add_tone(acute) end")]).unwrap();
+    let method = super::compiler::compile(&program).unwrap();
+    assert!(method.transforms_for('s').is_none());
+    assert!(method.transforms_for('1').is_some());
+}
@@ -0,0 +1,435 @@
+use std::ops::Range;
+
+use super::compiler::{InputMethod, LetterModification, Tone, Transform};
+use crate::engine::TransformOutcome;
+
+use LetterModification::*;
+
+const TONES: [Tone; 5] = [
+    Tone::Acute,
+    Tone::Grave,
+    Tone::HookAbove,
+    Tone::Tilde,
+    Tone::Underdot,
+];
+
+// Every vowel, with its letter modification, followed by its five toned forms
+// in the same order as `TONES`.
+const VOWEL_TABLE: [(char, Option<LetterModification>, &str); 12] = [
+    ('a', None, "aáàảãạ"),
+    ('a', Some(Breve), "ăắằẳẵặ"),
+    ('a', Some(Circumflex), "âấầẩẫậ"),
+    ('e', None, "eéèẻẽẹ"),
+    ('e', Some(Circumflex), "êếềểễệ"),
+    ('i', None, "iíìỉĩị"),
+    ('o', None, "oóòỏõọ"),
+    ('o', Some(Circumflex), "ôốồổỗộ"),
+    ('o', Some(Horn), "ơớờởỡợ"),
+    ('u', None, "uúùủũụ"),
+    ('u', Some(Horn), "ưứừửữự"),
+    ('y', None, "yýỳỷỹỵ"),
+];
+
+// Vowel clusters that can appear in a Vietnamese syllable. Letter
+// modifications are only applied when they produce one of these.
+const VALID_CLUSTERS: [&str; 54] = [
+    "a", "ă", "â", "e", "ê", "i", "o", "ô", "ơ", "u", "ư", "y", "ai", "ao", "au", "ay", "âu", "ây",
+    "eo", "êu", "ia", "iê", "iu", "oa", "oă", "oe", "oi", "ôi", "ơi", "oo", "ua", "uâ", "uê", "ui",
+    "uô", "uơ", "uy", "ưa", "ưi", "ưu", "ươ", "yê", "iêu", "yêu", "oai", "oay", "oeo", "uây",
+    "uôi", "uya", "uyê", "uyu", "ươi", "ươu",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Letter {
+    base: char,
+    modification: Option<LetterModification>,
+    tone: Option<Tone>,
+    uppercase: bool,
+}
+
+impl Letter {
+    fn from_char(c: char) -> Self {
+        let uppercase = c.is_uppercase();
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        if lower == 'đ' {
+            return Self {
+                base: 'd',
+                modification: Some(Dyet),
+                tone: None,
+                uppercase,
+            };
+        }
+        for (base, modification, forms) in VOWEL_TABLE {
+            if let Some(index) = forms.chars().position(|f| f == lower) {
+                return Self {
+                    base,
+                    modification,
+                    tone: index.checked_sub(1).map(|i| TONES[i]),
+                    uppercase,
+                };
+            }
+        }
+        Self {
+            base: lower,
+            modification: None,
+            tone: None,
+            uppercase,
+        }
+    }
+
+    fn to_char(self) -> char {
+        let lower = if self.modification == Some(Dyet) {
+            'đ'
+        } else {
+            VOWEL_TABLE
+                .iter()
+                .find(|(base, modification, _)| {
+                    *base == self.base && *modification == self.modification
+                })
+                .and_then(|(_, _, forms)| {
+                    let index = self
+                        .tone
+                        .map_or(0, |tone| TONES.iter().position(|t| *t == tone).unwrap() + 1);
+                    forms.chars().nth(index)
+                })
+                .unwrap_or(self.base)
+        };
+        if self.uppercase {
+            lower.to_uppercase().next().unwrap_or(lower)
+        } else {
+            lower
+        }
+    }
+
+    fn is_vowel(&self) -> bool {
+        "aeiouy".contains(self.base)
+    }
+
+    fn supports(&self, modification: LetterModification) -> bool {
+        modification.targets().contains(&self.base)
+    }
+}
+
+/// Find the vowel cluster of the word, skipping the `u` of `qu` and the
+/// `i` of `gi` which belong to the initial consonant.
+fn vowel_cluster(word: &[Letter]) -> Option<Range<usize>> {
+    let mut start = word.iter().position(|l| l.is_vowel())?;
+    let next_is_vowel = word.get(start + 1).is_some_and(|l| l.is_vowel());
+    if start > 0 && next_is_vowel {
+        let (previous, current) = (word[start - 1].base, word[start].base);
+        if (previous == 'q' && current == 'u') || (previous == 'g' && current == 'i') {
+            start += 1;
+        }
+    }
+    let end = word[start..]
+        .iter()
+        .position(|l| !l.is_vowel())
+        .map_or(word.len(), |i| start + i);
+    Some(start..end)
+}
+
+fn is_valid_cluster(word: &[Letter]) -> bool {
+    let Some(range) = vowel_cluster(word) else {
+        return true;
+    };
+    let cluster: String = word[range]
+        .iter()
+        .map(|l| {
+            Letter {
+                tone: None,
+                uppercase: false,
+                ..*l
+            }
+            .to_char()
+        })
+        .collect();
+    VALID_CLUSTERS.contains(&cluster.as_str())
+}
+
+/// Where the tone mark of the word should go.
+fn tone_position(word: &[Letter]) -> Option<usize> {
+    let range = vowel_cluster(word)?;
+    let cluster = &word[range.clone()];
+    if let Some(index) = cluster.iter().rposition(|l| l.modification.is_some()) {
+        return Some(range.start + index);
+    }
+    let has_final_consonant = range.end < word.len();
+    Some(if cluster.len() == 1 {
+        range.start
+    } else if has_final_consonant {
+        range.end - 1
+    } else if cluster.len() >= 3 {
+        range.start + 1
+    } else {
+        range.start
+    })
+}
+
+fn current_tone(word: &[Letter]) -> Option<Tone> {
+    word.iter().find_map(|l| l.tone)
+}
+
+fn clear_tone(word: &mut [Letter]) {
+    word.iter_mut().for_each(|l| l.tone = None);
+}
+
+/// Move the tone mark to where it belongs after the word changed.
+fn normalize_tone(word: &mut [Letter]) {
+    let Some(tone) = current_tone(word) else {
+        return;
+    };
+    if let Some(position) = tone_position(word) {
+        clear_tone(word);
+        word[position].tone = Some(tone);
+    }
+}
+
+struct State {
+    word: Vec<Letter>,
+    inserted_uw: Option<usize>,
+    outcome: TransformOutcome,
+}
+
+impl State {
+    fn push_key(&mut self, key: char) {
+        self.word.push(Letter::from_char(key));
+    }
+
+    fn add_tone(&mut self, tone: Tone, key: char) -> bool {
+        let Some(position) = tone_position(&self.word) else {
+            return false;
+        };
+        let previous = current_tone(&self.word);
+        clear_tone(&mut self.word);
+        if previous == Some(tone) {
+            self.push_key(key);
+            self.outcome.tone_mark_removed = true;
+        } else {
+            self.word[position].tone = Some(tone);
+        }
+        true
+    }
+
+    fn remove_tone(&mut self) -> bool {
+        if current_tone(&self.word).is_none() {
+            return false;
+        }
+        clear_tone(&mut self.word);
+        true
+    }
+
+    fn candidates(&self, modification: LetterModification) -> Vec<usize> {
+        if modification == Dyet {
+            return (0..self.word.len()).collect();
+        }
+        vowel_cluster(&self.word)
+            .map(|range| range.rev().collect())
+            .unwrap_or_default()
+    }
+
+    /// The other half of `uo`, which takes a horn together with `o`.
+    fn horn_pair(&self, index: usize) -> Option<usize> {
+        let letter = self.word[index].base;
+        if letter == 'o' && index > 0 && self.word[index - 1].base == 'u' {
+            return Some(index - 1);
+        }
+        if letter == 'u' && self.word.get(index + 1).is_some_and(|l| l.base == 'o') {
+            return Some(index + 1);
+        }
+        None
+    }
+
+    fn modify_letter(
+        &mut self,
+        modifications: &[LetterModification],
+        targets: &[char],
+        key: char,
+    ) -> bool {
+        // A key that is itself a target only modifies its own letter, so
+        // `a` turns `a` into `â` but leaves `e` alone.
+        let lower_key = key.to_lowercase().next().unwrap_or(key);
+        let allowed: Vec<char> = if targets.contains(&lower_key) {
+            vec![lower_key]
+        } else {
+            targets.to_vec()
+        };
+        for &modification in modifications {
+            for index in self.candidates(modification) {
+                let letter = self.word[index];
+                if !letter.supports(modification)
+                    || (!allowed.is_empty() && !allowed.contains(&letter.base))
+                {
+                    continue;
+                }
+                let mut indices = vec![index];
+                if modification == Horn {
+                    indices.extend(self.horn_pair(index));
+                }
+                if letter.modification == Some(modification) {
+                    indices
+                        .iter()
+                        .for_each(|&i| self.word[i].modification = None);
+                    self.push_key(key);
+                    self.outcome.letter_modification_removed = true;
+                    return true;
+                }
+                let mut word = self.word.clone();
+                indices
+                    .iter()
+                    .for_each(|&i| word[i].modification = Some(modification));
+                if modification == Dyet || is_valid_cluster(&word) {
+                    self.word = word;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn insert_uw(&mut self, key: char) -> bool {
+        self.word.push(Letter {
+            base: 'u',
+            modification: Some(Horn),
+            tone: None,
+            uppercase: key.is_uppercase(),
+        });
+        self.inserted_uw = Some(self.word.len() - 1);
+        true
+    }
+
+    fn reset_inserted_uw(&mut self, key: char) -> bool {
+        if self.inserted_uw.is_none() || self.inserted_uw != self.word.len().checked_sub(1) {
+            return false;
+        }
+        self.word.pop();
+        self.push_key(key);
+        self.inserted_uw = None;
+        self.outcome.letter_modification_removed = true;
+        true
+    }
+
+    fn apply(&mut self, transform: &Transform, key: char) -> bool {
+        match transform {
+            Transform::AddTone(tone) => self.add_tone(*tone, key),
+            Transform::RemoveTone => self.remove_tone(),
+            Transform::ModifyLetter {
+                modifications,
+                targets,
+            } => self.modify_letter(modifications, targets, key),
+            Transform::InsertUw => self.insert_uw(key),
+            Transform::ResetInsertedUw => self.reset_inserted_uw(key),
+        }
+    }
+}
+
+/// Runs the typed keys through a compiled input method, the same way
+/// `vi::telex::transform_buffer` does for the built-in Telex.
+///
+/// # Example
+///
+/// ```
+/// let (_, program) = parse_program("on s: add_tone(acute) end").unwrap();
+/// let method = compile(&program).unwrap();
+/// let mut output = String::new();
+/// transform_buffer(&method, "chas".chars(), &mut output);
+/// assert_eq!(output, "chá");
+/// ```
+pub fn transform_buffer<I>(method: &InputMethod, buffer: I, output: &mut String) -> TransformOutcome
+where
+    I: IntoIterator<Item = char>,
+{
+    let mut state = State {
+        word: Vec::new(),
+        inserted_uw: None,
+        outcome: TransformOutcome::default(),
+    };
+    let mut typed = Vec::new();
+    for key in buffer {
+        let lower_key = key.to_lowercase().next().unwrap_or(key);
+        let applied = method
+            .transforms_after(&typed, lower_key)
+            .is_some_and(|transforms| transforms.iter().any(|t| state.apply(t, key)));
+        if !applied {
+            state.push_key(key);
+        }
+        normalize_tone(&mut state.word);
+        typed.push(lower_key);
+    }
+    output.extend(state.word.iter().map(|l| l.to_char()));
+    state.outcome
+}
+
+#[cfg(test)]
+const TELEX_SCRIPT: &str = include_str!("modules/telex.gox");
+
+#[cfg(test)]
+const VNI_SCRIPT: &str = include_str!("modules/vni.gox");
+
+#[cfg(test)]
+fn transform(script: &str, input: &str) -> (String, TransformOutcome) {
+    let (_, program) = super::parser::parse_program(script).unwrap();
+    let method = super::compiler::compile(&program).unwrap();
+    let mut output = String::new();
+    let outcome = transform_buffer(&method, input.chars(), &mut output);
+    (output, outcome)
+}
+
+#[test]
+fn test_telex_script_words() {
+    for (input, expected) in [
+        ("vieetj", "việt"),
+        ("ddaay", "đây"),
+        ("nguoiwf", "người"),
+        ("hoawjc", "hoặc"),
+        ("muaw", "mưa"),
+        ("tieengs", "tiếng"),
+        ("Tieesng", "Tiếng"),
+        ("quaas", "quấ"),
+        ("gif", "gì"),
+        ("khoais", "khoái"),
+        ("hoaf", "hòa"),
+        ("tw", "tư"),
+        ("chafz", "cha"),
+    ] {
+        assert_eq!(transform(TELEX_SCRIPT, input).0, expected, "{input}");
+    }
+}
+
+#[test]
+fn test_telex_script_undo() {
+    let (output, outcome) = transform(TELEX_SCRIPT, "tess");
+    assert_eq!(output, "tes");
+    assert!(outcome.tone_mark_removed);
+
+    let (output, outcome) = transform(TELEX_SCRIPT, "aaa");
+    assert_eq!(output, "aa");
+    assert!(outcome.letter_modification_removed);
+
+    let (output, outcome) = transform(TELEX_SCRIPT, "ww");
+    assert_eq!(output, "w");
+    assert!(outcome.letter_modification_removed);
+}
+
+#[test]
+fn test_vni_script_words() {
+    for (input, expected) in [
+        ("vie65t", "việt"),
+        ("d9a6y", "đây"),
+        ("nguo7i2", "người"),
+        ("Tie61ng", "Tiếng"),
+        ("a81", "ắ"),
+    ] {
+        assert_eq!(transform(VNI_SCRIPT, input).0, expected, "{input}");
+    }
+}
+
+#[test]
+fn test_multi_character_trigger() {
+    let script = r#"
+        on "dd": letter_mod(dyet for d) end
+        on "aa": letter_mod(circumflex for a) end
+        on s: add_tone(acute) end
+    "#;
+    assert_eq!(transform(script, "ddaay").0, "đây");
+    assert_eq!(transform(script, "dady").0, "dady");
+}
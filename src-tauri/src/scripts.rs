@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::config::ConfigStore;
+use crate::scripting::compiler::InputMethod;
+use crate::scripting::resolver::{ModuleDir, BUILTIN_MODULES};
+use crate::scripting::{self, diagnostic::Diagnostic};
+
+const SCRIPT_EXTENSION: &str = "gox";
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptInfo {
+    pub name: String,
+    pub is_active: bool,
+}
+
+/// The user's goxscript files, stored as `<name>.gox` in a directory. The
+/// same directory is used to resolve imports, so a script can import any
+/// other script by its name.
+pub struct ScriptStore {
+    dir: PathBuf,
+}
+
+impl ScriptStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The store in the user's config directory.
+    pub fn user() -> Self {
+        Self::new(ConfigStore::get_scripts_dir())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.{SCRIPT_EXTENSION}"))
+    }
+
+    /// The names of the scripts in the store, sorted.
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == SCRIPT_EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    if validate_name(name).is_ok() {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn infos(&self, active: Option<&str>) -> Result<Vec<ScriptInfo>, String> {
+        let names = self
+            .list()
+            .map_err(|err| format!("cannot list scripts: {err}"))?;
+        Ok(names
+            .into_iter()
+            .map(|name| ScriptInfo {
+                is_active: active == Some(name.as_str()),
+                name,
+            })
+            .collect())
+    }
+
+    pub fn read(&self, name: &str) -> Result<String, String> {
+        validate_name(name)?;
+        fs::read_to_string(self.path(name)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => format!("script `{name}` does not exist"),
+            _ => format!("cannot read script `{name}`: {err}"),
+        })
+    }
+
+    pub fn create(&self, name: &str, source: &str) -> Result<(), String> {
+        validate_name(name)?;
+        let path = self.path(name);
+        if path.exists() {
+            return Err(format!("script `{name}` already exists"));
+        }
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(path, source))
+            .map_err(|err| format!("cannot create script `{name}`: {err}"))
+    }
+
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<(), String> {
+        validate_name(name)?;
+        validate_name(new_name)?;
+        let (from, to) = (self.path(name), self.path(new_name));
+        if !from.exists() {
+            return Err(format!("script `{name}` does not exist"));
+        }
+        if to.exists() {
+            return Err(format!("script `{new_name}` already exists"));
+        }
+        fs::rename(from, to).map_err(|err| format!("cannot rename script `{name}`: {err}"))
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        validate_name(name)?;
+        fs::remove_file(self.path(name)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => format!("script `{name}` does not exist"),
+            _ => format!("cannot delete script `{name}`: {err}"),
+        })
+    }
+
+    /// Checks a script for errors and warnings, resolving its imports from
+    /// the store.
+    pub fn validate(&self, name: &str) -> Result<Vec<Diagnostic>, String> {
+        let source = self.read(name)?;
+        Ok(scripting::check_script(&source, &self.modules()))
+    }
+
+    pub fn compile(&self, name: &str) -> Result<InputMethod, String> {
+        let source = self.read(name)?;
+        scripting::compile_script(&source, &self.modules())
+            .map_err(|err| format!("script `{name}` does not compile:\n{err}"))
+    }
+
+    fn modules(&self) -> ModuleDir {
+        ModuleDir(self.dir.clone())
+    }
+}
+
+/// Script names are used as module names in `import` and as file names, so
+/// they are limited to ASCII letters, digits and `_`, and cannot take the
+/// name of a built-in module.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("script name cannot be empty".to_string());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "invalid script name `{name}`, use only letters, digits and `_`"
+        ));
+    }
+    if BUILTIN_MODULES.iter().any(|(builtin, _)| *builtin == name) {
+        return Err(format!("`{name}` is the name of a built-in module"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn temp_store(test: &str) -> ScriptStore {
+    let dir = std::env::temp_dir().join(format!("goxkey-scripts-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    ScriptStore::new(dir)
+}
+
+#[test]
+fn test_validate_name() {
+    assert!(validate_name("my_telex2").is_ok());
+    assert!(validate_name("").is_err());
+    assert!(validate_name("../config").is_err());
+    assert!(validate_name("vni").is_err());
+}
+
+#[test]
+fn test_script_store() {
+    let store = temp_store("store");
+    assert_eq!(store.list().unwrap(), Vec::<String>::new());
+    store.create("mine", "import telex\n").unwrap();
+    store.create("base", "import vni\n").unwrap();
+    assert_eq!(
+        store.create("mine", ""),
+        Err("script `mine` already exists".to_string())
+    );
+    assert_eq!(store.list().unwrap(), vec!["base", "mine"]);
+    assert_eq!(
+        store.rename("mine", "base"),
+        Err("script `base` already exists".to_string())
+    );
+    store.rename("mine", "ours").unwrap();
+    assert_eq!(store.read("ours"), Ok("import telex\n".to_string()));
+    store.delete("ours").unwrap();
+    assert_eq!(
+        store.read("ours"),
+        Err("script `ours` does not exist".to_string())
+    );
+    assert_eq!(store.list().unwrap(), vec!["base"]);
+    let _ = fs::remove_dir_all(store.dir());
+}
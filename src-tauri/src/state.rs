@@ -10,6 +10,8 @@ use crate::platform::is_launch_on_login;
 pub enum TypingMethodDto {
     Telex,
     Vni,
+    /// A goxscript from the user's scripts.
+    Custom { name: String },
 }
 
 impl From<TypingMethod> for TypingMethodDto {
@@ -21,15 +23,6 @@ impl From<TypingMethod> for TypingMethodDto {
     }
 }
 
-impl From<TypingMethodDto> for TypingMethod {
-    fn from(value: TypingMethodDto) -> Self {
-        match value {
-            TypingMethodDto::Telex => TypingMethod::Telex,
-            TypingMethodDto::Vni => TypingMethod::VNI,
-        }
-    }
-}
-
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct HotkeyState {
@@ -49,14 +42,27 @@ pub struct MacroEntry {
     pub target: String,
 }
 
+/// Why Vietnamese typing is off although the user turned it on.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum SuspendedReason {
+    /// The keyboard layout of the OS does not type Latin letters.
+    NonLatinLayout { layout: String },
+}
+
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct UiState {
+    /// Whether the user turned Vietnamese on.
     pub is_enabled: bool,
+    /// Whether keys are typed as Vietnamese right now, which an excluded
+    /// app or a suspension keeps off whatever the user turned on.
+    pub is_typing_vietnamese: bool,
     pub typing_method: TypingMethodDto,
     pub auto_toggle_enabled: bool,
     pub macro_enabled: bool,
     pub macros: Vec<MacroEntry>,
+    pub numpad_tone_enabled: bool,
     pub launch_on_login: bool,
     pub active_app: String,
     pub hotkey: HotkeyState,
@@ -70,6 +76,12 @@ pub struct UiState {
     pub exclude_apps_enabled: bool,
     pub open_window_on_launch: bool,
     pub language: String,
+    /// The name of the keyboard layout of the OS.
+    pub keyboard_layout: String,
+    pub suspended_reason: Option<SuspendedReason>,
+    /// Why the active script could not be loaded on startup, in which case
+    /// Telex is used instead.
+    pub script_error: Option<String>,
 }
 
 impl UiState {
@@ -128,12 +140,29 @@ impl UiState {
                 .map(|c| c.get_language().to_string())
                 .unwrap_or_else(|_| "vi".to_string());
 
+            let keyboard_layout = input_state.reported_keyboard_layout().name().to_string();
+            let suspended_reason =
+                input_state
+                    .is_suspended_by_layout()
+                    .then(|| SuspendedReason::NonLatinLayout {
+                        layout: keyboard_layout.clone(),
+                    });
+
+            let typing_method = match input_state.active_script() {
+                Some(name) => TypingMethodDto::Custom {
+                    name: name.to_string(),
+                },
+                None => input_state.get_method().into(),
+            };
+
             Self {
-                is_enabled: input_state.is_enabled(),
-                typing_method: input_state.get_method().into(),
+                is_enabled: input_state.is_vietnamese_mode_enabled(),
+                is_typing_vietnamese: input_state.is_enabled(),
+                typing_method,
                 auto_toggle_enabled: input_state.is_auto_toggle_enabled(),
                 macro_enabled: input_state.is_macro_enabled(),
                 macros,
+                numpad_tone_enabled: input_state.is_numpad_tone_enabled(),
                 launch_on_login: is_launch_on_login(),
                 active_app: input_state.active_app().to_string(),
                 hotkey,
@@ -147,6 +176,9 @@ impl UiState {
                 exclude_apps_enabled,
                 open_window_on_launch,
                 language,
+                keyboard_layout,
+                suspended_reason,
+                script_error: input_state.script_error().map(str::to_string),
             }
         }
     }
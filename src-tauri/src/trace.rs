@@ -0,0 +1,340 @@
+//! Keystroke traces: a recording of what the event listener saw, one JSON
+//! object per line, which can be replayed through the engine.
+//!
+//! The first line of a trace describes the session (typing method, hotkey,
+//! macros...), every following line is a key event:
+//!
+//! ```text
+//! {"session":{"method":"telex","hotkey":"ctrl+space"}}
+//! {"event":"KeyDown","key":{"Char":"a"},"modifiers":["shift"],"app":"/Applications/Safari.app"}
+//! ```
+//!
+//! Set `GOXKEY_TRACE=/path/to/file.trace` to record a session.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::ConfigStore;
+use crate::engine::{Action, Engine, TypingMethod};
+use crate::hotkey::Hotkey;
+use crate::platform::{
+    EventTapType, KeyEvent, KeyModifier, PressedKey, KEY_DELETE, KEY_ENTER, KEY_ESCAPE, KEY_SPACE,
+    KEY_TAB, RAW_ARROW_DOWN, RAW_ARROW_LEFT, RAW_ARROW_RIGHT, RAW_ARROW_UP,
+};
+
+pub const TRACE_ENV_VAR: &str = "GOXKEY_TRACE";
+
+static TRACE_WRITER: Lazy<Mutex<Option<File>>> = Lazy::new(|| Mutex::new(None));
+
+/// Settings the engine was running with when the trace was recorded.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Session {
+    pub method: String,
+    pub hotkey: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub macro_enabled: bool,
+    #[serde(default)]
+    pub macros: BTreeMap<String, String>,
+    #[serde(default)]
+    pub allowed_words: Vec<String>,
+    #[serde(default)]
+    pub numpad_tone_enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Session {
+    pub fn from_config(config: &ConfigStore) -> Self {
+        Self {
+            method: config.get_method().to_string(),
+            hotkey: config.get_hotkey().to_string(),
+            enabled: config.is_vietnamese_mode_enabled(),
+            macro_enabled: config.is_macro_enabled(),
+            macros: config.get_macro_table().clone(),
+            allowed_words: config.get_allowed_words().clone(),
+            numpad_tone_enabled: config.is_numpad_tone_enabled(),
+        }
+    }
+
+    pub fn build_engine(&self) -> Engine {
+        let mut engine = Engine::new(
+            TypingMethod::from_str(&self.method).unwrap(),
+            Hotkey::from_str(&self.hotkey),
+        );
+        engine.set_enabled(self.enabled);
+        engine.set_macro_enabled(self.macro_enabled);
+        engine.set_macro_table(self.macros.clone());
+        engine.set_allowed_words(self.allowed_words.clone());
+        engine.set_numpad_tone_enabled(self.numpad_tone_enabled);
+        engine
+    }
+}
+
+/// A single event as delivered by the platform event listener. Which side
+/// the modifiers were pressed on is left out, the engine does not read it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEvent {
+    pub event: EventTapType,
+    #[serde(default)]
+    pub key: Option<PressedKey>,
+    #[serde(default = "KeyModifier::new", with = "modifier_names")]
+    pub modifiers: KeyModifier,
+    #[serde(default)]
+    pub app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keycode: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub repeat: bool,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl TraceEvent {
+    fn new(event: &KeyEvent, app: &str) -> Self {
+        Self {
+            event: event.event_type,
+            key: event.key,
+            modifiers: event.modifiers,
+            app: Some(app.to_string()).filter(|app| !app.is_empty()),
+            keycode: event.keycode,
+            repeat: event.is_repeat,
+            timestamp: event.timestamp,
+        }
+    }
+
+    fn key_event(&self) -> KeyEvent {
+        KeyEvent {
+            keycode: self.keycode,
+            is_repeat: self.repeat,
+            timestamp: self.timestamp,
+            ..KeyEvent::new(self.event, self.key, self.modifiers)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TraceRecord {
+    Session { session: Session },
+    Event(TraceEvent),
+}
+
+mod modifier_names {
+    use super::*;
+
+    const NAMES: [(&str, KeyModifier); 5] = [
+        ("shift", KeyModifier::MODIFIER_SHIFT),
+        ("super", KeyModifier::MODIFIER_SUPER),
+        ("ctrl", KeyModifier::MODIFIER_CONTROL),
+        ("alt", KeyModifier::MODIFIER_ALT),
+        ("capslock", KeyModifier::MODIFIER_CAPSLOCK),
+    ];
+
+    pub fn serialize<S: Serializer>(value: &KeyModifier, serializer: S) -> Result<S::Ok, S::Error> {
+        let names: Vec<&str> = NAMES
+            .iter()
+            .filter(|(_, flag)| value.contains(*flag))
+            .map(|(name, _)| *name)
+            .collect();
+        names.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<KeyModifier, D::Error> {
+        let names: Vec<String> = Vec::deserialize(deserializer)?;
+        let mut modifiers = KeyModifier::new();
+        for name in names {
+            let (_, flag) = NAMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .ok_or_else(|| serde::de::Error::custom(format!("unknown modifier `{name}`")))?;
+            modifiers.insert(*flag);
+        }
+        Ok(modifiers)
+    }
+}
+
+/// Start writing every event passed to `record` into `path`.
+pub fn start_recording(path: &Path, session: Session) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    write_record(&mut file, &TraceRecord::Session { session })?;
+    *TRACE_WRITER.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub fn record(event: &KeyEvent, app: &str) {
+    let mut writer = TRACE_WRITER.lock().unwrap();
+    let Some(file) = writer.as_mut() else {
+        return;
+    };
+    let record = TraceRecord::Event(TraceEvent::new(event, app));
+    if let Err(err) = write_record(file, &record) {
+        log::warn!("failed to write trace: {err}");
+    }
+}
+
+fn write_record(file: &mut File, record: &TraceRecord) -> std::io::Result<()> {
+    let line = serde_json::to_string(record)?;
+    writeln!(file, "{line}")
+}
+
+pub fn read_trace(path: &Path) -> Result<Vec<TraceRecord>, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.map_err(|err| err.to_string())?;
+            serde_json::from_str(&line)
+                .map_err(|err| format!("{}:{}: {err}", path.display(), index + 1))
+        })
+        .collect()
+}
+
+/// A text field with a cursor, edited the way an application would react
+/// to the events goxkey lets through and the edits it sends.
+#[derive(Default)]
+struct TextField {
+    text: Vec<char>,
+    cursor: usize,
+}
+
+impl TextField {
+    fn insert(&mut self, s: &str) {
+        for c in s.chars() {
+            self.text.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    fn backspace(&mut self, count: usize) {
+        for _ in 0..count.min(self.cursor) {
+            self.cursor -= 1;
+            self.text.remove(self.cursor);
+        }
+    }
+
+    fn apply_key(&mut self, key: PressedKey, modifiers: KeyModifier) {
+        match key {
+            PressedKey::Raw(RAW_ARROW_LEFT) => self.cursor = self.cursor.saturating_sub(1),
+            PressedKey::Raw(RAW_ARROW_RIGHT) => {
+                self.cursor = (self.cursor + 1).min(self.text.len())
+            }
+            PressedKey::Raw(RAW_ARROW_UP) => self.cursor = 0,
+            PressedKey::Raw(RAW_ARROW_DOWN) => self.cursor = self.text.len(),
+            PressedKey::Raw(_) => {
+                if let Some(digit) = key.keypad_digit() {
+                    self.insert(&digit.to_string());
+                }
+            }
+            _ if modifiers.is_control() || modifiers.is_super() || modifiers.is_alt() => {}
+            PressedKey::Char(KEY_DELETE) => self.backspace(1),
+            PressedKey::Char(KEY_ENTER) => self.insert("\n"),
+            PressedKey::Char(KEY_TAB) => self.insert("\t"),
+            PressedKey::Char(KEY_SPACE) => self.insert(" "),
+            PressedKey::Char(KEY_ESCAPE) => {}
+            PressedKey::Char(c) => {
+                if modifiers.is_shift() || modifiers.is_capslock() {
+                    self.insert(&c.to_uppercase().to_string())
+                } else {
+                    self.insert(&c.to_string())
+                }
+            }
+        }
+    }
+}
+
+/// Feed a trace through a fresh engine and return the text the target
+/// application would end up with.
+pub fn replay(records: &[TraceRecord]) -> Result<String, String> {
+    let mut records = records.iter();
+    let Some(TraceRecord::Session { session }) = records.next() else {
+        return Err("trace must start with a session record".to_string());
+    };
+    let mut engine = session.build_engine();
+    let mut field = TextField::default();
+    for record in records {
+        let TraceRecord::Event(event) = record else {
+            return Err("unexpected session record in the middle of a trace".to_string());
+        };
+        if let Some(app) = &event.app {
+            if app != engine.active_app() {
+                engine.set_active_app(app.clone(), None);
+            }
+        }
+        let actions = engine.handle_event(&event.key_event());
+        let mut swallowed = false;
+        for action in actions {
+            match action {
+                Action::Backspace(count) => field.backspace(count),
+                Action::Insert(text) => field.insert(&text),
+                Action::Swallow => swallowed = true,
+                Action::VietnameseToggled => {}
+            }
+        }
+        if let (EventTapType::KeyDown, Some(key), false) = (&event.event, event.key, swallowed) {
+            field.apply_key(key, event.modifiers);
+        }
+    }
+    Ok(field.text.into_iter().collect())
+}
+
+#[test]
+fn test_modifiers_round_trip() {
+    let event = TraceEvent {
+        event: EventTapType::KeyDown,
+        key: Some(PressedKey::Char('a')),
+        modifiers: KeyModifier::MODIFIER_SHIFT | KeyModifier::MODIFIER_CONTROL,
+        app: None,
+        keycode: None,
+        repeat: false,
+        timestamp: 0,
+    };
+    let json = serde_json::to_string(&event).unwrap();
+    assert!(json.contains(r#""modifiers":["shift","ctrl"]"#));
+    let parsed: TraceEvent = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.modifiers, event.modifiers);
+}
+
+#[test]
+fn test_golden_traces() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("traces");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "trace"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let expected = std::fs::read_to_string(path.with_extension("expected")).unwrap();
+        let records = read_trace(&path).unwrap();
+        let actual = replay(&records).unwrap();
+        assert_eq!(
+            actual,
+            expected.strip_suffix('\n').unwrap_or(&expected),
+            "{}",
+            path.display()
+        );
+    }
+}
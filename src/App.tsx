@@ -16,6 +16,7 @@ import {
   MacroForm,
   MacroList,
   ExcludedAppsSection,
+  ScriptsSection,
 } from "@/components"
 import { Card } from "@/components/card"
 
@@ -148,6 +149,7 @@ export default function App() {
                   <MainToggle
                     isEnabled={state.isEnabled}
                     activeApp={state.activeApp}
+                    suspendedReason={state.suspendedReason}
                     onToggle={() =>
                       runCommand(() => ipc.setEnabled(!state.isEnabled))
                     }
@@ -163,6 +165,13 @@ export default function App() {
                     </Card>
                   </section>
 
+                  <ScriptsSection
+                    typingMethod={state.typingMethod}
+                    scriptError={state.scriptError}
+                    runCommand={runCommand}
+                    onScriptsChange={refreshState}
+                  />
+
                   <HotkeyConfig
                     currentHotkey={state.hotkey.display}
                     onSave={saveHotkey}
@@ -284,6 +293,15 @@ function AppSettingsContent({
         }
       />
 
+      <ToggleRow
+        title={t.settings.numpadTone}
+        description={t.settings.numpadToneDescription}
+        checked={state.numpadToneEnabled}
+        onClick={() =>
+          runCommand(() => ipc.setNumpadToneEnabled(!state.numpadToneEnabled))
+        }
+      />
+
       <ThemeSelector
         theme={state.theme}
         onThemeChange={(theme) => runCommand(() => ipc.setTheme(theme))}
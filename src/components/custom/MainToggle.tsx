@@ -1,5 +1,5 @@
 import { memo } from "react"
-import type { TypingMethod } from "../../lib"
+import type { SuspendedReason, TypingMethod } from "../../lib"
 import { Switch } from "../switch"
 import { Button } from "../button"
 import { Card } from "../card"
@@ -8,6 +8,7 @@ import { useI18n } from "../../lib/i18n"
 type Props = {
   isEnabled: boolean
   activeApp: string
+  suspendedReason?: SuspendedReason
   onToggle: () => void
   typingMethod: TypingMethod
   onTypingMethodChange: (method: TypingMethod) => void
@@ -16,6 +17,7 @@ type Props = {
 export const MainToggle = memo(function MainToggle({
   isEnabled,
   activeApp,
+  suspendedReason,
   onToggle,
   typingMethod,
   onTypingMethodChange,
@@ -33,6 +35,11 @@ export const MainToggle = memo(function MainToggle({
             <p className="text-[11px] text-gray-500 dark:text-gray-400 mt-0.5">
               {t.mainToggle.app}: {activeApp.split("/").pop()}
             </p>
+            {suspendedReason?.kind === "nonLatinLayout" && (
+              <p className="text-[11px] text-amber-600 dark:text-amber-500 mt-0.5">
+                {t.mainToggle.suspendedByLayout}: {suspendedReason.layout}
+              </p>
+            )}
           </div>
           <Switch checked={isEnabled} onCheckedChange={onToggle} />
         </div>
@@ -61,6 +68,11 @@ export const MainToggle = memo(function MainToggle({
               </Button>
             </div>
           </div>
+          {typeof typingMethod === "object" && (
+            <p className="text-[11px] text-gray-500 dark:text-gray-400 mt-1">
+              {t.mainToggle.script}: {typingMethod.custom.name}
+            </p>
+          )}
         </div>
       </Card>
     </section>
@@ -0,0 +1,236 @@
+import type { FormEvent } from "react"
+import { memo, useCallback, useEffect, useState } from "react"
+import { Card } from "../card"
+import { Input } from "../input"
+import { Button } from "../button"
+import { Check, Pencil, X } from "lucide-react"
+import {
+  ipc,
+  type ScriptDiagnostic,
+  type ScriptInfo,
+  type TypingMethod,
+  type UiState,
+} from "../../lib"
+import { useI18n } from "../../lib/i18n"
+
+type Props = {
+  typingMethod: TypingMethod
+  scriptError?: string
+  runCommand: (handler: () => Promise<UiState>) => void
+  onScriptsChange: () => void
+}
+
+export const ScriptsSection = memo(function ScriptsSection({
+  typingMethod,
+  scriptError,
+  runCommand,
+  onScriptsChange,
+}: Props) {
+  const { t } = useI18n()
+  const [scripts, setScripts] = useState<ScriptInfo[]>([])
+  const [name, setName] = useState("")
+  const [source, setSource] = useState("")
+  const [renaming, setRenaming] = useState<string | null>(null)
+  const [newName, setNewName] = useState("")
+  const [diagnostics, setDiagnostics] = useState<{
+    name: string
+    items: ScriptDiagnostic[]
+  } | null>(null)
+  const [error, setError] = useState<string | null>(null)
+
+  const activeName = typeof typingMethod === "object" ? typingMethod.custom.name : null
+
+  const run = useCallback(
+    async (handler: () => Promise<ScriptInfo[]>) => {
+      try {
+        setScripts(await handler())
+        setError(null)
+        onScriptsChange()
+      } catch (err) {
+        setError(String(err))
+      }
+    },
+    [onScriptsChange]
+  )
+
+  // The list tells which script is active, which the typing method changes.
+  useEffect(() => {
+    ipc.listScripts().then(setScripts).catch((err) => setError(String(err)))
+  }, [activeName])
+
+  const handleCreate = (event: FormEvent) => {
+    event.preventDefault()
+    if (!name.trim()) {
+      return
+    }
+    run(() => ipc.createScript(name.trim(), source))
+    setName("")
+    setSource("")
+  }
+
+  const handleRename = (script: string) => {
+    if (newName.trim() && newName.trim() !== script) {
+      run(() => ipc.renameScript(script, newName.trim()))
+    }
+    setRenaming(null)
+  }
+
+  const handleValidate = async (script: string) => {
+    try {
+      setDiagnostics({ name: script, items: await ipc.validateScript(script) })
+      setError(null)
+    } catch (err) {
+      setError(String(err))
+    }
+  }
+
+  return (
+    <section className="mt-4">
+      <Card className="border-gray-200 dark:border-gray-700/50">
+        <div className="px-4 py-3 border-b border-gray-200 dark:border-gray-700/50">
+          <p className="text-[13px] text-gray-900 dark:text-gray-100">
+            {t.scripts.title}
+          </p>
+          <p className="text-[11px] text-gray-500 dark:text-gray-400 mt-0.5">
+            {t.scripts.description}
+          </p>
+        </div>
+        <div className="px-4 py-3 space-y-3">
+          {scriptError && (
+            <p className="text-[11px] text-red-500 dark:text-red-400 whitespace-pre-wrap">
+              {t.scripts.fallback}: {scriptError}
+            </p>
+          )}
+          {error && (
+            <p className="text-[11px] text-red-500 dark:text-red-400 whitespace-pre-wrap">
+              {error}
+            </p>
+          )}
+
+          {scripts.length === 0 ? (
+            <p className="text-[11px] text-gray-500 dark:text-gray-400">
+              {t.scripts.none}
+            </p>
+          ) : (
+            <div className="space-y-1.5 max-h-48 overflow-y-auto">
+              {scripts.map((script) => (
+                <div
+                  key={script.name}
+                  className="flex items-center justify-between rounded border border-gray-200 dark:border-gray-700/50 bg-gray-50 dark:bg-[#2c2c2e] px-2.5 py-2"
+                >
+                  {renaming === script.name ? (
+                    <Input
+                      autoFocus
+                      value={newName}
+                      onChange={(event) => setNewName(event.target.value)}
+                      onBlur={() => handleRename(script.name)}
+                      onKeyDown={(event) => {
+                        if (event.key === "Enter") handleRename(script.name)
+                        if (event.key === "Escape") setRenaming(null)
+                      }}
+                      className="h-7 text-[12px] border-gray-300 dark:border-gray-600"
+                    />
+                  ) : (
+                    <p className="text-[12px] text-gray-900 dark:text-gray-100 truncate">
+                      {script.name}
+                      {script.isActive && (
+                        <span className="ml-2 text-[10px] text-green-600 dark:text-green-500">
+                          {t.scripts.active}
+                        </span>
+                      )}
+                    </p>
+                  )}
+                  <div className="flex items-center ml-2 shrink-0">
+                    {!script.isActive && (
+                      <Button
+                        variant="ghost"
+                        size="sm"
+                        className="h-6 px-2 text-[11px]"
+                        onClick={() => runCommand(() => ipc.activateScript(script.name))}
+                      >
+                        {t.scripts.activate}
+                      </Button>
+                    )}
+                    <Button
+                      variant="ghost"
+                      size="icon"
+                      className="h-6 w-6"
+                      aria-label={t.scripts.validate}
+                      onClick={() => handleValidate(script.name)}
+                    >
+                      <Check className="h-4 w-4" />
+                    </Button>
+                    <Button
+                      variant="ghost"
+                      size="icon"
+                      className="h-6 w-6"
+                      aria-label={t.scripts.rename}
+                      onClick={() => {
+                        setNewName(script.name)
+                        setRenaming(script.name)
+                      }}
+                    >
+                      <Pencil className="h-3.5 w-3.5" />
+                    </Button>
+                    <Button
+                      variant="ghost"
+                      size="icon"
+                      className="h-6 w-6"
+                      aria-label={t.scripts.delete}
+                      onClick={() => run(() => ipc.deleteScript(script.name))}
+                    >
+                      <X className="h-4 w-4 text-red-500 dark:text-red-400" />
+                    </Button>
+                  </div>
+                </div>
+              ))}
+            </div>
+          )}
+
+          {diagnostics && (
+            <div className="text-[11px]">
+              {diagnostics.items.length === 0 ? (
+                <p className="text-green-600 dark:text-green-500">
+                  {diagnostics.name}: {t.scripts.valid}
+                </p>
+              ) : (
+                diagnostics.items.map((item, index) => (
+                  <p
+                    key={index}
+                    className={
+                      item.severity === "error"
+                        ? "text-red-500 dark:text-red-400"
+                        : "text-amber-600 dark:text-amber-500"
+                    }
+                  >
+                    {diagnostics.name}:{item.span.start.line}:{item.span.start.column}{" "}
+                    {item.message}
+                  </p>
+                ))
+              )}
+            </div>
+          )}
+
+          <form className="space-y-2" onSubmit={handleCreate}>
+            <Input
+              placeholder={t.scripts.namePlaceholder}
+              value={name}
+              onChange={(event) => setName(event.target.value)}
+              className="h-8 text-[11px] border-gray-300 dark:border-gray-600"
+            />
+            <textarea
+              placeholder={t.scripts.sourcePlaceholder}
+              value={source}
+              onChange={(event) => setSource(event.target.value)}
+              rows={4}
+              className="w-full rounded-md border border-gray-300 dark:border-gray-600 bg-background px-3 py-2 font-mono text-[11px]"
+            />
+            <Button type="submit" size="sm" className="h-8 px-3 text-[11px]">
+              {t.scripts.create}
+            </Button>
+          </form>
+        </div>
+      </Card>
+    </section>
+  )
+})
@@ -9,3 +9,4 @@ export { LanguageSelector } from "./custom/LanguageSelector";
 export { MacroForm } from "./custom/MacroForm";
 export { MacroList } from "./custom/MacroList";
 export { ExcludedAppsSection } from "./custom/ExcludedAppsSection";
+export { ScriptsSection } from "./custom/ScriptsSection";
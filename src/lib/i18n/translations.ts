@@ -6,6 +6,8 @@ export const translations = {
       enableVietnamese: "Enable Vietnamese typing",
       app: "App",
       typingMethod: "Typing method",
+      suspendedByLayout: "Paused, the keyboard layout has no Latin letters",
+      script: "Script",
     },
     settings: {
       autoToggleByApp: "Auto toggle by app",
@@ -17,6 +19,8 @@ export const translations = {
       menubarIconDescription: "Show icon in menu",
       macro: "Macro",
       macroDescription: "Enable/disable word replacement",
+      numpadTone: "Numpad tones",
+      numpadToneDescription: "Type VNI tones with the numeric keypad",
       theme: "Interface",
       themeAuto: "Auto",
       themeLight: "Light",
@@ -52,6 +56,21 @@ export const translations = {
       currentlyExcluding: "Currently excluding",
       none: "No apps yet",
     },
+    scripts: {
+      title: "Scripts",
+      description: "Type with your own goxscript instead of Telex or VNI",
+      fallback: "The script could not be loaded, typing with Telex",
+      none: "No scripts yet",
+      active: "In use",
+      activate: "Use",
+      validate: "Check",
+      valid: "no problems found",
+      rename: "Rename",
+      delete: "Delete",
+      namePlaceholder: "Script name",
+      sourcePlaceholder: "goxscript source",
+      create: "Create",
+    },
     macro: {
       addDescription: "Add abbreviation to automatically replace",
       sourcePlaceholder: "word",
@@ -78,6 +97,8 @@ export const translations = {
       enableVietnamese: "Bật gõ tiếng Việt",
       app: "Ứng dụng",
       typingMethod: "Kiểu gõ",
+      suspendedByLayout: "Tạm dừng, bố cục bàn phím không có chữ Latin",
+      script: "Script",
     },
     settings: {
       autoToggleByApp: "Tự đổi theo app",
@@ -89,6 +110,8 @@ export const translations = {
       menubarIconDescription: "Hiện icon trên menu",
       macro: "Macro",
       macroDescription: "Bật/tắt thay thế từ",
+      numpadTone: "Bỏ dấu bằng bàn phím số",
+      numpadToneDescription: "Gõ dấu VNI bằng các phím số bên phải",
       theme: "Giao diện",
       themeAuto: "Tự động",
       themeLight: "Sáng",
@@ -124,6 +147,21 @@ export const translations = {
       currentlyExcluding: "Đang bỏ qua",
       none: "Chưa có ứng dụng nào",
     },
+    scripts: {
+      title: "Script",
+      description: "Gõ bằng goxscript của bạn thay cho Telex hay VNI",
+      fallback: "Không tải được script, đang gõ bằng Telex",
+      none: "Chưa có script nào",
+      active: "Đang dùng",
+      activate: "Dùng",
+      validate: "Kiểm tra",
+      valid: "không có lỗi",
+      rename: "Đổi tên",
+      delete: "Xóa",
+      namePlaceholder: "Tên script",
+      sourcePlaceholder: "Mã goxscript",
+      create: "Tạo",
+    },
     macro: {
       addDescription: "Thêm từ viết tắt để tự động thay thế",
       sourcePlaceholder: "từ",
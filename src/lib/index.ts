@@ -6,4 +6,7 @@ export type {
   UiState,
   AppInfo,
   HotkeyValidation,
+  ScriptDiagnostic,
+  ScriptInfo,
+  SuspendedReason,
 } from "./ipc";
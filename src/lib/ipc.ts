@@ -1,6 +1,6 @@
 import { invoke } from "@tauri-apps/api/core";
 
-export type TypingMethod = "telex" | "vni";
+export type TypingMethod = "telex" | "vni" | { custom: { name: string } };
 
 export type HotkeyState = {
   display: string;
@@ -29,11 +29,37 @@ export type AppInfo = {
   path: string;
 };
 
+export type ScriptPosition = {
+  line: number;
+  column: number;
+};
+
+export type ScriptDiagnostic = {
+  severity: "error" | "warning";
+  message: string;
+  span: {
+    start: ScriptPosition;
+    end: ScriptPosition;
+  };
+};
+
+export type ScriptInfo = {
+  name: string;
+  isActive: boolean;
+};
+
+export type SuspendedReason = {
+  kind: "nonLatinLayout";
+  layout: string;
+};
+
 export type UiState = {
   isEnabled: boolean;
+  isTypingVietnamese: boolean;
   typingMethod: TypingMethod;
   autoToggleEnabled: boolean;
   macroEnabled: boolean;
+  numpadToneEnabled: boolean;
   macros: MacroEntry[];
   launchOnLogin: boolean;
   activeApp: string;
@@ -48,6 +74,9 @@ export type UiState = {
   excludeAppsEnabled: boolean;
   openWindowOnLaunch: boolean;
   language: string;
+  keyboardLayout: string;
+  suspendedReason?: SuspendedReason;
+  scriptError?: string;
 };
 
 async function invokeCommand<T>(
@@ -89,6 +118,8 @@ export const ipc = {
   setMacroEnabled: (enabled: boolean) =>
     invokeCommand<UiState>("set_macro_enabled", { enabled }),
 
+  setNumpadToneEnabled: (enabled: boolean) =>
+    invokeCommand<UiState>("set_numpad_tone_enabled", { enabled }),
   setExcludeAppsEnabled: (enabled: boolean) =>
     invokeCommand<UiState>("set_exclude_apps_enabled", { enabled }),
 
@@ -115,5 +146,28 @@ export const ipc = {
 
   searchApps: (query?: string) => invokeCommand<AppInfo[]>("search_apps", { query }),
 
+  checkScript: (source: string) =>
+    invokeCommand<ScriptDiagnostic[]>("check_script", { source }),
+
+  formatScript: (source: string) =>
+    invokeCommand<string>("format_script", { source }),
+
+  listScripts: () => invokeCommand<ScriptInfo[]>("list_scripts"),
+
+  createScript: (name: string, source: string) =>
+    invokeCommand<ScriptInfo[]>("create_script", { name, source }),
+
+  renameScript: (name: string, newName: string) =>
+    invokeCommand<ScriptInfo[]>("rename_script", { name, newName }),
+
+  deleteScript: (name: string) =>
+    invokeCommand<ScriptInfo[]>("delete_script", { name }),
+
+  validateScript: (name: string) =>
+    invokeCommand<ScriptDiagnostic[]>("validate_script", { name }),
+
+  activateScript: (name: string) =>
+    invokeCommand<UiState>("activate_script", { name }),
+
   openUrl: (url: string) => invokeCommand<void>("open_url", { url }),
 };